@@ -0,0 +1,320 @@
+use std::fmt;
+
+use crate::{Canvas, Color};
+
+pub fn hline<C: Canvas + ?Sized>(canvas: &mut C, x0: i32, x1: i32, y: i32, color: Color) {
+    let (x0, x1) = if x0 <= x1 { (x0, x1) } else { (x1, x0) };
+    if y < 0 || y >= canvas.height() as i32 {
+        return;
+    }
+
+    canvas.blend_span(x0, x1, y, color);
+}
+
+pub fn vline<C: Canvas + ?Sized>(canvas: &mut C, x: i32, y0: i32, y1: i32, color: Color) {
+    let (y0, y1) = if y0 <= y1 { (y0, y1) } else { (y1, y0) };
+    let (y0, y1) = (y0.max(0), y1.min(canvas.height() as i32 - 1));
+
+    for y in y0..=y1 {
+        canvas.blend_pixel(x, y, color);
+    }
+}
+
+// the rectangle covers `width` x `height` pixels with its top left corner at (x, y)
+pub fn fill_rect<C: Canvas + ?Sized>(
+    canvas: &mut C,
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+    color: Color,
+) {
+    if width == 0 || height == 0 {
+        return;
+    }
+
+    // sizes past i32::MAX reach beyond any canvas anyway
+    let x1 = x.saturating_add(last_offset(width));
+    let y0 = y.max(0);
+    let y1 = y
+        .saturating_add(last_offset(height))
+        .min(canvas.height() as i32 - 1);
+
+    for row in y0..=y1 {
+        canvas.blend_span(x, x1, row, color);
+    }
+}
+
+// offset of the last pixel of a run of `len` > 0 pixels
+fn last_offset(len: u32) -> i32 {
+    (len - 1).min(i32::MAX as u32) as i32
+}
+
+// Bresenham, both end points included
+pub fn line<C: Canvas + ?Sized>(canvas: &mut C, x0: i32, y0: i32, x1: i32, y1: i32, color: Color) {
+    let dx = (x1 - x0).abs();
+    let dy = -(y1 - y0).abs();
+    let sx = if x0 < x1 { 1 } else { -1 };
+    let sy = if y0 < y1 { 1 } else { -1 };
+
+    let (mut x, mut y) = (x0, y0);
+    let mut err = dx + dy;
+
+    loop {
+        canvas.blend_pixel(x, y, color);
+        if x == x1 && y == y1 {
+            break;
+        }
+
+        let e2 = 2 * err;
+        if e2 >= dy {
+            err += dy;
+            x += sx;
+        }
+        if e2 <= dx {
+            err += dx;
+            y += sy;
+        }
+    }
+}
+
+// midpoint circle outline
+pub fn circle<C: Canvas + ?Sized>(canvas: &mut C, cx: i32, cy: i32, radius: u32, color: Color) {
+    if radius == 0 {
+        canvas.blend_pixel(cx, cy, color);
+        return;
+    }
+
+    let mut x = radius as i32;
+    let mut y = 0;
+    let mut err = 1 - x;
+
+    while x >= y {
+        // the octants meet on the diagonals and axes, don't blend those pixels twice
+        let mut points = [
+            (cx + x, cy + y),
+            (cx - x, cy + y),
+            (cx + x, cy - y),
+            (cx - x, cy - y),
+            (cx + y, cy + x),
+            (cx - y, cy + x),
+            (cx + y, cy - x),
+            (cx - y, cy - x),
+        ];
+        points.sort_unstable();
+        for (idx, &(px, py)) in points.iter().enumerate() {
+            if idx == 0 || points[idx - 1] != (px, py) {
+                canvas.blend_pixel(px, py, color);
+            }
+        }
+
+        y += 1;
+        if err < 0 {
+            err += 2 * y + 1;
+        } else {
+            x -= 1;
+            err += 2 * (y - x) + 1;
+        }
+    }
+}
+
+pub fn fill_circle<C: Canvas + ?Sized>(
+    canvas: &mut C,
+    cx: i32,
+    cy: i32,
+    radius: u32,
+    color: Color,
+) {
+    let r = radius as i32;
+    let r2 = r * r + r;
+
+    for dy in -r..=r {
+        // widest run on this row that stays inside the circle
+        let mut half = 0;
+        while (half + 1) * (half + 1) + dy * dy <= r2 {
+            half += 1;
+        }
+
+        hline(canvas, cx - half, cx + half, cy + dy, color);
+    }
+}
+
+#[derive(Debug)]
+pub struct ImageTooSmall {
+    pub width: u32,
+    pub height: u32,
+    pub stride: usize,
+    pub len: usize,
+}
+
+impl fmt::Display for ImageTooSmall {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} bytes are too few for a {}x{} RGBA image with a stride of {}",
+            self.len, self.width, self.height, self.stride
+        )
+    }
+}
+
+impl std::error::Error for ImageTooSmall {}
+
+// straight (non premultiplied) RGBA pixels, `stride` bytes per row
+pub struct Image<'a> {
+    width: u32,
+    height: u32,
+    stride: usize,
+    data: &'a [u8],
+}
+
+impl<'a> Image<'a> {
+    pub fn new(width: u32, height: u32, data: &'a [u8]) -> Result<Self, ImageTooSmall> {
+        Image::with_stride(width, height, width as usize * 4, data)
+    }
+
+    // `stride` is the number of bytes per row, which can be more than width * 4
+    pub fn with_stride(
+        width: u32,
+        height: u32,
+        stride: usize,
+        data: &'a [u8],
+    ) -> Result<Self, ImageTooSmall> {
+        let row = width as usize * 4;
+        let needed = match height {
+            0 => Some(0),
+            _ => stride
+                .checked_mul(height as usize - 1)
+                .and_then(|rows| rows.checked_add(row)),
+        };
+
+        match needed {
+            Some(needed) if stride >= row && data.len() >= needed => Ok(Image {
+                width,
+                height,
+                stride,
+                data,
+            }),
+            _ => Err(ImageTooSmall {
+                width,
+                height,
+                stride,
+                len: data.len(),
+            }),
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+}
+
+// copies `image` with its top left corner at (x, y), honouring its alpha and `opacity`
+pub fn blit<C: Canvas + ?Sized>(canvas: &mut C, image: &Image, x: i32, y: i32, opacity: u8) {
+    for row in 0..image.height {
+        let py = y + row as i32;
+        if py < 0 || py >= canvas.height() as i32 {
+            continue;
+        }
+
+        let line = &image.data[row as usize * image.stride..];
+        for col in 0..image.width {
+            let px = x + col as i32;
+            if !canvas.contains(px, py) {
+                continue;
+            }
+
+            let p = &line[col as usize * 4..col as usize * 4 + 4];
+            let alpha = (u32::from(p[3]) * u32::from(opacity) + 127) / 255;
+            if alpha == 0 {
+                continue;
+            }
+
+            canvas.blend_pixel(px, py, Color::rgba(p[0], p[1], p[2], alpha as u8));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{PackedCanvas, PackedLayout};
+
+    const RED: Color = Color::rgb(255, 0, 0);
+
+    fn rgb(width: u32, height: u32) -> Vec<u8> {
+        vec![0; (width * height * 3) as usize]
+    }
+
+    fn canvas(data: &mut [u8], width: u32, height: u32) -> PackedCanvas<'_> {
+        PackedCanvas::new(data, width, height, width as usize * 3, PackedLayout::RGB).unwrap()
+    }
+
+    #[test]
+    fn fill_rect_covers_exactly_its_pixels() {
+        let mut data = rgb(4, 4);
+        let mut c = canvas(&mut data, 4, 4);
+        fill_rect(&mut c, 1, 1, 2, 3, RED);
+
+        for y in 0..4 {
+            for x in 0..4 {
+                let inside = (1..3).contains(&x) && (1..4).contains(&y);
+                assert_eq!(c.pixel(x, y)[0] == 255, inside, "({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn fill_rect_clips_to_the_canvas() {
+        let mut data = rgb(4, 4);
+        let mut c = canvas(&mut data, 4, 4);
+        fill_rect(&mut c, -2, -2, 3, 3, RED);
+        fill_rect(&mut c, 3, 3, u32::MAX, u32::MAX, RED);
+        fill_rect(&mut c, 10, 0, 5, 5, RED);
+
+        let painted: Vec<(u32, u32)> = (0..4)
+            .flat_map(|y| (0..4).map(move |x| (x, y)))
+            .filter(|&(x, y)| c.pixel(x, y)[0] == 255)
+            .collect();
+        assert_eq!(painted, vec![(0, 0), (3, 3)]);
+    }
+
+    #[test]
+    fn image_must_hold_its_pixels() {
+        let data = [0; 16];
+
+        assert!(Image::new(2, 2, &data).is_ok());
+        assert!(Image::new(3, 2, &data).is_err());
+        assert!(Image::with_stride(1, 2, 8, &data[..12]).is_ok());
+        assert!(Image::with_stride(2, 2, 4, &data).is_err());
+        assert!(Image::with_stride(1, u32::MAX, usize::MAX, &data).is_err());
+    }
+
+    #[test]
+    fn blit_honours_alpha_opacity_and_clipping() {
+        #[rustfmt::skip]
+        let pixels = [
+            255, 0, 0, 255,   0, 255, 0, 0,
+            0, 0, 255, 255,   255, 255, 255, 255,
+        ];
+        let image = Image::new(2, 2, &pixels).unwrap();
+
+        let mut data = rgb(3, 3);
+        let mut c = canvas(&mut data, 3, 3);
+        blit(&mut c, &image, 1, 1, 255);
+        assert_eq!(c.pixel(1, 1), &[255, 0, 0]);
+        // fully transparent source pixel
+        assert_eq!(c.pixel(2, 1), &[0, 0, 0]);
+        assert_eq!(c.pixel(1, 2), &[0, 0, 255]);
+        assert_eq!(c.pixel(2, 2), &[255, 255, 255]);
+
+        let mut data = rgb(2, 2);
+        let mut c = canvas(&mut data, 2, 2);
+        blit(&mut c, &image, -1, -1, 128);
+        assert_eq!(c.pixel(0, 0), &[128, 128, 128]);
+        assert_eq!(c.pixel(1, 1), &[0, 0, 0]);
+    }
+}
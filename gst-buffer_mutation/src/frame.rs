@@ -0,0 +1,151 @@
+use std::fmt;
+use std::slice;
+
+use gstreamer as gst;
+use gstreamer_video as gst_video;
+
+use crate::{Canvas, Color, I420Canvas, Matrix, Nv12Canvas, PackedCanvas, PackedLayout};
+
+#[derive(Debug)]
+pub struct UnsupportedFormat(pub gst_video::VideoFormat);
+
+impl fmt::Display for UnsupportedFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Unsupported video format {:?}", self.0)
+    }
+}
+
+impl std::error::Error for UnsupportedFormat {}
+
+// a canvas over the planes of a mapped video frame
+pub enum FrameCanvas<'a> {
+    Packed(PackedCanvas<'a>),
+    I420(I420Canvas<'a>),
+    Nv12(Nv12Canvas<'a>),
+}
+
+fn packed_layout(format: gst_video::VideoFormat) -> Option<PackedLayout> {
+    use gst_video::VideoFormat;
+
+    let layout = match format {
+        VideoFormat::Bgrx => PackedLayout::BGRX,
+        VideoFormat::Bgra => PackedLayout::BGRA,
+        VideoFormat::Rgbx => PackedLayout::RGBX,
+        VideoFormat::Rgba => PackedLayout::RGBA,
+        VideoFormat::Xrgb => PackedLayout::XRGB,
+        VideoFormat::Argb => PackedLayout::ARGB,
+        VideoFormat::Xbgr => PackedLayout::XBGR,
+        VideoFormat::Abgr => PackedLayout::ABGR,
+        VideoFormat::Rgb => PackedLayout::RGB,
+        VideoFormat::Bgr => PackedLayout::BGR,
+        _ => return None,
+    };
+
+    Some(layout)
+}
+
+// the matrix YUV frames described by `info` were made with, BT.601 unless they
+// say BT.709
+pub fn matrix(info: &gst_video::VideoInfo) -> Matrix {
+    match info.colorimetry().matrix() {
+        gst_video::VideoColorMatrix::Bt709 => Matrix::Bt709,
+        _ => Matrix::Bt601,
+    }
+}
+
+pub fn canvas<'a>(
+    frame: &'a mut gst_video::VideoFrameRef<&mut gst::BufferRef>,
+) -> Result<FrameCanvas<'a>, UnsupportedFormat> {
+    use gst_video::VideoFormat;
+
+    let format = frame.format();
+    let matrix = matrix(frame.info());
+    let (width, height) = (frame.width(), frame.height());
+    let strides: Vec<usize> = frame.plane_stride().iter().map(|s| *s as usize).collect();
+
+    // the planes of a frame never overlap, but each `plane_data_mut` call borrows the
+    // whole frame, so collect them first and hand out the disjoint slices together
+    let mut planes: Vec<(*mut u8, usize)> = Vec::new();
+    for plane in 0..frame.n_planes() {
+        let data = frame
+            .plane_data_mut(plane)
+            .map_err(|_| UnsupportedFormat(format))?;
+        planes.push((data.as_mut_ptr(), data.len()));
+    }
+    let mut planes = planes
+        .into_iter()
+        .map(|(ptr, len)| unsafe { slice::from_raw_parts_mut(ptr, len) });
+
+    let canvas = if let Some(layout) = packed_layout(format) {
+        let data = planes.next().ok_or(UnsupportedFormat(format))?;
+        PackedCanvas::new(data, width, height, strides[0], layout).map(FrameCanvas::Packed)
+    } else {
+        match format {
+            VideoFormat::I420 | VideoFormat::Yv12 => {
+                let y = planes.next().ok_or(UnsupportedFormat(format))?;
+                let mut u = planes.next().ok_or(UnsupportedFormat(format))?;
+                let mut v = planes.next().ok_or(UnsupportedFormat(format))?;
+                let mut chroma_strides = (strides[1], strides[2]);
+
+                // YV12 stores V before U
+                if format == VideoFormat::Yv12 {
+                    std::mem::swap(&mut u, &mut v);
+                    chroma_strides = (chroma_strides.1, chroma_strides.0);
+                }
+
+                let strides = [strides[0], chroma_strides.0, chroma_strides.1];
+                I420Canvas::new(y, u, v, width, height, strides)
+                    .map(|c| FrameCanvas::I420(c.with_matrix(matrix)))
+            }
+            VideoFormat::Nv12 | VideoFormat::Nv21 => {
+                let y = planes.next().ok_or(UnsupportedFormat(format))?;
+                let uv = planes.next().ok_or(UnsupportedFormat(format))?;
+                let strides = [strides[0], strides[1]];
+
+                let canvas = if format == VideoFormat::Nv12 {
+                    Nv12Canvas::new(y, uv, width, height, strides)
+                } else {
+                    Nv12Canvas::nv21(y, uv, width, height, strides)
+                };
+                canvas.map(|c| FrameCanvas::Nv12(c.with_matrix(matrix)))
+            }
+            _ => None,
+        }
+    };
+
+    canvas.ok_or(UnsupportedFormat(format))
+}
+
+impl<'a> Canvas for FrameCanvas<'a> {
+    fn width(&self) -> u32 {
+        match self {
+            FrameCanvas::Packed(c) => c.width(),
+            FrameCanvas::I420(c) => c.width(),
+            FrameCanvas::Nv12(c) => c.width(),
+        }
+    }
+
+    fn height(&self) -> u32 {
+        match self {
+            FrameCanvas::Packed(c) => c.height(),
+            FrameCanvas::I420(c) => c.height(),
+            FrameCanvas::Nv12(c) => c.height(),
+        }
+    }
+
+    fn blend_pixel(&mut self, x: i32, y: i32, color: Color) {
+        match self {
+            FrameCanvas::Packed(c) => c.blend_pixel(x, y, color),
+            FrameCanvas::I420(c) => c.blend_pixel(x, y, color),
+            FrameCanvas::Nv12(c) => c.blend_pixel(x, y, color),
+        }
+    }
+
+    fn blend_span(&mut self, x0: i32, x1: i32, y: i32, color: Color) {
+        match self {
+            FrameCanvas::Packed(c) => c.blend_span(x0, x1, y, color),
+            FrameCanvas::I420(c) => c.blend_span(x0, x1, y, color),
+            FrameCanvas::Nv12(c) => c.blend_span(x0, x1, y, color),
+        }
+    }
+}
@@ -0,0 +1,93 @@
+// Minimal raster drawing on raw video frames, without cairo. Everything except
+// `frame`, behind the "gst" feature, works on plain byte slices, so the
+// primitives can be used (and checked) without GStreamer.
+
+mod draw;
+mod font;
+#[cfg(feature = "gst")]
+mod frame;
+mod packed;
+mod planar;
+mod yuv;
+
+pub use draw::{blit, circle, fill_circle, fill_rect, hline, line, vline, Image, ImageTooSmall};
+pub use font::{draw_text, text_size, GLYPH_HEIGHT, GLYPH_WIDTH};
+#[cfg(feature = "gst")]
+pub use frame::{canvas, matrix, FrameCanvas, UnsupportedFormat};
+pub use packed::{PackedCanvas, PackedLayout};
+pub use planar::{I420Canvas, Nv12Canvas};
+pub use yuv::{over, Matrix};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl Color {
+    pub const BLACK: Color = Color::rgb(0, 0, 0);
+    pub const WHITE: Color = Color::rgb(255, 255, 255);
+
+    pub const fn rgb(r: u8, g: u8, b: u8) -> Self {
+        Color { r, g, b, a: 255 }
+    }
+
+    pub const fn rgba(r: u8, g: u8, b: u8, a: u8) -> Self {
+        Color { r, g, b, a }
+    }
+
+    pub fn with_alpha(self, a: u8) -> Self {
+        Color { a, ..self }
+    }
+
+    // BT.601 limited range, what streams that don't say otherwise use
+    pub fn to_yuv(self) -> (u8, u8, u8) {
+        self.to_yuv_with(Matrix::Bt601)
+    }
+
+    pub fn to_yuv_with(self, matrix: Matrix) -> (u8, u8, u8) {
+        let (r, g, b) = (i32::from(self.r), i32::from(self.g), i32::from(self.b));
+        let (u, v) = matrix.chroma(r, g, b, 255);
+
+        (matrix.luma(r, g, b, 255) as u8, u as u8, v as u8)
+    }
+
+    // components scaled by alpha, as `Matrix` and `over` expect them
+    pub fn premultiplied(self) -> (i32, i32, i32, i32) {
+        let a = i32::from(self.a);
+        let scale = |c: u8| (i32::from(c) * a + 127) / 255;
+
+        (scale(self.r), scale(self.g), scale(self.b), a)
+    }
+}
+
+// blends `src` over `dst` with an 8 bit alpha
+#[inline]
+pub fn blend(dst: u8, src: u8, alpha: u8) -> u8 {
+    let (dst, src, alpha) = (u32::from(dst), u32::from(src), u32::from(alpha));
+
+    ((src * alpha + dst * (255 - alpha) + 127) / 255) as u8
+}
+
+// Something pixels can be drawn on. Coordinates outside the canvas are ignored,
+// so callers never have to clip.
+pub trait Canvas {
+    fn width(&self) -> u32;
+    fn height(&self) -> u32;
+
+    fn blend_pixel(&mut self, x: i32, y: i32, color: Color);
+
+    // horizontal run of pixels from `x0` to `x1` inclusive
+    fn blend_span(&mut self, x0: i32, x1: i32, y: i32, color: Color) {
+        let (x0, x1) = (x0.max(0), x1.min(self.width() as i32 - 1));
+        for x in x0..=x1 {
+            self.blend_pixel(x, y, color);
+        }
+    }
+
+    fn contains(&self, x: i32, y: i32) -> bool {
+        x >= 0 && y >= 0 && (x as u32) < self.width() && (y as u32) < self.height()
+    }
+}
@@ -1,7 +1,10 @@
 use gstreamer as gst;
+use gstreamer_video as gst_video;
 
 use gst::prelude::*;
 
+use raster::Color;
+
 const FRAME_WIDTH: i32 = 640;
 const FRAME_HEIGHT: i32 = 640;
 
@@ -16,7 +19,9 @@ fn example_main() {
     pipeline.add_many(&[&src, &capsfilter, &sink]).unwrap();
     gst::Element::link_many(&[&src, &capsfilter, &sink]).unwrap();
 
+    // any format `raster::canvas` supports would do, frames in others pass untouched
     let caps = gst::Caps::builder("video/x-raw")
+        .field("format", &"BGRx")
         .field("width", &FRAME_WIDTH)
         .field("height", &FRAME_HEIGHT)
         .field("framerate", &gst::Fraction::new(15, 1))
@@ -29,103 +34,44 @@ fn example_main() {
     let src = pipeline.get_by_name("src").unwrap();
     let pad = src.get_static_pad("src").unwrap();
 
-    pad.add_probe(gst::PadProbeType::BUFFER, move |_, probe_info| {
+    pad.add_probe(gst::PadProbeType::BUFFER, move |pad, probe_info| {
         if let Some(gst::PadProbeData::Buffer(ref mut buffer)) = probe_info.data {
-            let buffer = buffer.make_mut();
-            let mut map = buffer.map_writable().unwrap();
-
-            let ref mut buf_modified = map.to_vec();
-
-            //& redundant block of code
-            { // let mut row_start = 0;
-                 // let mut row_end = 2560;
-                 // for _ in 0..320 {
-                 //     // let row = &mut modified[row_start..row_end];
-                 //     // let pix = &mut row[1280];
-                 //     // *pix += 255;
-                 //     // row_start += 2560;
-                 //     // row_end += 2560;
-                 //     let row = modified.chunks_exact_mut(4);
-                 //     let mut count = 0;
-                 //     for arr in row {
-                 //         if count == 120 {
-                 //             for pix in arr {
-                 //                 *pix = 255;
-                 //             }
-                 //         }
-                 //         count += 1;
-                 //     }
-                 // }
-            }
-
-            //& redundant block of code
-            { // let mut count = 0;
-                 // for _ in map.to_vec().iter_mut() {
-                 //     if count < 640 * 2 {
-                 //         map[count] = 0;
-                 //     } else {
-                 //         count = 0;
-                 //     }
-                 //     count += 1;
-                 // }
-            }
-
-            //? _____Draw a square at (x,y) position in the buffer frame_____ (Attempt: 1)
+            let info = match pad
+                .get_current_caps()
+                .and_then(|caps| gst_video::VideoInfo::from_caps(&caps).ok())
             {
-                //? draw dimensions for square
-                let lines = FRAME_WIDTH * 4;
-                let draw_x = 320;
-                let draw_y = 320;
-
-                //^ note : square size cannot be greater than any of the draw dimensions
-                let square_size = 50;
-
-                //^ counter for vertical lines in the frame
-                let mut vertical_lines = 0;
-
-                //^ Iterates over each line (note: accounts for stride per line)
-                for line in buf_modified.chunks_exact_mut(lines as usize) {
-                    //^ since the square grows from the center i.e. half in each direction and
-                    //^ there is an exact '4' chunks per iteration, square_size is divided by 8 (4 * 2)
-
-                    if (vertical_lines > draw_y - (square_size / 8))
-                        && (vertical_lines < draw_y + (square_size / 8))
-                    {
-                        for pix in &mut line[((draw_x * 4) - (square_size / 2))
-                            ..=((draw_x * 4) + (square_size / 2))]
-                            .chunks_exact_mut(4)
-                        {
-                            //^ change each of 'ARGB' values per pixel to white
-                            pix[0] = 255;
-                            pix[1] = 255;
-                            pix[2] = 255;
-                            pix[3] = 255;
-                        }
-                    }
-                    vertical_lines += 1
-                }
+                Some(info) => info,
+                None => return gst::PadProbeReturn::Ok,
+            };
 
-                map.swap_with_slice(buf_modified);
-            }
-
-            //? _____Draw a square at (x,y) position in the buffer frame_____ (Attempt: 2)
+            let buffer = buffer.make_mut();
+            let mut frame = match gst_video::VideoFrameRef::from_buffer_ref_writable(buffer, &info)
             {
-                let x_pos = 320;
-                let y_pos = 320;
-                let square_size = 50;
-
-                for j in 0..=square_size {
-                    let start_idx = ((y_pos + j) * 2560) + (x_pos * 4);
-                    let end_idx = start_idx + (square_size * 4);
-
-                    let line = &mut buf_modified[start_idx..=end_idx];
-                    for val in line {
-                        *val = 255;
-                    }
+                Ok(frame) => frame,
+                Err(_) => return gst::PadProbeReturn::Ok,
+            };
+            let mut canvas = match raster::canvas(&mut frame) {
+                Ok(canvas) => canvas,
+                Err(err) => {
+                    eprintln!("Not drawing: {}", err);
+                    return gst::PadProbeReturn::Ok;
                 }
-
-                map.swap_with_slice(buf_modified);
-            }
+            };
+
+            //? draw a square centered at (x,y) position in the buffer frame
+            let draw_x = 320;
+            let draw_y = 320;
+            let square_size = 50;
+
+            raster::fill_rect(
+                &mut canvas,
+                draw_x - square_size / 2,
+                draw_y - square_size / 2,
+                square_size as u32,
+                square_size as u32,
+                Color::WHITE,
+            );
+            raster::draw_text(&mut canvas, 10, 10, "gst-buffer_mutation", 2, Color::WHITE);
         };
 
         gst::PadProbeReturn::Ok
@@ -0,0 +1,162 @@
+use crate::{blend, Canvas, Color};
+
+// byte positions of the components inside one packed pixel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PackedLayout {
+    pub bytes_per_pixel: usize,
+    pub r: usize,
+    pub g: usize,
+    pub b: usize,
+    pub a: Option<usize>,
+}
+
+impl PackedLayout {
+    pub const BGRX: PackedLayout = PackedLayout::new(4, 2, 1, 0, None);
+    pub const BGRA: PackedLayout = PackedLayout::new(4, 2, 1, 0, Some(3));
+    pub const RGBX: PackedLayout = PackedLayout::new(4, 0, 1, 2, None);
+    pub const RGBA: PackedLayout = PackedLayout::new(4, 0, 1, 2, Some(3));
+    pub const XRGB: PackedLayout = PackedLayout::new(4, 1, 2, 3, None);
+    pub const ARGB: PackedLayout = PackedLayout::new(4, 1, 2, 3, Some(0));
+    pub const XBGR: PackedLayout = PackedLayout::new(4, 3, 2, 1, None);
+    pub const ABGR: PackedLayout = PackedLayout::new(4, 3, 2, 1, Some(0));
+    pub const RGB: PackedLayout = PackedLayout::new(3, 0, 1, 2, None);
+    pub const BGR: PackedLayout = PackedLayout::new(3, 2, 1, 0, None);
+
+    pub const fn new(
+        bytes_per_pixel: usize,
+        r: usize,
+        g: usize,
+        b: usize,
+        a: Option<usize>,
+    ) -> Self {
+        PackedLayout {
+            bytes_per_pixel,
+            r,
+            g,
+            b,
+            a,
+        }
+    }
+}
+
+pub struct PackedCanvas<'a> {
+    data: &'a mut [u8],
+    width: u32,
+    height: u32,
+    stride: usize,
+    layout: PackedLayout,
+}
+
+impl<'a> PackedCanvas<'a> {
+    // `stride` is the number of bytes per row, which can be more than width * bytes_per_pixel
+    pub fn new(
+        data: &'a mut [u8],
+        width: u32,
+        height: u32,
+        stride: usize,
+        layout: PackedLayout,
+    ) -> Option<Self> {
+        let row = width as usize * layout.bytes_per_pixel;
+        if height > 0 && (stride < row || data.len() < stride * (height as usize - 1) + row) {
+            return None;
+        }
+
+        Some(PackedCanvas {
+            data,
+            width,
+            height,
+            stride,
+            layout,
+        })
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> &[u8] {
+        let idx = y as usize * self.stride + x as usize * self.layout.bytes_per_pixel;
+        &self.data[idx..idx + self.layout.bytes_per_pixel]
+    }
+}
+
+impl<'a> Canvas for PackedCanvas<'a> {
+    fn width(&self) -> u32 {
+        self.width
+    }
+
+    fn height(&self) -> u32 {
+        self.height
+    }
+
+    fn blend_pixel(&mut self, x: i32, y: i32, color: Color) {
+        if !self.contains(x, y) || color.a == 0 {
+            return;
+        }
+
+        let layout = self.layout;
+        let idx = y as usize * self.stride + x as usize * layout.bytes_per_pixel;
+        let px = &mut self.data[idx..idx + layout.bytes_per_pixel];
+
+        px[layout.r] = blend(px[layout.r], color.r, color.a);
+        px[layout.g] = blend(px[layout.g], color.g, color.a);
+        px[layout.b] = blend(px[layout.b], color.b, color.a);
+        if let Some(a) = layout.a {
+            px[a] = blend(px[a], 255, color.a);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_buffers_too_small_for_the_stride() {
+        // the last row needs no padding
+        let mut data = vec![0; 24];
+
+        assert!(PackedCanvas::new(&mut data, 2, 2, 8, PackedLayout::BGRX).is_some());
+        assert!(PackedCanvas::new(&mut data, 2, 2, 16, PackedLayout::BGRX).is_some());
+        assert!(PackedCanvas::new(&mut data, 2, 2, 20, PackedLayout::BGRX).is_none());
+        assert!(PackedCanvas::new(&mut data, 2, 2, 4, PackedLayout::BGRX).is_none());
+        assert!(PackedCanvas::new(&mut data, 4, 2, 16, PackedLayout::BGRX).is_none());
+    }
+
+    #[test]
+    fn writes_components_in_layout_order() {
+        let color = Color::rgb(10, 20, 30);
+        let cases = [
+            (PackedLayout::BGRX, [30, 20, 10, 0]),
+            (PackedLayout::RGBA, [10, 20, 30, 255]),
+            (PackedLayout::ARGB, [255, 10, 20, 30]),
+            (PackedLayout::XBGR, [0, 30, 20, 10]),
+        ];
+
+        for &(layout, expected) in cases.iter() {
+            let mut data = vec![0; 4];
+            let mut canvas = PackedCanvas::new(&mut data, 1, 1, 4, layout).unwrap();
+            canvas.blend_pixel(0, 0, color);
+            assert_eq!(canvas.pixel(0, 0), &expected, "{:?}", layout);
+        }
+    }
+
+    #[test]
+    fn skips_row_padding_and_outside_pixels() {
+        // two 1 pixel rows, 2 bytes of padding each
+        let mut data = vec![0; 10];
+        {
+            let mut canvas = PackedCanvas::new(&mut data, 1, 2, 5, PackedLayout::RGB).unwrap();
+            canvas.blend_pixel(0, 1, Color::WHITE);
+            canvas.blend_pixel(1, 0, Color::WHITE);
+            canvas.blend_pixel(0, -1, Color::WHITE);
+        }
+
+        assert_eq!(data, vec![0, 0, 0, 0, 0, 255, 255, 255, 0, 0]);
+    }
+
+    #[test]
+    fn translucent_colors_blend() {
+        let mut data = vec![0, 0, 0, 100];
+        let mut canvas = PackedCanvas::new(&mut data, 1, 1, 4, PackedLayout::RGBA).unwrap();
+        canvas.blend_pixel(0, 0, Color::rgba(255, 0, 0, 51));
+
+        assert_eq!(canvas.pixel(0, 0), &[51, 0, 0, 131]);
+    }
+}
@@ -0,0 +1,370 @@
+use std::collections::HashMap;
+use std::mem;
+
+use crate::{over, Canvas, Color, Matrix};
+
+// Chroma is shared by 2x2 pixel blocks. Luma is blended as pixels are drawn,
+// chroma once per sample when the canvas is dropped, from the mean colour and
+// coverage of its block like gst-draw_on_video does, so translucent colours get
+// the same strength in both and shape edges only tint their block partly.
+
+fn check_plane(len: usize, stride: usize, row: usize, rows: usize) -> bool {
+    rows == 0 || (stride >= row && len >= stride * (rows - 1) + row)
+}
+
+fn chroma_size(width: u32, height: u32) -> (usize, usize) {
+    (width.div_ceil(2) as usize, height.div_ceil(2) as usize)
+}
+
+// premultiplied RGBA drawn over the pixels of each touched 2x2 block
+#[derive(Default)]
+struct PendingChroma {
+    blocks: HashMap<(usize, usize), [[i32; 4]; 4]>,
+}
+
+impl PendingChroma {
+    fn add(&mut self, x: usize, y: usize, src: (i32, i32, i32, i32)) {
+        let block = self.blocks.entry((x / 2, y / 2)).or_default();
+        let dst = &mut block[(y % 2) * 2 + x % 2];
+
+        let (r, g, b, a) = src;
+        for (dst, src) in dst.iter_mut().zip([r, g, b, a]) {
+            *dst = src + (*dst * (255 - a) + 127) / 255;
+        }
+    }
+
+    // chroma sample position with the mean premultiplied colour of its block,
+    // pixels past the right or bottom edge left out
+    fn samples(self, width: u32, height: u32) -> impl Iterator<Item = (usize, usize, [i32; 4])> {
+        let (width, height) = (width as usize, height as usize);
+
+        self.blocks.into_iter().map(move |((cx, cy), block)| {
+            let columns = (width - cx * 2).min(2);
+            let rows = (height - cy * 2).min(2);
+            let count = (columns * rows) as i32;
+
+            let mut mean = [0; 4];
+            for (i, mean) in mean.iter_mut().enumerate() {
+                let sum: i32 = block.iter().map(|pixel| pixel[i]).sum();
+                *mean = (sum + count / 2) / count;
+            }
+
+            (cx, cy, mean)
+        })
+    }
+}
+
+// three planes: full resolution Y, quarter resolution U and V (YV12 has them swapped)
+pub struct I420Canvas<'a> {
+    y: &'a mut [u8],
+    u: &'a mut [u8],
+    v: &'a mut [u8],
+    width: u32,
+    height: u32,
+    strides: [usize; 3],
+    matrix: Matrix,
+    pending: PendingChroma,
+}
+
+impl<'a> I420Canvas<'a> {
+    pub fn new(
+        y: &'a mut [u8],
+        u: &'a mut [u8],
+        v: &'a mut [u8],
+        width: u32,
+        height: u32,
+        strides: [usize; 3],
+    ) -> Option<Self> {
+        let (cw, ch) = chroma_size(width, height);
+        if !check_plane(y.len(), strides[0], width as usize, height as usize)
+            || !check_plane(u.len(), strides[1], cw, ch)
+            || !check_plane(v.len(), strides[2], cw, ch)
+        {
+            return None;
+        }
+
+        Some(I420Canvas {
+            y,
+            u,
+            v,
+            width,
+            height,
+            strides,
+            matrix: Matrix::default(),
+            pending: PendingChroma::default(),
+        })
+    }
+
+    pub fn with_matrix(mut self, matrix: Matrix) -> Self {
+        self.matrix = matrix;
+        self
+    }
+}
+
+impl<'a> Canvas for I420Canvas<'a> {
+    fn width(&self) -> u32 {
+        self.width
+    }
+
+    fn height(&self) -> u32 {
+        self.height
+    }
+
+    fn blend_pixel(&mut self, x: i32, y: i32, color: Color) {
+        if !self.contains(x, y) || color.a == 0 {
+            return;
+        }
+
+        let (r, g, b, a) = color.premultiplied();
+        let cy = self.matrix.luma(r, g, b, a);
+        let (x, y) = (x as usize, y as usize);
+
+        let idx = y * self.strides[0] + x;
+        self.y[idx] = over(self.y[idx], cy, a as u32);
+        self.pending.add(x, y, (r, g, b, a));
+    }
+}
+
+impl<'a> Drop for I420Canvas<'a> {
+    fn drop(&mut self) {
+        let pending = mem::take(&mut self.pending);
+        for (cx, cy, [r, g, b, a]) in pending.samples(self.width, self.height) {
+            let (cu, cv) = self.matrix.chroma(r, g, b, a);
+
+            let idx = cy * self.strides[1] + cx;
+            self.u[idx] = over(self.u[idx], cu, a as u32);
+            let idx = cy * self.strides[2] + cx;
+            self.v[idx] = over(self.v[idx], cv, a as u32);
+        }
+    }
+}
+
+// two planes: full resolution Y and interleaved quarter resolution UV (VU for NV21)
+pub struct Nv12Canvas<'a> {
+    y: &'a mut [u8],
+    uv: &'a mut [u8],
+    width: u32,
+    height: u32,
+    strides: [usize; 2],
+    swap_uv: bool,
+    matrix: Matrix,
+    pending: PendingChroma,
+}
+
+impl<'a> Nv12Canvas<'a> {
+    pub fn new(
+        y: &'a mut [u8],
+        uv: &'a mut [u8],
+        width: u32,
+        height: u32,
+        strides: [usize; 2],
+    ) -> Option<Self> {
+        let (cw, ch) = chroma_size(width, height);
+        if !check_plane(y.len(), strides[0], width as usize, height as usize)
+            || !check_plane(uv.len(), strides[1], cw * 2, ch)
+        {
+            return None;
+        }
+
+        Some(Nv12Canvas {
+            y,
+            uv,
+            width,
+            height,
+            strides,
+            swap_uv: false,
+            matrix: Matrix::default(),
+            pending: PendingChroma::default(),
+        })
+    }
+
+    pub fn nv21(
+        y: &'a mut [u8],
+        vu: &'a mut [u8],
+        width: u32,
+        height: u32,
+        strides: [usize; 2],
+    ) -> Option<Self> {
+        Nv12Canvas::new(y, vu, width, height, strides).map(|mut canvas| {
+            canvas.swap_uv = true;
+            canvas
+        })
+    }
+
+    pub fn with_matrix(mut self, matrix: Matrix) -> Self {
+        self.matrix = matrix;
+        self
+    }
+}
+
+impl<'a> Canvas for Nv12Canvas<'a> {
+    fn width(&self) -> u32 {
+        self.width
+    }
+
+    fn height(&self) -> u32 {
+        self.height
+    }
+
+    fn blend_pixel(&mut self, x: i32, y: i32, color: Color) {
+        if !self.contains(x, y) || color.a == 0 {
+            return;
+        }
+
+        let (r, g, b, a) = color.premultiplied();
+        let cy = self.matrix.luma(r, g, b, a);
+        let (x, y) = (x as usize, y as usize);
+
+        let idx = y * self.strides[0] + x;
+        self.y[idx] = over(self.y[idx], cy, a as u32);
+        self.pending.add(x, y, (r, g, b, a));
+    }
+}
+
+impl<'a> Drop for Nv12Canvas<'a> {
+    fn drop(&mut self) {
+        let pending = mem::take(&mut self.pending);
+        for (cx, cy, [r, g, b, a]) in pending.samples(self.width, self.height) {
+            let (cu, cv) = self.matrix.chroma(r, g, b, a);
+            let (cu, cv) = if self.swap_uv { (cv, cu) } else { (cu, cv) };
+
+            let idx = cy * self.strides[1] + cx * 2;
+            self.uv[idx] = over(self.uv[idx], cu, a as u32);
+            self.uv[idx + 1] = over(self.uv[idx + 1], cv, a as u32);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::fill_rect;
+
+    const RED: Color = Color::rgb(255, 0, 0);
+
+    // a single blend of `color` over `dst`, what a fully covered block gets
+    fn chroma_over(dst: (u8, u8), color: Color, matrix: Matrix) -> (u8, u8) {
+        let (r, g, b, a) = color.premultiplied();
+        let (cu, cv) = matrix.chroma(r, g, b, a);
+        (over(dst.0, cu, a as u32), over(dst.1, cv, a as u32))
+    }
+
+    #[test]
+    fn rejects_planes_too_small() {
+        let (mut y, mut u, mut v) = (vec![0; 9], vec![0; 4], vec![0; 4]);
+        assert!(I420Canvas::new(&mut y, &mut u, &mut v, 3, 3, [3, 2, 2]).is_some());
+        assert!(I420Canvas::new(&mut y, &mut u, &mut v, 3, 3, [3, 1, 2]).is_none());
+
+        let (mut y, mut uv) = (vec![0; 9], vec![0; 7]);
+        assert!(Nv12Canvas::new(&mut y, &mut uv, 3, 3, [3, 4]).is_none());
+    }
+
+    #[test]
+    fn i420_blends_luma_per_pixel_and_chroma_per_block() {
+        let (mut y, mut u, mut v) = (vec![16; 16], vec![128; 4], vec![128; 4]);
+        {
+            let mut canvas = I420Canvas::new(&mut y, &mut u, &mut v, 4, 4, [4, 2, 2]).unwrap();
+            canvas.blend_pixel(3, 1, RED);
+            canvas.blend_pixel(4, 1, RED);
+        }
+
+        let mut expected = vec![16; 16];
+        expected[7] = RED.to_yuv().0;
+        assert_eq!(y, expected);
+
+        // one pixel of four, the block is a quarter red
+        let (cu, cv) = Matrix::Bt601.chroma(64, 0, 0, 64);
+        assert_eq!(u, vec![128, over(128, cu, 64), 128, 128]);
+        assert_eq!(v, vec![128, over(128, cv, 64), 128, 128]);
+    }
+
+    #[test]
+    fn translucent_blocks_blend_chroma_once() {
+        let red = RED.with_alpha(128);
+        let (u, v) = chroma_over((128, 128), red, Matrix::Bt601);
+
+        let (mut y, mut cu, mut cv) = (vec![16; 4], vec![128], vec![128]);
+        fill_rect(
+            &mut I420Canvas::new(&mut y, &mut cu, &mut cv, 2, 2, [2, 1, 1]).unwrap(),
+            0,
+            0,
+            2,
+            2,
+            red,
+        );
+        assert_eq!((cu[0], cv[0]), (u, v));
+
+        let (r, g, b, a) = red.premultiplied();
+        let luma = over(16, Matrix::Bt601.luma(r, g, b, a), a as u32);
+        assert_eq!(y, vec![luma; 4]);
+
+        let (mut y, mut uv) = (vec![16; 4], vec![128; 2]);
+        fill_rect(
+            &mut Nv12Canvas::new(&mut y, &mut uv, 2, 2, [2, 2]).unwrap(),
+            0,
+            0,
+            2,
+            2,
+            red,
+        );
+        assert_eq!(uv, vec![u, v]);
+    }
+
+    #[test]
+    fn overlapping_draws_stack_before_the_chroma_blend() {
+        let half = RED.with_alpha(128);
+        let (mut y, mut u, mut v) = (vec![16; 4], vec![128], vec![128]);
+        {
+            let mut canvas = I420Canvas::new(&mut y, &mut u, &mut v, 2, 2, [2, 1, 1]).unwrap();
+            fill_rect(&mut canvas, 0, 0, 2, 2, half);
+            fill_rect(&mut canvas, 0, 0, 2, 2, half);
+        }
+
+        // half over half is three quarters red
+        let (cu, cv) = Matrix::Bt601.chroma(192, 0, 0, 192);
+        assert_eq!((u[0], v[0]), (over(128, cu, 192), over(128, cv, 192)));
+    }
+
+    #[test]
+    fn odd_sizes_reach_the_last_chroma_sample() {
+        let (mut y, mut u, mut v) = (vec![0; 9], vec![128; 4], vec![128; 4]);
+        {
+            let mut canvas = I420Canvas::new(&mut y, &mut u, &mut v, 3, 3, [3, 2, 2]).unwrap();
+            canvas.blend_pixel(2, 2, RED);
+        }
+
+        assert_eq!(u[3], RED.to_yuv().1);
+    }
+
+    #[test]
+    fn nv12_interleaves_and_nv21_swaps_chroma() {
+        let (_, cu, cv) = RED.to_yuv();
+
+        let (mut y, mut uv) = (vec![0; 4], vec![128; 2]);
+        let mut canvas = Nv12Canvas::new(&mut y, &mut uv, 2, 2, [2, 2]).unwrap();
+        fill_rect(&mut canvas, 0, 0, 2, 2, RED);
+        drop(canvas);
+        assert_eq!(uv, vec![cu, cv]);
+
+        let (mut y, mut vu) = (vec![0; 4], vec![128; 2]);
+        let mut canvas = Nv12Canvas::nv21(&mut y, &mut vu, 2, 2, [2, 2]).unwrap();
+        fill_rect(&mut canvas, 0, 0, 2, 2, RED);
+        drop(canvas);
+        assert_eq!(vu, vec![cv, cu]);
+    }
+
+    #[test]
+    fn hd_canvases_use_bt709() {
+        let (mut y, mut uv) = (vec![0; 4], vec![128; 2]);
+        let mut canvas = Nv12Canvas::new(&mut y, &mut uv, 2, 2, [2, 2])
+            .unwrap()
+            .with_matrix(Matrix::Bt709);
+        fill_rect(&mut canvas, 0, 0, 2, 2, RED);
+        drop(canvas);
+
+        let (cy, cu, cv) = RED.to_yuv_with(Matrix::Bt709);
+        assert_eq!((y[0], uv[0], uv[1]), (cy, cu, cv));
+        assert_ne!(cy, RED.to_yuv().0);
+    }
+}
@@ -0,0 +1,87 @@
+// Limited range YUV from RGB, shared by the planar canvases and by overlays
+// blended from premultiplied ARGB into YUV frames.
+
+// the colour matrix a stream's YUV was made with
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Matrix {
+    // SD, and what streams that don't say are assumed to use
+    #[default]
+    Bt601,
+    // HD
+    Bt709,
+}
+
+impl Matrix {
+    // rows for Y, U and V, scaled by 256
+    fn rows(self) -> [[i32; 3]; 3] {
+        match self {
+            Matrix::Bt601 => [[66, 129, 25], [-38, -74, 112], [112, -94, -18]],
+            Matrix::Bt709 => [[47, 157, 16], [-26, -86, 112], [112, -102, -10]],
+        }
+    }
+
+    // Y of a colour premultiplied by `a`, the offset is premultiplied like the colour
+    pub fn luma(self, r: i32, g: i32, b: i32, a: i32) -> i32 {
+        let [k, _, _] = self.rows();
+        ((k[0] * r + k[1] * g + k[2] * b + 128) >> 8) + (16 * a + 127) / 255
+    }
+
+    // U and V of a colour premultiplied by `a`
+    pub fn chroma(self, r: i32, g: i32, b: i32, a: i32) -> (i32, i32) {
+        let [_, u, v] = self.rows();
+        let offset = (128 * a + 127) / 255;
+
+        (
+            ((u[0] * r + u[1] * g + u[2] * b + 128) >> 8) + offset,
+            ((v[0] * r + v[1] * g + v[2] * b + 128) >> 8) + offset,
+        )
+    }
+}
+
+// blends a premultiplied sample over `dst`, so only the destination is scaled
+#[inline]
+pub fn over(dst: u8, src: i32, alpha: u32) -> u8 {
+    let dst = i32::from(dst) * (255 - alpha as i32) / 255;
+    (src + dst).clamp(0, 255) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn black_and_white_span_the_limited_range() {
+        for &matrix in [Matrix::Bt601, Matrix::Bt709].iter() {
+            assert_eq!(matrix.luma(0, 0, 0, 255), 16);
+            assert_eq!(matrix.luma(255, 255, 255, 255), 235);
+            assert_eq!(matrix.chroma(0, 0, 0, 255), (128, 128));
+            assert_eq!(matrix.chroma(255, 255, 255, 255), (128, 128));
+        }
+    }
+
+    #[test]
+    fn matrices_differ_for_colours() {
+        // green is where BT.601 and BT.709 are furthest apart
+        assert_eq!(Matrix::Bt601.luma(0, 255, 0, 255), 144);
+        assert_eq!(Matrix::Bt709.luma(0, 255, 0, 255), 172);
+        assert_eq!(Matrix::Bt601.chroma(255, 0, 0, 255), (90, 240));
+        assert_eq!(Matrix::Bt709.chroma(255, 0, 0, 255), (102, 240));
+    }
+
+    #[test]
+    fn offsets_are_premultiplied() {
+        // half transparent black only darkens by half
+        assert_eq!(Matrix::Bt601.luma(0, 0, 0, 128), 8);
+        assert_eq!(Matrix::Bt601.chroma(0, 0, 0, 128), (64, 64));
+        assert_eq!(Matrix::Bt601.luma(0, 0, 0, 0), 0);
+    }
+
+    #[test]
+    fn over_scales_only_the_destination() {
+        assert_eq!(over(200, 50, 255), 50);
+        assert_eq!(over(200, 0, 0), 200);
+        // 200 * 127 / 255 left of the destination
+        assert_eq!(over(200, 8, 128), 107);
+        assert_eq!(over(255, 300, 0), 255);
+    }
+}
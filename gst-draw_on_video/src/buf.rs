@@ -5,6 +5,8 @@ use pango::prelude::*;
 use std::ops;
 use std::sync::{Arc, Mutex};
 
+use vid_overlay::CairoFrame;
+
 const FRAME_WIDTH: i32 = 640;
 const FRAME_HEIGHT: i32 = 640;
 struct DrawingContext {
@@ -41,6 +43,7 @@ fn example_main() {
     gst::Element::link_many(&[&src, &capsfilter, &sink]).unwrap();
 
     let caps = gst::Caps::builder("video/x-raw")
+        .field("format", &gst_video::VideoFormat::Bgrx.to_str())
         .field("width", &FRAME_WIDTH)
         .field("height", &FRAME_HEIGHT)
         .field("framerate", &gst::Fraction::new(15, 1))
@@ -69,62 +72,40 @@ fn example_main() {
 
     let drawer_clone = drawer.clone();
 
-    pad.add_probe(gst::PadProbeType::BUFFER, move |_, probe_info| {
+    pad.add_probe(gst::PadProbeType::BUFFER, move |pad, probe_info| {
         if let Some(gst::PadProbeData::Buffer(ref mut buffer)) = probe_info.data {
-            let buffer = buffer.make_mut();
-            let mut map = buffer.map_writable().unwrap();
-
-            let buf_modified = &mut map.to_vec();
-
             let drawer = &drawer_clone;
-            let drawer = drawer.lock().unwrap();
+            let mut drawer = drawer.lock().unwrap();
+
+            if drawer.info.is_none() {
+                drawer.info = pad
+                    .get_current_caps()
+                    .and_then(|caps| gst_video::VideoInfo::from_caps(&caps).ok());
+            }
+            let info = match drawer.info {
+                Some(ref info) => info,
+                None => return gst::PadProbeReturn::Ok,
+            };
 
             let layout = drawer.layout.borrow();
 
-            //? _____Draw a square at (x,y) position in the buffer frame_____ (Attempt: 2)
-            // {
-            //     let x_pos = 320;
-            //     let y_pos = 320;
-            //     let square_size = 50;
-            //     let mut buf_idx = vec![];
-            //     for j in 0..=square_size {
-            //         let start_idx = ((y_pos + j) * 2560) + (x_pos * 4);
-            //         let end_idx = start_idx + (square_size * 4);
-            //         buf_idx.push((start_idx, end_idx));
-            //         let line = &mut buf_modified[start_idx..=end_idx];
-            //         for val in line {
-            //             *val = 0;
-            //         }
-            //     }
-            //     map.swap_with_slice(buf_modified);
-            // }
-
-            let buf_idx = &mut buf_modified[0..(2560 * 50)];
-            let mut cloned_buf = vec![0; (2560 * 50)];
-            cloned_buf.copy_from_slice(buf_idx);
-
-            let surface = cairo::ImageSurface::create_for_data(
-                cloned_buf,
-                cairo::Format::ARgb32,
-                640 as i32,
-                50 as i32,
-                2560 as i32,
-            )
-            .unwrap();
-
-            let cr = cairo::Context::new(&surface);
-
-            cr.paint();
+            // draw straight into the buffer, no copy in or out
+            let buffer = buffer.make_mut();
+            let frame = match CairoFrame::map(buffer, info) {
+                Ok(frame) => frame,
+                Err(err) => {
+                    eprintln!("Error! {}", err);
+                    return gst::PadProbeReturn::Ok;
+                }
+            };
+
+            let cr = frame.context();
 
             cr.set_source_rgb(1.0, 0., 0.);
 
-            cr.translate(f64::from(FRAME_WIDTH) / 2.0, 50.);
+            cr.translate(f64::from(frame.width()) / 2.0, 50.);
 
             pangocairo::functions::show_layout(&cr, &**layout);
-
-            map.swap_with_slice(&mut cloned_buf);
-            drop(cr);
-            drop(surface);
         };
 
         gst::PadProbeReturn::Ok
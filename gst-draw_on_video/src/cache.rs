@@ -0,0 +1,457 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::ops;
+use std::rc::Rc;
+
+use crate::gauge::draw_gauge;
+use crate::map::TrackMap;
+use crate::{track, GaugeSource, OverlayElement};
+
+// surfaces not used for this many frames are dropped
+const MAX_IDLE_FRAMES: u64 = 30;
+
+// The surface never leaves its wrapper and the wrapper is never cloned, so the
+// `Rc` holds the only handle and its count tells whether the cell may move.
+#[derive(Debug)]
+pub struct SurfaceWrapper(Rc<cairo::ImageSurface>);
+
+impl ops::Deref for SurfaceWrapper {
+    type Target = cairo::ImageSurface;
+
+    fn deref(&self) -> &cairo::ImageSurface {
+        &self.0
+    }
+}
+
+unsafe impl glib::SendUnique for SurfaceWrapper {
+    fn is_unique(&self) -> bool {
+        Rc::strong_count(&self.0) == 1
+    }
+}
+
+// a premultiplied ARGB surface sized to the bounding box of one element
+pub struct CachedSurface {
+    surface: glib::SendUniqueCell<SurfaceWrapper>,
+    buffer: Option<gst::Buffer>,
+    pub width: i32,
+    pub height: i32,
+    last_used: u64,
+}
+
+impl CachedSurface {
+    // renders an element without keeping it, for content that changes every frame
+    pub fn render(layout: &pango::Layout, element: &OverlayElement) -> Option<CachedSurface> {
+        CachedSurface::new(render(layout, element)?)
+    }
+
+    fn new(surface: cairo::ImageSurface) -> Option<CachedSurface> {
+        let (width, height) = (surface.get_width(), surface.get_height());
+
+        Some(CachedSurface {
+            surface: glib::SendUniqueCell::new(SurfaceWrapper(Rc::new(surface))).ok()?,
+            buffer: None,
+            width,
+            height,
+            last_used: 0,
+        })
+    }
+
+    // draws over the pixels in place, for content that changes now and then
+    fn redraw<F: FnOnce(&cairo::Context)>(&mut self, draw: F) {
+        {
+            let surface = self.surface.borrow();
+            let ctx = cairo::Context::new(&surface);
+            draw(&ctx);
+            surface.flush();
+        }
+        self.buffer = None;
+    }
+
+    // blend the surface onto a frame with its top left corner at (x, y)
+    pub fn paint(&self, ctx: &cairo::Context, x: f64, y: f64) {
+        let surface = self.surface.borrow();
+
+        ctx.save();
+        ctx.set_source_surface(&surface, x, y);
+        ctx.rectangle(x, y, f64::from(self.width), f64::from(self.height));
+        ctx.fill();
+        ctx.restore();
+    }
+
+    // premultiplied ARGB32 pixels and the row stride, for blending without cairo
+    pub fn with_pixels<R, F: FnOnce(&[u8], usize) -> R>(&self, f: F) -> Option<R> {
+        let surface = self.surface.borrow();
+        let stride = surface.get_stride() as usize;
+
+        let mut result = None;
+        surface
+            .with_data(|data| result = Some(f(data, stride)))
+            .ok()?;
+
+        result
+    }
+
+    // overlay rectangle sharing the pixels copied out of the surface on first use
+    pub fn to_rectangle(&mut self, x: f64, y: f64) -> Option<gst_video::VideoOverlayRectangle> {
+        if self.buffer.is_none() {
+            self.buffer = Some(self.to_buffer()?);
+        }
+
+        Some(gst_video::VideoOverlayRectangle::new_raw(
+            self.buffer.as_ref().unwrap(),
+            x.floor() as i32,
+            y.floor() as i32,
+            self.width as u32,
+            self.height as u32,
+            gst_video::VideoOverlayFormatFlags::PREMULTIPLIED_ALPHA,
+        ))
+    }
+
+    fn to_buffer(&self) -> Option<gst::Buffer> {
+        let surface = self.surface.borrow();
+        let stride = surface.get_stride() as usize;
+
+        let mut buffer = gst::Buffer::with_size(stride * self.height as usize).ok()?;
+        {
+            let buffer = buffer.get_mut().unwrap();
+
+            let mut copied = false;
+            surface
+                .with_data(|data| copied = buffer.copy_from_slice(0, data).is_ok())
+                .ok()?;
+            if !copied {
+                return None;
+            }
+
+            // cairo's ARGB32 is BGRA in memory on little endian machines
+            gst_video::VideoMeta::add_full(
+                buffer,
+                gst_video::VideoFrameFlags::empty(),
+                gst_video::VideoFormat::Bgra,
+                self.width as u32,
+                self.height as u32,
+                &[0],
+                &[stride as i32],
+            )
+            .ok()?;
+        }
+
+        Some(buffer)
+    }
+}
+
+// a track's static map and the surface showing it at the log time last drawn
+struct CachedTrack {
+    map: TrackMap,
+    now: f64,
+    surface: CachedSurface,
+}
+
+// rendered elements keyed by their content and style, so moving an element or
+// showing it again later never re-renders it. Tracks keep one surface each, only
+// the travelled part is redrawn as time goes on. A cache belongs to a single
+// layout and font, the key does not cover them.
+#[derive(Default)]
+pub struct RenderCache {
+    entries: HashMap<u64, CachedSurface>,
+    tracks: HashMap<u64, CachedTrack>,
+    frame: u64,
+}
+
+impl RenderCache {
+    pub fn new() -> Self {
+        RenderCache::default()
+    }
+
+    // starts a new frame and drops surfaces that were not used recently
+    pub fn begin_frame(&mut self) {
+        self.frame += 1;
+
+        let frame = self.frame;
+        self.entries
+            .retain(|_, entry| entry.last_used + MAX_IDLE_FRAMES >= frame);
+        self.tracks
+            .retain(|_, entry| entry.surface.last_used + MAX_IDLE_FRAMES >= frame);
+    }
+
+    pub fn get_or_render(
+        &mut self,
+        layout: &pango::Layout,
+        element: &OverlayElement,
+    ) -> Option<&mut CachedSurface> {
+        let key = cache_key(element);
+        if let OverlayElement::Track(track) = element {
+            return self.get_or_draw_track(key, track);
+        }
+
+        if !self.entries.contains_key(&key) {
+            let surface = CachedSurface::render(layout, element)?;
+            self.entries.insert(key, surface);
+        }
+
+        let entry = self.entries.get_mut(&key).unwrap();
+        entry.last_used = self.frame;
+        Some(entry)
+    }
+
+    fn get_or_draw_track(&mut self, key: u64, track: &track) -> Option<&mut CachedSurface> {
+        if !self.tracks.contains_key(&key) {
+            let map = TrackMap::new(&track.log, &track.style, track.width, track.height)?;
+            let surface = cairo::ImageSurface::create(
+                cairo::Format::ARgb32,
+                track.width.ceil() as i32,
+                track.height.ceil() as i32,
+            )
+            .ok()?;
+            let entry = CachedTrack {
+                map,
+                // nothing drawn yet
+                now: f64::NAN,
+                surface: CachedSurface::new(surface)?,
+            };
+            self.tracks.insert(key, entry);
+        }
+
+        let entry = self.tracks.get_mut(&key).unwrap();
+        let now = track.now.unwrap_or(track.log.start() as f64);
+        if entry.now.to_bits() != now.to_bits() {
+            let map = &entry.map;
+            entry
+                .surface
+                .redraw(|ctx| map.draw(ctx, &track.log, &track.style, now));
+            entry.now = now;
+        }
+
+        entry.surface.last_used = self.frame;
+        Some(&mut entry.surface)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len() + self.tracks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty() && self.tracks.is_empty()
+    }
+}
+
+// position and presentation time are left out, they don't change the pixels
+fn cache_key(element: &OverlayElement) -> u64 {
+    let mut hasher = DefaultHasher::new();
+
+    match element {
+        OverlayElement::Rectangle(rect) => {
+            "rect".hash(&mut hasher);
+            rect.width.to_bits().hash(&mut hasher);
+            rect.height.to_bits().hash(&mut hasher);
+            hash_rgba(rect.rgba, &mut hasher);
+        }
+        OverlayElement::Text(text) => {
+            "text".hash(&mut hasher);
+            text.content.hash(&mut hasher);
+            hash_rgba(text.rgba, &mut hasher);
+        }
+        // resolved to text before drawing, see `OverlayElement::at`
+        OverlayElement::Telemetry(_) => "telemetry".hash(&mut hasher),
+        OverlayElement::Track(track) => {
+            // samples are not hashed one by one, the first, last and count tell logs
+            // apart. The time drawn is left out, the cache redraws the travelled part.
+            "track".hash(&mut hasher);
+            track.width.to_bits().hash(&mut hasher);
+            track.height.to_bits().hash(&mut hasher);
+            track.style.projection.hash(&mut hasher);
+            track.style.north.hash(&mut hasher);
+            hash_rgba(track.style.line, &mut hasher);
+            hash_rgba(track.style.marker, &mut hasher);
+            hash_rgba(track.style.background, &mut hasher);
+            track.log.samples().len().hash(&mut hasher);
+            track.log.start().hash(&mut hasher);
+            track.log.end().hash(&mut hasher);
+        }
+        OverlayElement::Gauge(gauge) => {
+            "gauge".hash(&mut hasher);
+            gauge.width.to_bits().hash(&mut hasher);
+            gauge.height.to_bits().hash(&mut hasher);
+            if let GaugeSource::Live { value } = gauge.source {
+                let value = value.map(|value| gauge.style.quantise(value));
+                value.map(f64::to_bits).hash(&mut hasher);
+            }
+            let style = &gauge.style;
+            style.kind.hash(&mut hasher);
+            style.min.to_bits().hash(&mut hasher);
+            style.max.to_bits().hash(&mut hasher);
+            style.ticks.hash(&mut hasher);
+            style.label.hash(&mut hasher);
+            style.unit.hash(&mut hasher);
+            hash_rgba(style.face, &mut hasher);
+            hash_rgba(style.needle, &mut hasher);
+            hash_rgba(style.text, &mut hasher);
+        }
+    }
+
+    hasher.finish()
+}
+
+fn hash_rgba(rgba: (f64, f64, f64, f64), hasher: &mut DefaultHasher) {
+    rgba.0.to_bits().hash(hasher);
+    rgba.1.to_bits().hash(hasher);
+    rgba.2.to_bits().hash(hasher);
+    rgba.3.to_bits().hash(hasher);
+}
+
+fn render(layout: &pango::Layout, element: &OverlayElement) -> Option<cairo::ImageSurface> {
+    let (width, height) = match element {
+        OverlayElement::Rectangle(rect) => (rect.width.ceil() as i32, rect.height.ceil() as i32),
+        OverlayElement::Text(text) => {
+            layout.set_text(text.content.as_str());
+            layout.get_pixel_size()
+        }
+        OverlayElement::Telemetry(_) => return None,
+        OverlayElement::Track(track) => (track.width.ceil() as i32, track.height.ceil() as i32),
+        OverlayElement::Gauge(gauge) => (gauge.width.ceil() as i32, gauge.height.ceil() as i32),
+    };
+
+    if width <= 0 || height <= 0 {
+        return None;
+    }
+
+    let surface = cairo::ImageSurface::create(cairo::Format::ARgb32, width, height).ok()?;
+    {
+        let ctx = cairo::Context::new(&surface);
+        match element {
+            OverlayElement::Rectangle(rect) => {
+                ctx.set_source_rgba(rect.rgba.0, rect.rgba.1, rect.rgba.2, rect.rgba.3);
+                ctx.rectangle(0., 0., rect.width, rect.height);
+                ctx.fill();
+            }
+            OverlayElement::Text(text) => {
+                ctx.set_source_rgba(text.rgba.0, text.rgba.1, text.rgba.2, text.rgba.3);
+                pangocairo::functions::show_layout(&ctx, layout);
+            }
+            OverlayElement::Telemetry(_) => (),
+            OverlayElement::Track(track) => {
+                let now = track.now.unwrap_or(track.log.start() as f64);
+                let map = TrackMap::new(&track.log, &track.style, track.width, track.height)?;
+                map.draw(&ctx, &track.log, &track.style, now);
+            }
+            OverlayElement::Gauge(gauge) => {
+                // log bound gauges are drawn once resolved, see `OverlayElement::at`
+                let value = match gauge.source {
+                    GaugeSource::Live { value } => value.map(|value| gauge.style.quantise(value)),
+                    GaugeSource::Log { .. } => None,
+                };
+                draw_gauge(&ctx, &gauge.style, value, gauge.width, gauge.height);
+            }
+        }
+    }
+    surface.flush();
+
+    Some(surface)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{new_layout, GpsLog, GpsSample, MapStyle};
+
+    fn text(content: &str, x: f64, rgba: (f64, f64, f64, f64)) -> OverlayElement {
+        OverlayElement::create_text(content.to_string(), x, 0., rgba, (0., 10.))
+    }
+
+    #[test]
+    fn key_ignores_position_and_time() {
+        let white = (1., 1., 1., 1.);
+        let moved = OverlayElement::create_text("a".to_string(), 50., 60., white, (5., 6.));
+
+        assert_eq!(cache_key(&text("a", 0., white)), cache_key(&moved));
+    }
+
+    #[test]
+    fn key_covers_content_colour_and_kind() {
+        let white = (1., 1., 1., 1.);
+        let key = cache_key(&text("a", 0., white));
+
+        assert_ne!(key, cache_key(&text("b", 0., white)));
+        assert_ne!(key, cache_key(&text("a", 0., (1., 0., 0., 1.))));
+
+        let rect = |width| OverlayElement::create_rectangle(0., 0., width, 10., white, (0., 1.));
+        assert_ne!(key, cache_key(&rect(10.)));
+        assert_ne!(cache_key(&rect(10.)), cache_key(&rect(20.)));
+    }
+
+    #[test]
+    fn surfaces_are_reused_and_evicted_when_idle() {
+        let layout = new_layout();
+        let mut cache = RenderCache::new();
+        let rect = OverlayElement::create_rectangle(0., 0., 10., 10., (1., 0., 0., 1.), (0., 1.));
+
+        cache.begin_frame();
+        assert!(cache.get_or_render(&layout, &rect).is_some());
+        assert!(cache.get_or_render(&layout, &rect).is_some());
+        assert_eq!(cache.len(), 1);
+
+        for _ in 0..MAX_IDLE_FRAMES {
+            cache.begin_frame();
+        }
+        assert_eq!(cache.len(), 1);
+
+        // used again, so it stays for another MAX_IDLE_FRAMES
+        cache.get_or_render(&layout, &rect);
+        cache.begin_frame();
+        assert_eq!(cache.len(), 1);
+
+        for _ in 0..=MAX_IDLE_FRAMES {
+            cache.begin_frame();
+        }
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn renders_without_caching() {
+        let layout = new_layout();
+        let rect = OverlayElement::create_rectangle(0., 0., 10.5, 4., (1., 0., 0., 1.), (0., 1.));
+
+        let rendered = CachedSurface::render(&layout, &rect).unwrap();
+        assert_eq!((rendered.width, rendered.height), (11, 4));
+    }
+
+    #[test]
+    fn tracks_keep_one_surface_as_time_goes_on() {
+        let sample = |log_time, lat| GpsSample {
+            log_time,
+            lat,
+            lon: 8.,
+            alt: 0.,
+            speed: 0.,
+            speed_accu: 0.,
+            error: 0.,
+        };
+        let log = GpsLog::new(vec![sample(1000, 50.), sample(2000, 50.01)]).unwrap();
+        let map = MapStyle::default();
+        let element = OverlayElement::create_track(log, map, 0, 0., 0., 100., 80., (0., 10.));
+
+        let early = element.at(gst::ClockTime::from_mseconds(100)).unwrap();
+        let late = element.at(gst::ClockTime::from_mseconds(900)).unwrap();
+        assert_eq!(cache_key(&early), cache_key(&late));
+
+        let layout = new_layout();
+        let mut cache = RenderCache::new();
+        cache.begin_frame();
+        let size = |surface: &mut CachedSurface| (surface.width, surface.height);
+        assert_eq!(
+            cache.get_or_render(&layout, &early).map(size),
+            Some((100, 80))
+        );
+        assert_eq!(
+            cache.get_or_render(&layout, &late).map(size),
+            Some((100, 80))
+        );
+        assert_eq!(cache.len(), 1);
+
+        for _ in 0..=MAX_IDLE_FRAMES {
+            cache.begin_frame();
+        }
+        assert!(cache.is_empty());
+    }
+}
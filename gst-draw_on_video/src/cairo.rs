@@ -1,29 +1,23 @@
 use gst::prelude::*;
-use std::ops;
+
+use vid_overlay::{OverlayElement, PipelineRunner, VideoContext};
 
 const FRAME_WIDTH: i32 = 1920;
 const FRAME_HEIGHT: i32 = 1080;
 
-#[derive(Debug)]
-struct LayoutWrapper(pango::Layout);
-
-impl ops::Deref for LayoutWrapper {
-    type Target = pango::Layout;
-
-    fn deref(&self) -> &pango::Layout {
-        &self.0
-    }
-}
-
-unsafe impl glib::SendUnique for LayoutWrapper {
-    fn is_unique(&self) -> bool {
-        self.0.ref_count() == 1
-    }
-}
-
 fn example_main() {
     gst::init().unwrap();
 
+    // drawing works on packed RGB and on I420/NV12/YUY2 without a conversion, e.g. `cairo NV12`
+    let format = std::env::args()
+        .nth(1)
+        .map(|name| gst_video::VideoFormat::from_string(&name))
+        .unwrap_or(gst_video::VideoFormat::Bgrx);
+    if format == gst_video::VideoFormat::Unknown {
+        eprintln!("Unknown video format");
+        return;
+    }
+
     let src = gst::ElementFactory::make("videotestsrc", Some("src")).unwrap();
     let sink = gst::ElementFactory::make("autovideosink", Some("sink")).unwrap();
     let capsfilter = gst::ElementFactory::make("capsfilter", None).unwrap();
@@ -33,6 +27,7 @@ fn example_main() {
     gst::Element::link_many(&[&src, &capsfilter, &sink]).unwrap();
 
     let caps = gst::Caps::builder("video/x-raw")
+        .field("format", &format.to_str())
         .field("width", &FRAME_WIDTH)
         .field("height", &FRAME_HEIGHT)
         .field("framerate", &gst::Fraction::new(30, 1))
@@ -45,123 +40,33 @@ fn example_main() {
     let src = pipeline.get_by_name("src").unwrap();
     let pad = src.get_static_pad("src").unwrap();
 
-    pad.add_probe(gst::PadProbeType::BUFFER, move |_, probe_info| {
-        if let Some(gst::PadProbeData::Buffer(ref mut buffer)) = probe_info.data {
-            let buffer = buffer.make_mut();
-            let time = buffer.get_pts();
-            // let mut time_str = time.to_string();
-            // let mut time_str = format!("{:.11}", time.to_string());
-            let mut time_str = String::from("");
-            let mut map = buffer.map_writable().unwrap();
-
-            let buf_modified = &mut map.to_vec();
-
-            let mut surface = cairo::ImageSurface::create_for_data(
-                buf_modified.clone(),
-                cairo::Format::Rgb30,
-                FRAME_WIDTH,
-                FRAME_HEIGHT,
-                FRAME_WIDTH * 4,
-            )
-            .unwrap();
-
-            let ctx = cairo::Context::new(&surface);
-            ctx.set_source_rgb(1.0, 1.0, 0.5);
-            ctx.rectangle(1650., 960., 200., 50.);
-            ctx.fill();
-
-            ctx.set_source_rgb(0., 0.0, 0.);
-            ctx.select_font_face("Purisa", cairo::FontSlant::Normal, cairo::FontWeight::Bold);
-            ctx.set_font_size(20.);
-            ctx.move_to(1730., 990.);
-
-            // TODO : use overlay args to emulate if overlays can be shown with cmd line argument
-            let mut overlay_args = vec![
-                (0, 4, false, "FOO"),
-                (4, 6, false, "BAR"),
-                (6, 8, false, "BAZ"),
-                (6, 10, false, "QUX"),
-            ];
-
-            for arg in overlay_args.iter_mut() {
-                if arg.2 == false {
-                    if time < arg.1 * gst::SECOND {
-                        time_str = arg.3.to_string();
-                        break;
-                    } else {
-                        arg.2 = true;
-                    }
-                } else {
-                    continue;
-                }
-            }
-
-            ctx.show_text(&time_str);
-
-            //? EXPERIMENT : test update text with buffer pts
-            // if time > 2 * gst::SECOND {
-            //     time_str = "time's up".to_string();
-            // };
-
-            let mut msg = "HWAT";
-            ctx.save();
-            ctx.move_to(10., 15.);
-            if time > 2 * gst::SECOND {
-                msg = "WHAT";
-            };
-            ctx.show_text(msg);
-
-            drop(ctx);
-
-            let mut raw = surface.get_data().unwrap().to_vec();
-
-            drop(surface);
-
-            map.swap_with_slice(&mut raw);
-
-            // {
-            //     let mut surface =
-            //         cairo::ImageSurface::create(cairo::Format::ARgb32, FRAME_WIDTH, FRAME_HEIGHT)
-            //             .unwrap();
-            //     let ctx = cairo::Context::new(&surface);
-            //     ctx.set_source_rgb(1., 0., 0.);
-            //     ctx.select_font_face(
-            //         "Purisa",
-            //         cairo::FontSlant::Normal,
-            //         cairo::FontWeight::Normal,
-            //     );
-            //     ctx.set_font_size(18.);
-            //     ctx.move_to(400., 590.);
-            //     ctx.show_text("What is love");
-            //     // ctx.rectangle(20., 20., 100., 100.);
-            //     // ctx.fill();
-            //     drop(ctx);
-            //     let stride = surface.get_stride();
-            //     let data = surface.get_data().unwrap().to_vec();
-            //     let data = &data[..];
-            //     let mut target_data = vec![0; (640 * 4 * 640)];
-            //     target_data.copy_from_slice(data);
-            //     map.swap_with_slice(&mut target_data);
-            // }
-        };
-
-        gst::PadProbeReturn::Ok
-    });
-
-    pipeline.set_state(gst::State::Playing).unwrap();
+    // overlays change with the buffer timestamps, no element is added for them
+    let mut overlay_args = vec![
+        OverlayElement::create_rectangle(1650., 960., 200., 50., (1.0, 1.0, 0.5, 1.), (0., 10.)),
+        OverlayElement::create_text("HWAT".to_string(), 10., 15., (1.0, 1.0, 1.0, 1.), (0., 2.)),
+        OverlayElement::create_text("WHAT".to_string(), 10., 15., (1.0, 1.0, 1.0, 1.), (2., 10.)),
+    ];
+    for (start, end, msg) in &[
+        (0., 4., "FOO"),
+        (4., 6., "BAR"),
+        (6., 8., "BAZ"),
+        (8., 10., "QUX"),
+    ] {
+        overlay_args.push(OverlayElement::create_text(
+            msg.to_string(),
+            1730.,
+            970.,
+            (0., 0., 0., 1.),
+            (*start, *end),
+        ));
+    }
 
-    let bus = pipeline.get_bus().unwrap();
-    for msg in bus.iter_timed(gst::CLOCK_TIME_NONE) {
-        use gst::MessageView;
+    VideoContext::draw_on_pad(&pad, overlay_args);
 
-        match msg.view() {
-            MessageView::Eos(_) => break,
-            MessageView::Error(_) => break,
-            _ => (),
-        }
+    match PipelineRunner::new(pipeline).run() {
+        Ok(report) => report.warnings.iter().for_each(|w| eprintln!("{}", w)),
+        Err(e) => eprintln!("Error! {}", e),
     }
-
-    pipeline.set_state(gst::State::Null).unwrap();
 }
 
 fn main() {
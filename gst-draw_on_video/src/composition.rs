@@ -0,0 +1,91 @@
+use std::sync::{Arc, Mutex};
+
+use gst::prelude::*;
+
+use crate::{new_layout, CachedSurface, LayoutWrapper, OverlayElement, RenderCache, Scene};
+
+struct CompositionContext {
+    layout: glib::SendUniqueCell<LayoutWrapper>,
+    info: Option<gst_video::VideoInfo>,
+    cache: RenderCache,
+}
+
+pub(crate) fn compose_on(overlay: gst::Element, scene: Scene) {
+    let layout = new_layout();
+
+    let composer = Arc::new(Mutex::new(CompositionContext {
+        layout: glib::SendUniqueCell::new(layout).unwrap(),
+        info: None,
+        cache: RenderCache::new(),
+    }));
+
+    let composer_clone = composer.clone();
+    overlay
+        .connect("draw", false, move |args| {
+            let mut composer = composer_clone.lock().unwrap();
+            let composer = &mut *composer;
+            let layout = composer.layout.borrow();
+            composer.cache.begin_frame();
+
+            let sample = args[1].get::<gst::Sample>().unwrap().unwrap();
+            let timestamp = sample.get_buffer().unwrap().get_pts();
+
+            let info = match composer.info {
+                Some(ref info) => info.clone(),
+                None => return None,
+            };
+
+            let mut rectangles = Vec::new();
+
+            // running clock in the bottom right corner, new text every frame so
+            // it is not worth caching
+            let timestamp_str = format!("{:.11}", timestamp.to_string());
+            let clock = OverlayElement::create_text(
+                timestamp_str,
+                f64::from(info.width()) - 120.,
+                f64::from(info.height()) - 110.,
+                (1.0, 1.0, 1.0, 1.),
+                (0., 0.),
+            );
+            let (x, y) = clock.position();
+            if let Some(rectangle) = CachedSurface::render(&layout, &clock)
+                .and_then(|mut rendered| rendered.to_rectangle(x, y))
+            {
+                rectangles.push(rectangle);
+            }
+
+            let elements = scene.lock();
+            for ele in elements.iter().filter_map(|ele| ele.at(timestamp)) {
+                let (x, y) = ele.position();
+                if let Some(rectangle) = composer
+                    .cache
+                    .get_or_render(&layout, &ele)
+                    .and_then(|cached| cached.to_rectangle(x, y))
+                {
+                    rectangles.push(rectangle);
+                }
+            }
+
+            if rectangles.is_empty() {
+                return None;
+            }
+
+            Some(
+                gst_video::VideoOverlayComposition::new(&rectangles)
+                    .unwrap()
+                    .to_value(),
+            )
+        })
+        .unwrap();
+
+    overlay
+        .connect("caps-changed", false, move |args| {
+            let caps = args[1].get::<gst::Caps>().unwrap().unwrap();
+
+            let mut composer = composer.lock().unwrap();
+            composer.info = Some(gst_video::VideoInfo::from_caps(&caps).unwrap());
+
+            None
+        })
+        .unwrap();
+}
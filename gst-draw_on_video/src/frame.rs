@@ -0,0 +1,218 @@
+use anyhow::Error;
+use derive_more::{Display, Error};
+
+#[derive(Debug, Display, Error)]
+#[display(fmt = "Unsupported video format {:?}", _0)]
+pub struct UnsupportedFormat(#[error(not(source))] pub gst_video::VideoFormat);
+
+#[derive(Debug, Display, Error)]
+#[display(fmt = "Unable to map video frame writable")]
+pub struct FrameMapError;
+
+// cairo formats are native endian words, so the matching byte order depends on the machine
+pub fn cairo_format(format: gst_video::VideoFormat) -> Option<cairo::Format> {
+    use gst_video::VideoFormat;
+
+    match format {
+        #[cfg(target_endian = "little")]
+        VideoFormat::Bgra => Some(cairo::Format::ARgb32),
+        #[cfg(target_endian = "little")]
+        VideoFormat::Bgrx => Some(cairo::Format::Rgb24),
+        #[cfg(target_endian = "big")]
+        VideoFormat::Argb => Some(cairo::Format::ARgb32),
+        #[cfg(target_endian = "big")]
+        VideoFormat::Xrgb => Some(cairo::Format::Rgb24),
+        VideoFormat::Rgb16 => Some(cairo::Format::Rgb16_565),
+        _ => None,
+    }
+}
+
+// A video frame mapped in place as a cairo surface. Drawing goes straight into
+// the buffer memory, the surface is finished and the frame unmapped on drop, so
+// surfaces or contexts that outlive it can no longer touch the buffer.
+pub struct CairoFrame<'a> {
+    surface: cairo::ImageSurface,
+    frame: gst_video::VideoFrameRef<&'a mut gst::BufferRef>,
+}
+
+impl<'a> CairoFrame<'a> {
+    pub fn map(
+        buffer: &'a mut gst::BufferRef,
+        info: &gst_video::VideoInfo,
+    ) -> Result<CairoFrame<'a>, Error> {
+        let format = cairo_format(info.format()).ok_or(UnsupportedFormat(info.format()))?;
+
+        let mut frame = gst_video::VideoFrameRef::from_buffer_ref_writable(buffer, info)
+            .map_err(|_| FrameMapError)?;
+
+        let width = frame.width() as i32;
+        let height = frame.height() as i32;
+        let stride = frame.plane_stride()[0];
+        let data = frame.plane_data_mut(0)?;
+
+        if data.len() < (stride * height) as usize {
+            return Err(FrameMapError.into());
+        }
+
+        // the frame stays mapped for as long as the surface can be drawn on, see Drop
+        let surface = unsafe {
+            cairo::ImageSurface::from_raw_full(cairo_sys::cairo_image_surface_create_for_data(
+                data.as_mut_ptr(),
+                format.into(),
+                width,
+                height,
+                stride,
+            ))?
+        };
+
+        Ok(CairoFrame { surface, frame })
+    }
+
+    pub fn surface(&self) -> &cairo::ImageSurface {
+        &self.surface
+    }
+
+    pub fn context(&self) -> cairo::Context {
+        cairo::Context::new(&self.surface)
+    }
+
+    pub fn info(&self) -> &gst_video::VideoInfo {
+        self.frame.info()
+    }
+
+    pub fn width(&self) -> u32 {
+        self.frame.width()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.frame.height()
+    }
+}
+
+impl<'a> Drop for CairoFrame<'a> {
+    fn drop(&mut self) {
+        self.surface.flush();
+        self.surface.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use gst_video::VideoFormat;
+
+    fn frame_buffer(
+        format: VideoFormat,
+        width: u32,
+        height: u32,
+    ) -> (gst::Buffer, gst_video::VideoInfo) {
+        gst::init().unwrap();
+        let info = gst_video::VideoInfo::builder(format, width, height)
+            .build()
+            .unwrap();
+        // zeroed, so whatever was drawn stands out
+        let buffer = gst::Buffer::from_mut_slice(vec![0u8; info.size()]);
+        (buffer, info)
+    }
+
+    #[test]
+    fn formats_map_to_native_endian_cairo_formats() {
+        #[cfg(target_endian = "little")]
+        {
+            assert_eq!(cairo_format(VideoFormat::Bgra), Some(cairo::Format::ARgb32));
+            assert_eq!(cairo_format(VideoFormat::Bgrx), Some(cairo::Format::Rgb24));
+            assert_eq!(cairo_format(VideoFormat::Argb), None);
+        }
+        #[cfg(target_endian = "big")]
+        {
+            assert_eq!(cairo_format(VideoFormat::Argb), Some(cairo::Format::ARgb32));
+            assert_eq!(cairo_format(VideoFormat::Xrgb), Some(cairo::Format::Rgb24));
+            assert_eq!(cairo_format(VideoFormat::Bgra), None);
+        }
+        assert_eq!(
+            cairo_format(VideoFormat::Rgb16),
+            Some(cairo::Format::Rgb16_565)
+        );
+        assert_eq!(cairo_format(VideoFormat::I420), None);
+    }
+
+    #[test]
+    #[cfg(target_endian = "little")]
+    fn drawing_lands_in_the_buffer() {
+        let (mut buffer, info) = frame_buffer(VideoFormat::Bgra, 3, 2);
+        let stride = info.stride()[0] as usize;
+        {
+            let frame = CairoFrame::map(buffer.get_mut().unwrap(), &info).unwrap();
+            assert_eq!((frame.width(), frame.height()), (3, 2));
+
+            let ctx = frame.context();
+            ctx.set_source_rgba(1., 0., 0., 1.);
+            ctx.rectangle(1., 1., 1., 1.);
+            ctx.fill();
+        }
+
+        let map = buffer.map_readable().unwrap();
+        let red = stride + 4;
+        assert_eq!(&map[red..red + 4], &[0, 0, 255, 255]);
+        assert!(map[..red].iter().chain(&map[red + 4..]).all(|&b| b == 0));
+    }
+
+    #[test]
+    fn rows_follow_the_stride() {
+        // 3 pixels of 2 bytes, rows padded to 8 bytes
+        let (mut buffer, info) = frame_buffer(VideoFormat::Rgb16, 3, 2);
+        let stride = info.stride()[0] as usize;
+        assert_eq!(stride, 8);
+        {
+            let frame = CairoFrame::map(buffer.get_mut().unwrap(), &info).unwrap();
+            let ctx = frame.context();
+            ctx.set_source_rgb(1., 1., 1.);
+            ctx.rectangle(0., 1., 1., 1.);
+            ctx.fill();
+        }
+
+        let map = buffer.map_readable().unwrap();
+        let white = u16::from_ne_bytes([map[stride], map[stride + 1]]);
+        assert_eq!(white, 0xffff);
+        assert!(map[..stride].iter().all(|&b| b == 0));
+        assert!(map[stride + 2..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn short_buffers_are_refused() {
+        let (_, info) = frame_buffer(VideoFormat::Bgrx, 4, 4);
+        let mut short = gst::Buffer::from_mut_slice(vec![0u8; info.size() - 1]);
+
+        let err = CairoFrame::map(short.get_mut().unwrap(), &info)
+            .err()
+            .unwrap();
+        assert!(err.downcast_ref::<FrameMapError>().is_some());
+    }
+
+    #[test]
+    fn unsupported_formats_are_refused() {
+        let (mut buffer, info) = frame_buffer(VideoFormat::I420, 4, 4);
+
+        let err = CairoFrame::map(buffer.get_mut().unwrap(), &info)
+            .err()
+            .unwrap();
+        let err = err.downcast_ref::<UnsupportedFormat>().unwrap();
+        assert_eq!(err.0, VideoFormat::I420);
+    }
+
+    #[test]
+    fn contexts_outliving_the_frame_draw_nothing() {
+        let (mut buffer, info) = frame_buffer(VideoFormat::Bgrx, 4, 4);
+
+        let frame = CairoFrame::map(buffer.get_mut().unwrap(), &info).unwrap();
+        let ctx = frame.context();
+        drop(frame);
+
+        ctx.set_source_rgb(1., 1., 1.);
+        ctx.paint();
+
+        let map = buffer.map_readable().unwrap();
+        assert!(map.iter().all(|&b| b == 0));
+    }
+}
@@ -0,0 +1,433 @@
+use std::f64::consts::PI;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{DistanceUnit, GpsLog, GpsSample, SpeedUnit, Units};
+
+// the dial sweeps 270 degrees, open at the bottom
+const DIAL_START: f64 = 0.75 * PI;
+const DIAL_SWEEP: f64 = 1.5 * PI;
+
+// how finely a gauge tells values apart, as parts of its range
+const STEPS: f64 = 400.;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum GaugeKind {
+    Dial,
+    // horizontal when wider than tall, filling upwards otherwise
+    Bar,
+}
+
+// what a gauge shows out of a GPS sample
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Channel {
+    Speed,
+    Altitude,
+    Error,
+    SpeedAccuracy,
+}
+
+impl Channel {
+    pub fn value(self, log: &GpsLog, sample: &GpsSample, units: Units) -> f64 {
+        match self {
+            Channel::Speed => log.speed_unit().convert(sample.speed, units.speed),
+            Channel::SpeedAccuracy => log.speed_unit().convert(sample.speed_accu, units.speed),
+            Channel::Altitude => units.distance.convert(sample.alt),
+            Channel::Error => units.distance.convert(sample.error),
+        }
+    }
+
+    pub fn unit(self, units: Units) -> &'static str {
+        match self {
+            Channel::Speed | Channel::SpeedAccuracy => units.speed.suffix(),
+            Channel::Altitude | Channel::Error => units.distance.suffix(),
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Channel::Speed => "SPEED",
+            Channel::SpeedAccuracy => "SPEED ±",
+            Channel::Altitude => "ALT",
+            Channel::Error => "ERROR",
+        }
+    }
+}
+
+// Where the value of a gauge comes from: a channel of a GPS log looked up at the
+// frame's time, or whatever was last set on the element by a live feed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum GaugeSource {
+    Log {
+        log: GpsLog,
+        channel: Channel,
+        units: Units,
+        // stream time of the first sample in milliseconds
+        offset_ms: i64,
+    },
+    Live {
+        value: Option<f64>,
+    },
+}
+
+impl GaugeSource {
+    pub(crate) fn value_at(&self, stream_ms: f64) -> Option<f64> {
+        match self {
+            GaugeSource::Log {
+                log,
+                channel,
+                units,
+                offset_ms,
+            } => log
+                .sample_at_stream_time(stream_ms, *offset_ms)
+                .map(|sample| channel.value(log, &sample, *units)),
+            GaugeSource::Live { value } => *value,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GaugeStyle {
+    pub kind: GaugeKind,
+    pub min: f64,
+    pub max: f64,
+    // numbered divisions between min and max
+    pub ticks: u32,
+    pub label: String,
+    pub unit: String,
+    pub face: (f64, f64, f64, f64),
+    pub needle: (f64, f64, f64, f64),
+    pub text: (f64, f64, f64, f64),
+}
+
+impl GaugeStyle {
+    pub fn new(kind: GaugeKind, min: f64, max: f64, label: &str, unit: &str) -> Self {
+        GaugeStyle {
+            kind,
+            min,
+            max,
+            ticks: 5,
+            label: label.to_string(),
+            unit: unit.to_string(),
+            face: (0., 0., 0., 0.6),
+            needle: (1.0, 0.3, 0.2, 1.),
+            text: (1., 1., 1., 1.),
+        }
+    }
+
+    // a range that suits the channel in the usual units
+    pub fn for_channel(kind: GaugeKind, channel: Channel, units: Units) -> Self {
+        let feet = units.distance == DistanceUnit::Feet;
+        let max = match channel {
+            Channel::Speed => SpeedUnit::KilometersPerHour.convert(200., units.speed),
+            Channel::SpeedAccuracy => SpeedUnit::KilometersPerHour.convert(10., units.speed),
+            Channel::Altitude if feet => 10_000.,
+            Channel::Altitude => 3000.,
+            Channel::Error if feet => 100.,
+            Channel::Error => 30.,
+        };
+
+        GaugeStyle::new(kind, 0., max.round(), channel.label(), channel.unit(units))
+    }
+
+    // 0 at min to 1 at max, clamped
+    fn fraction(&self, value: f64) -> f64 {
+        if self.max <= self.min {
+            return 0.;
+        }
+
+        ((value - self.min) / (self.max - self.min)).clamp(0., 1.)
+    }
+
+    fn tick_value(&self, tick: u32) -> f64 {
+        self.min + (self.max - self.min) * f64::from(tick) / f64::from(self.ticks.max(1))
+    }
+
+    // `value` on the nearest step the gauge shows, the finer of a STEPS-th of the
+    // range and the digits printed. Values closer than that draw the same pixels
+    // and share a cached surface.
+    pub(crate) fn quantise(&self, value: f64) -> f64 {
+        let printed = if value.abs() < 10. { 0.1 } else { 1. };
+        let step = ((self.max - self.min).abs() / STEPS).min(printed);
+
+        if step > 0. {
+            (value / step).round() * step
+        } else {
+            value
+        }
+    }
+}
+
+fn set_rgba(ctx: &cairo::Context, rgba: (f64, f64, f64, f64)) {
+    ctx.set_source_rgba(rgba.0, rgba.1, rgba.2, rgba.3);
+}
+
+// text centred on (x, y)
+fn centred_text(ctx: &cairo::Context, text: &str, x: f64, y: f64, size: f64) {
+    ctx.set_font_size(size);
+    let extents = ctx.text_extents(text);
+    ctx.move_to(
+        x - extents.width / 2. - extents.x_bearing,
+        y - extents.height / 2. - extents.y_bearing,
+    );
+    ctx.show_text(text);
+}
+
+fn format_value(value: Option<f64>) -> String {
+    match value {
+        Some(value) if value.abs() < 10. => format!("{:.1}", value),
+        Some(value) => format!("{:.0}", value),
+        None => "--".to_string(),
+    }
+}
+
+fn draw_dial(
+    ctx: &cairo::Context,
+    style: &GaugeStyle,
+    value: Option<f64>,
+    width: f64,
+    height: f64,
+) {
+    let (cx, cy) = (width / 2., height / 2.);
+    let radius = width.min(height) / 2. - 2.;
+    let angle = |fraction: f64| DIAL_START + DIAL_SWEEP * fraction;
+
+    set_rgba(ctx, style.face);
+    ctx.arc(cx, cy, radius, 0., 2. * PI);
+    ctx.fill();
+
+    // numbered ticks with an unnumbered one halfway between them
+    set_rgba(ctx, style.text);
+    ctx.set_line_cap(cairo::LineCap::Round);
+    let ticks = style.ticks.max(1);
+    for half_tick in 0..=ticks * 2 {
+        let major = half_tick % 2 == 0;
+        let a = angle(f64::from(half_tick) / f64::from(ticks * 2));
+        let inner = if major { 0.78 } else { 0.86 };
+
+        ctx.set_line_width(if major { 2. } else { 1. });
+        ctx.move_to(cx + a.cos() * radius * inner, cy + a.sin() * radius * inner);
+        ctx.line_to(cx + a.cos() * radius * 0.94, cy + a.sin() * radius * 0.94);
+        ctx.stroke();
+
+        if major {
+            let label = format!("{:.0}", style.tick_value(half_tick / 2));
+            let r = radius * 0.62;
+            centred_text(
+                ctx,
+                &label,
+                cx + a.cos() * r,
+                cy + a.sin() * r,
+                radius * 0.13,
+            );
+        }
+    }
+
+    centred_text(ctx, &style.label, cx, cy - radius * 0.3, radius * 0.12);
+    centred_text(
+        ctx,
+        &format_value(value),
+        cx,
+        cy + radius * 0.45,
+        radius * 0.24,
+    );
+    centred_text(ctx, &style.unit, cx, cy + radius * 0.7, radius * 0.12);
+
+    if let Some(value) = value {
+        let a = angle(style.fraction(value));
+        set_rgba(ctx, style.needle);
+        ctx.set_line_width((radius * 0.04).max(2.));
+        ctx.move_to(cx, cy);
+        ctx.line_to(cx + a.cos() * radius * 0.85, cy + a.sin() * radius * 0.85);
+        ctx.stroke();
+    }
+
+    set_rgba(ctx, style.needle);
+    ctx.arc(cx, cy, radius * 0.07, 0., 2. * PI);
+    ctx.fill();
+}
+
+fn draw_bar(ctx: &cairo::Context, style: &GaugeStyle, value: Option<f64>, width: f64, height: f64) {
+    let horizontal = width >= height;
+
+    set_rgba(ctx, style.face);
+    ctx.rectangle(0., 0., width, height);
+    ctx.fill();
+
+    // the bar leaves room for the caption on one side and tick labels on the other
+    let (x, y, length, thickness) = if horizontal {
+        (4., height * 0.4, width - 8., height * 0.3)
+    } else {
+        (width * 0.35, height - 4., height * 0.75, width * 0.3)
+    };
+
+    if let Some(value) = value {
+        let filled = length * style.fraction(value);
+        set_rgba(ctx, style.needle);
+        if horizontal {
+            ctx.rectangle(x, y, filled, thickness);
+        } else {
+            ctx.rectangle(x, y - filled, thickness, filled);
+        }
+        ctx.fill();
+    }
+
+    set_rgba(ctx, style.text);
+    ctx.set_line_width(1.);
+    if horizontal {
+        ctx.rectangle(x, y, length, thickness);
+    } else {
+        ctx.rectangle(x, y - length, thickness, length);
+    }
+    ctx.stroke();
+
+    let size = (width.min(height) * 0.2).min(14.);
+    let ticks = style.ticks.max(1);
+    for tick in 0..=ticks {
+        let along = length * f64::from(tick) / f64::from(ticks);
+        let label = format!("{:.0}", style.tick_value(tick));
+        if horizontal {
+            ctx.move_to(x + along, y + thickness);
+            ctx.line_to(x + along, y + thickness + 4.);
+            ctx.stroke();
+            centred_text(
+                ctx,
+                &label,
+                x + along,
+                y + thickness + 6. + size / 2.,
+                size * 0.8,
+            );
+        } else {
+            ctx.move_to(x + thickness, y - along);
+            ctx.line_to(x + thickness + 4., y - along);
+            ctx.stroke();
+            centred_text(
+                ctx,
+                &label,
+                x + thickness + 8. + size,
+                y - along,
+                size * 0.8,
+            );
+        }
+    }
+
+    let caption = format!("{} {} {}", style.label, format_value(value), style.unit);
+    if horizontal {
+        centred_text(ctx, &caption, width / 2., y / 2., size);
+    } else {
+        centred_text(
+            ctx,
+            &caption,
+            width / 2.,
+            (height - length - 4.) / 2.,
+            size * 0.8,
+        );
+    }
+}
+
+// draws a gauge filling a width x height surface
+pub(crate) fn draw_gauge(
+    ctx: &cairo::Context,
+    style: &GaugeStyle,
+    value: Option<f64>,
+    width: f64,
+    height: f64,
+) {
+    ctx.select_font_face("Sans", cairo::FontSlant::Normal, cairo::FontWeight::Bold);
+
+    match style.kind {
+        GaugeKind::Dial => draw_dial(ctx, style, value, width, height),
+        GaugeKind::Bar => draw_bar(ctx, style, value, width, height),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn style(min: f64, max: f64) -> GaugeStyle {
+        GaugeStyle::new(GaugeKind::Dial, min, max, "SPEED", "km/h")
+    }
+
+    #[test]
+    fn fraction_is_clamped_to_the_range() {
+        let style = style(-20., 80.);
+
+        assert_eq!(style.fraction(-20.), 0.);
+        assert_eq!(style.fraction(30.), 0.5);
+        assert_eq!(style.fraction(80.), 1.);
+        assert_eq!(style.fraction(-100.), 0.);
+        assert_eq!(style.fraction(1000.), 1.);
+    }
+
+    #[test]
+    fn empty_range_has_no_fraction() {
+        assert_eq!(style(10., 10.).fraction(10.), 0.);
+        assert_eq!(style(10., 0.).fraction(5.), 0.);
+    }
+
+    #[test]
+    fn ticks_divide_the_range() {
+        let mut style = style(0., 200.);
+
+        assert_eq!(style.tick_value(0), 0.);
+        assert_eq!(style.tick_value(1), 40.);
+        assert_eq!(style.tick_value(5), 200.);
+
+        style.ticks = 0;
+        assert_eq!(style.tick_value(1), 200.);
+    }
+
+    #[test]
+    fn values_are_quantised_to_what_shows() {
+        let style = style(0., 200.);
+
+        // half a km/h on 200, a tenth below 10 where a decimal is printed
+        assert_eq!(style.quantise(120.3), 120.5);
+        assert_eq!(style.quantise(120.2), 120.);
+        assert!((style.quantise(4.26) - 4.3).abs() < 1e-9);
+        assert_eq!(style.quantise(120.2), style.quantise(119.8));
+        assert_ne!(style.quantise(120.), style.quantise(121.));
+
+        let flat = GaugeStyle::new(GaugeKind::Bar, 5., 5., "", "");
+        assert_eq!(flat.quantise(5.123), 5.123);
+    }
+
+    #[test]
+    fn channels_convert_to_the_display_units() {
+        let sample = GpsSample {
+            log_time: 0,
+            lat: 0.,
+            lon: 0.,
+            alt: 304.8,
+            speed: 10.,
+            speed_accu: 1.,
+            error: 3.048,
+        };
+        let log = GpsLog::new(vec![sample]).unwrap();
+        let metric = Units::default();
+        let imperial = Units {
+            speed: SpeedUnit::MilesPerHour,
+            distance: DistanceUnit::Feet,
+        };
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-9;
+
+        // the log is in m/s unless told otherwise
+        assert!(close(Channel::Speed.value(&log, &sample, metric), 36.));
+        assert!(close(
+            Channel::SpeedAccuracy.value(&log, &sample, metric),
+            3.6
+        ));
+        assert!(close(Channel::Altitude.value(&log, &sample, metric), 304.8));
+        assert!(close(
+            Channel::Altitude.value(&log, &sample, imperial),
+            1000.
+        ));
+        assert!(close(Channel::Error.value(&log, &sample, imperial), 10.));
+
+        let knots = log.with_speed_unit(SpeedUnit::Knots);
+        assert!(close(Channel::Speed.value(&knots, &sample, metric), 18.52));
+        assert_eq!(Channel::Speed.unit(imperial), "mph");
+        assert_eq!(Channel::Error.unit(metric), "m");
+    }
+}
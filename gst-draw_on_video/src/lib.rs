@@ -1,28 +1,61 @@
+use std::borrow::Cow;
 use std::ops;
 use std::sync::{Arc, Mutex};
 
 use anyhow::Error;
 use derive_more::{Display, Error};
+use serde::{Deserialize, Serialize};
 
 use gst::prelude::*;
 use pango::prelude::*;
 
+mod cache;
+mod composition;
+mod frame;
+mod gauge;
+mod map;
+mod mqtt;
+mod probe;
+mod remote;
+mod runner;
+mod scene;
+mod snapshot;
+mod status;
+mod subtitle;
+mod telemetry;
+mod webrtc;
+mod yuv;
+
+pub use cache::{CachedSurface, RenderCache, SurfaceWrapper};
+pub use frame::{cairo_format, CairoFrame, FrameMapError, UnsupportedFormat};
+pub use gauge::{Channel, GaugeKind, GaugeSource, GaugeStyle};
+pub use map::{MapStyle, Projection};
+pub use mqtt::{lookup, mqtt_options, Binding, InvalidPort, MqttOverlay};
+pub use remote::{handle_overlay_request, serve_pipeline};
+pub use remote_control::{RemoteRequest, RemoteServer, RpcError};
+pub use rumqttc::MqttOptions;
+pub use scene::Scene;
+pub use snapshot::{last_sample, snapshot, ImageFormat, NoSample};
+pub use status::{sink_stats, StatusReporter, StatusTopics};
+pub use subtitle::{
+    Cue, InvalidTimestamp, SubtitleFormat, SubtitleStyle, Subtitles, UnknownFormat,
+};
+pub use telemetry::{DistanceUnit, EmptyLog, GpsLog, GpsSample, SpeedUnit, Units};
+pub use webrtc::{NoOffer, SignallingError, WebRtcConfig, WebRtcSession};
+pub use yuv::{is_yuv, Overlay, YuvFrame};
+
+pub use runner::{
+    CtrlCError, ErrorMessage, PipelineRunner, RunOutcome, RunReport, RunnerHandle, WarningMessage,
+};
+
 #[derive(Debug, Display, Error)]
 #[display(fmt = "Missing element {}", _0)]
 struct MissingElement(#[error(not(source))] &'static str);
 
-#[derive(Debug, Display, Error)]
-#[display(fmt = "Received error from {}: {} (debug: {:?})", src, error, debug)]
-pub struct ErrorMessage {
-    pub src: String,
-    pub error: String,
-    pub debug: Option<String>,
-    pub source: glib::Error,
-}
-
 pub struct DrawingContext {
     pub layout: glib::SendUniqueCell<LayoutWrapper>,
     pub info: Option<gst_video::VideoInfo>,
+    pub cache: RenderCache,
 }
 
 #[derive(Debug)]
@@ -41,17 +74,18 @@ unsafe impl glib::SendUnique for LayoutWrapper {
         self.0.ref_count() == 1
     }
 }
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
 #[allow(non_camel_case_types)]
 pub struct text {
     content: String,
     x: f64,
     y: f64,
     rgba: (f64, f64, f64, f64),
-    present_time: (u64, u64),
-    pub(crate) rendered: bool,
+    present_time: (f64, f64),
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
 }
-#[derive(Copy, Clone)]
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
 #[allow(non_camel_case_types)]
 pub struct rect {
     x: f64,
@@ -59,14 +93,70 @@ pub struct rect {
     width: f64,
     height: f64,
     rgba: (f64, f64, f64, f64),
-    present_time: (u64, u64),
-    pub(crate) rendered: bool,
+    present_time: (f64, f64),
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+}
+
+// readings of a GPS log at the frame's time, drawn as text
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[allow(non_camel_case_types)]
+pub struct telemetry {
+    log: GpsLog,
+    units: Units,
+    // stream time of the first sample in milliseconds
+    offset_ms: i64,
+    x: f64,
+    y: f64,
+    rgba: (f64, f64, f64, f64),
+    present_time: (f64, f64),
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
 }
 
-#[derive(Clone)]
+// inset map of a GPS log with the position at the frame's time
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[allow(non_camel_case_types)]
+pub struct track {
+    log: GpsLog,
+    style: MapStyle,
+    offset_ms: i64,
+    x: f64,
+    y: f64,
+    width: f64,
+    height: f64,
+    present_time: (f64, f64),
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    // log time drawn, filled in by `OverlayElement::at`
+    #[serde(skip)]
+    now: Option<f64>,
+}
+
+// dial or bar showing a value from a GPS log or a live feed
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[allow(non_camel_case_types)]
+pub struct gauge {
+    source: GaugeSource,
+    style: GaugeStyle,
+    x: f64,
+    y: f64,
+    width: f64,
+    height: f64,
+    present_time: (f64, f64),
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+}
+
+// serialized as {"type": "text", "content": ..., "x": ..., ...}
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
 pub enum OverlayElement {
     Rectangle(rect),
     Text(text),
+    Telemetry(telemetry),
+    Track(track),
+    Gauge(gauge),
 }
 
 impl OverlayElement {
@@ -76,7 +166,7 @@ impl OverlayElement {
         width: f64,
         height: f64,
         rgba: (f64, f64, f64, f64),
-        present_time: (u64, u64),
+        present_time: (f64, f64),
     ) -> Self {
         let rect = rect {
             x,
@@ -85,7 +175,7 @@ impl OverlayElement {
             height,
             rgba,
             present_time,
-            rendered: false,
+            name: None,
         };
         OverlayElement::Rectangle(rect)
     }
@@ -95,7 +185,7 @@ impl OverlayElement {
         x: f64,
         y: f64,
         rgba: (f64, f64, f64, f64),
-        present_time: (u64, u64),
+        present_time: (f64, f64),
     ) -> Self {
         let text = text {
             content,
@@ -103,33 +193,303 @@ impl OverlayElement {
             y,
             rgba,
             present_time,
-            rendered: false,
+            name: None,
         };
         OverlayElement::Text(text)
     }
+
+    pub fn create_telemetry(
+        log: GpsLog,
+        units: Units,
+        offset_ms: i64,
+        x: f64,
+        y: f64,
+        rgba: (f64, f64, f64, f64),
+        present_time: (f64, f64),
+    ) -> Self {
+        let telemetry = telemetry {
+            log,
+            units,
+            offset_ms,
+            x,
+            y,
+            rgba,
+            present_time,
+            name: None,
+        };
+        OverlayElement::Telemetry(telemetry)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_track(
+        log: GpsLog,
+        style: MapStyle,
+        offset_ms: i64,
+        x: f64,
+        y: f64,
+        width: f64,
+        height: f64,
+        present_time: (f64, f64),
+    ) -> Self {
+        let track = track {
+            log,
+            style,
+            offset_ms,
+            x,
+            y,
+            width,
+            height,
+            present_time,
+            now: None,
+            name: None,
+        };
+        OverlayElement::Track(track)
+    }
+
+    pub fn create_gauge(
+        source: GaugeSource,
+        style: GaugeStyle,
+        x: f64,
+        y: f64,
+        width: f64,
+        height: f64,
+        present_time: (f64, f64),
+    ) -> Self {
+        let gauge = gauge {
+            source,
+            style,
+            x,
+            y,
+            width,
+            height,
+            present_time,
+            name: None,
+        };
+        OverlayElement::Gauge(gauge)
+    }
+
+    // names the element so it can be found again, e.g. by an MQTT `Binding`
+    pub fn with_name(mut self, name: &str) -> Self {
+        *self.name_mut() = Some(name.to_string());
+        self
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            OverlayElement::Rectangle(rect) => rect.name.as_deref(),
+            OverlayElement::Text(text) => text.name.as_deref(),
+            OverlayElement::Telemetry(telemetry) => telemetry.name.as_deref(),
+            OverlayElement::Track(track) => track.name.as_deref(),
+            OverlayElement::Gauge(gauge) => gauge.name.as_deref(),
+        }
+    }
+
+    fn name_mut(&mut self) -> &mut Option<String> {
+        match self {
+            OverlayElement::Rectangle(rect) => &mut rect.name,
+            OverlayElement::Text(text) => &mut text.name,
+            OverlayElement::Telemetry(telemetry) => &mut telemetry.name,
+            OverlayElement::Track(track) => &mut track.name,
+            OverlayElement::Gauge(gauge) => &mut gauge.name,
+        }
+    }
+
+    // replaces what a text element says, false for every other element
+    pub fn set_content(&mut self, content: String) -> bool {
+        match self {
+            OverlayElement::Text(text) => {
+                text.content = content;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // feeds a live gauge, false for every other element
+    pub fn set_value(&mut self, value: f64) -> bool {
+        match self {
+            OverlayElement::Gauge(gauge) => match gauge.source {
+                GaugeSource::Live {
+                    value: ref mut current,
+                } => {
+                    *current = Some(value);
+                    true
+                }
+                GaugeSource::Log { .. } => false,
+            },
+            _ => false,
+        }
+    }
+
+    pub fn position(&self) -> (f64, f64) {
+        match self {
+            OverlayElement::Rectangle(rect) => (rect.x, rect.y),
+            OverlayElement::Text(text) => (text.x, text.y),
+            OverlayElement::Telemetry(telemetry) => (telemetry.x, telemetry.y),
+            OverlayElement::Track(track) => (track.x, track.y),
+            OverlayElement::Gauge(gauge) => (gauge.x, gauge.y),
+        }
+    }
+
+    // start and end in seconds
+    pub fn present_time(&self) -> (f64, f64) {
+        match self {
+            OverlayElement::Rectangle(rect) => rect.present_time,
+            OverlayElement::Text(text) => text.present_time,
+            OverlayElement::Telemetry(telemetry) => telemetry.present_time,
+            OverlayElement::Track(track) => track.present_time,
+            OverlayElement::Gauge(gauge) => gauge.present_time,
+        }
+    }
+
+    // what a text element says, None for everything else
+    pub fn content(&self) -> Option<&str> {
+        match self {
+            OverlayElement::Text(text) => Some(&text.content),
+            _ => None,
+        }
+    }
+
+    pub fn is_visible(&self, timestamp: gst::ClockTime) -> bool {
+        let present_time = self.present_time();
+
+        // present_time is in seconds, fractions allowed for subtitle-like timing,
+        // the start included so elements starting at 0 show on the first frame
+        match timestamp.nseconds() {
+            Some(ns) => {
+                let seconds = ns as f64 / 1e9;
+                seconds >= present_time.0 && seconds < present_time.1
+            }
+            None => false,
+        }
+    }
+
+    // The element as drawn at `timestamp`, None when it is not shown. Elements that
+    // change with time resolve to plain ones here so the backends only draw those.
+    pub fn at(&self, timestamp: gst::ClockTime) -> Option<Cow<'_, OverlayElement>> {
+        if !self.is_visible(timestamp) {
+            return None;
+        }
+
+        match self {
+            OverlayElement::Telemetry(telemetry) => {
+                let ms = timestamp.nseconds()? as f64 / 1e6;
+                let sample = telemetry
+                    .log
+                    .sample_at_stream_time(ms, telemetry.offset_ms)?;
+
+                Some(Cow::Owned(OverlayElement::create_text(
+                    telemetry.log.describe(&sample, telemetry.units),
+                    telemetry.x,
+                    telemetry.y,
+                    telemetry.rgba,
+                    telemetry.present_time,
+                )))
+            }
+            OverlayElement::Track(track) => {
+                // the whole track stays up before and after the log
+                let ms = timestamp.nseconds()? as f64 / 1e6;
+                let now = track.log.start() as f64 + ms - track.offset_ms as f64;
+                let now = now.clamp(track.log.start() as f64, track.log.end() as f64);
+
+                let mut track = track.clone();
+                track.now = Some(now);
+                Some(Cow::Owned(OverlayElement::Track(track)))
+            }
+            OverlayElement::Gauge(gauge) => match gauge.source {
+                // only the value is kept, not the log behind it
+                GaugeSource::Log { .. } => {
+                    let ms = timestamp.nseconds()? as f64 / 1e6;
+                    let value = gauge.source.value_at(ms);
+                    Some(Cow::Owned(OverlayElement::create_gauge(
+                        GaugeSource::Live { value },
+                        gauge.style.clone(),
+                        gauge.x,
+                        gauge.y,
+                        gauge.width,
+                        gauge.height,
+                        gauge.present_time,
+                    )))
+                }
+                GaugeSource::Live { .. } => Some(Cow::Borrowed(self)),
+            },
+            _ => Some(Cow::Borrowed(self)),
+        }
+    }
+}
+
+// how overlay elements end up on the video frames
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    // `cairooverlay`, draws into every frame
+    Cairo,
+    // `overlaycomposition`, attaches cached rectangles as meta for the sink to blend
+    Composition,
+}
+
+// where the overlaid video goes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Output {
+    // `autovideosink`
+    Window,
+    // VP8 through a `webrtcbin` named "webrtc", see `WebRtcSession`
+    WebRtc,
 }
 
 pub struct VideoContext {}
 
 impl VideoContext {
     pub fn new(width: i32, height: i32, framerate: i32) -> Result<gst::Pipeline, Error> {
+        VideoContext::with_backend(width, height, framerate, Backend::Cairo)
+    }
+
+    pub fn with_backend(
+        width: i32,
+        height: i32,
+        framerate: i32,
+        backend: Backend,
+    ) -> Result<gst::Pipeline, Error> {
+        VideoContext::with_output(width, height, framerate, backend, Output::Window)
+    }
+
+    pub fn with_output(
+        width: i32,
+        height: i32,
+        framerate: i32,
+        backend: Backend,
+        output: Output,
+    ) -> Result<gst::Pipeline, Error> {
         gst::init()?;
         let pipeline = gst::Pipeline::new(None);
 
         // initiate elements
         let src = gst::ElementFactory::make("videotestsrc", None)
             .map_err(|_| MissingElement("videotestsrc"))?;
-        let overlay = gst::ElementFactory::make("cairooverlay", Some("overlay"))
-            .map_err(|_| MissingElement("cairooverlay"))?;
-        let capsfilter = gst::ElementFactory::make("capsfilter", None)
+        let overlay_factory = match backend {
+            Backend::Cairo => "cairooverlay",
+            Backend::Composition => "overlaycomposition",
+        };
+        let overlay = gst::ElementFactory::make(overlay_factory, Some("overlay"))
+            .map_err(|_| MissingElement(overlay_factory))?;
+        let capsfilter = gst::ElementFactory::make("capsfilter", Some("format"))
             .map_err(|_| MissingElement("capsfilter"))?;
         let videoconvert = gst::ElementFactory::make("videoconvert", None)
             .map_err(|_| MissingElement("videoconvert"))?;
-        let sink = gst::ElementFactory::make("autovideosink", None)
-            .map_err(|_| MissingElement("autovideosink"))?;
 
-        pipeline.add_many(&[&src, &overlay, &capsfilter, &videoconvert, &sink])?;
-        gst::Element::link_many(&[&src, &overlay, &capsfilter, &videoconvert, &sink])?;
+        pipeline.add_many(&[&src, &overlay, &capsfilter, &videoconvert])?;
+        gst::Element::link_many(&[&src, &overlay, &capsfilter, &videoconvert])?;
+
+        match output {
+            Output::Window => {
+                let sink = gst::ElementFactory::make("autovideosink", None)
+                    .map_err(|_| MissingElement("autovideosink"))?;
+                pipeline.add(&sink)?;
+                videoconvert.link(&sink)?;
+            }
+            Output::WebRtc => {
+                webrtc::add_webrtc_sink(&pipeline, &videoconvert)?;
+            }
+        }
 
         let caps = gst::Caps::builder("video/x-raw")
             .field("width", &width)
@@ -142,16 +502,38 @@ impl VideoContext {
         Ok(pipeline)
     }
 
-    pub fn draw_on(overlay: gst::Element, overlay_element: Vec<OverlayElement>) {
-        let fontmap = pangocairo::FontMap::new().unwrap();
-        let context = fontmap.create_context().unwrap();
-        let layout = LayoutWrapper(pango::Layout::new(&context));
-        let font_desc = pango::FontDescription::from_string("Sans Bold 12");
-        layout.set_font_description(Some(&font_desc));
+    // the frame size a pipeline from `with_output` is set to, known before it runs
+    pub fn frame_size(pipeline: &gst::Pipeline) -> Option<(i32, i32)> {
+        let capsfilter = pipeline.get_by_name("format")?;
+        let caps = capsfilter
+            .get_property("caps")
+            .ok()?
+            .get::<gst::Caps>()
+            .ok()??;
+        let structure = caps.get_structure(0)?;
+
+        let width = structure.get_some::<i32>("width").ok()?;
+        let height = structure.get_some::<i32>("height").ok()?;
+        Some((width, height))
+    }
+
+    // `scene` can be a plain Vec, or a `Scene` kept around to change the elements later
+    pub fn draw_on<S: Into<Scene>>(overlay: gst::Element, scene: S) {
+        let scene = scene.into();
+        let is_composition = overlay
+            .get_factory()
+            .map(|f| f.get_name() == "overlaycomposition")
+            .unwrap_or(false);
+        if is_composition {
+            return composition::compose_on(overlay, scene);
+        }
+
+        let layout = new_layout();
 
         let drawer = Arc::new(Mutex::new(DrawingContext {
             layout: glib::SendUniqueCell::new(layout).unwrap(),
             info: None,
+            cache: RenderCache::new(),
         }));
 
         // let draw_args = Box::new(overlay_element);
@@ -160,12 +542,13 @@ impl VideoContext {
         let drawer_clone = drawer.clone();
         overlay
             .connect("draw", false, move |args| {
-                let drawer = &drawer_clone;
-                let drawer = drawer.lock().unwrap();
+                let mut drawer = drawer_clone.lock().unwrap();
+                let drawer = &mut *drawer;
 
                 let timestamp = args[2].get_some::<gst::ClockTime>().unwrap();
                 let ctx = args[1].get::<cairo::Context>().unwrap().unwrap();
                 let layout = drawer.layout.borrow();
+                drawer.cache.begin_frame();
 
                 // create an empty string as a placeholder for render time comparison
                 // let mut time_str = String::from("");
@@ -181,53 +564,8 @@ impl VideoContext {
                 layout.set_text(&timestamp_str);
                 pangocairo::functions::show_layout(&ctx, &**layout);
 
-                let draw_args = overlay_element.clone();
-
-                for ele in draw_args.into_iter() {
-                    match ele {
-                        OverlayElement::Rectangle(mut rect) => {
-                            if !rect.rendered {
-                                if (timestamp < rect.present_time.1 * gst::SECOND)
-                                    && (timestamp > rect.present_time.0 * gst::SECOND)
-                                {
-                                    ctx.set_source_rgba(
-                                        rect.rgba.0,
-                                        rect.rgba.1,
-                                        rect.rgba.2,
-                                        rect.rgba.3,
-                                    );
-                                    ctx.rectangle(rect.x, rect.y, rect.width, rect.height);
-                                    ctx.fill();
-                                } else {
-                                    rect.rendered = true;
-                                }
-                            } else {
-                                continue;
-                            }
-                        }
-                        OverlayElement::Text(mut text) => {
-                            if !text.rendered {
-                                if (timestamp < text.present_time.1 * gst::SECOND)
-                                    && (timestamp > text.present_time.0 * gst::SECOND)
-                                {
-                                    ctx.move_to(text.x, text.y);
-                                    ctx.set_source_rgba(
-                                        text.rgba.0,
-                                        text.rgba.1,
-                                        text.rgba.2,
-                                        text.rgba.3,
-                                    );
-                                    layout.set_text(text.content.as_str());
-                                    pangocairo::functions::show_layout(&ctx, &**layout);
-                                } else {
-                                    text.rendered = true;
-                                }
-                            } else {
-                                continue;
-                            }
-                        }
-                    }
-                }
+                draw_scene(&ctx, &layout, &mut drawer.cache, &scene.lock(), timestamp);
+
                 None
             })
             .unwrap();
@@ -244,4 +582,76 @@ impl VideoContext {
             })
             .unwrap();
     }
+    // annotates any pad of any pipeline, see `probe::probe_on`
+    pub fn draw_on_pad<S: Into<Scene>>(pad: &gst::Pad, scene: S) -> Option<gst::PadProbeId> {
+        probe::probe_on(pad, scene.into())
+    }
+}
+
+pub(crate) fn new_layout() -> LayoutWrapper {
+    let fontmap = pangocairo::FontMap::new().unwrap();
+    let context = fontmap.create_context().unwrap();
+    let layout = LayoutWrapper(pango::Layout::new(&context));
+    let font_desc = pango::FontDescription::from_string("Sans Bold 12");
+    layout.set_font_description(Some(&font_desc));
+
+    layout
+}
+
+// draws the elements visible at `timestamp`, shared by every cairo based backend
+pub(crate) fn draw_scene(
+    ctx: &cairo::Context,
+    layout: &pango::Layout,
+    cache: &mut RenderCache,
+    overlay_element: &[OverlayElement],
+    timestamp: gst::ClockTime,
+) {
+    for ele in overlay_element.iter() {
+        let ele = match ele.at(timestamp) {
+            Some(ele) => ele,
+            None => continue,
+        };
+
+        match *ele {
+            OverlayElement::Rectangle(ref rect) => {
+                ctx.set_source_rgba(rect.rgba.0, rect.rgba.1, rect.rgba.2, rect.rgba.3);
+                ctx.rectangle(rect.x, rect.y, rect.width, rect.height);
+                ctx.fill();
+            }
+            _ => {
+                // text is laid out once and blitted from the cache afterwards
+                let (x, y) = ele.position();
+                if let Some(cached) = cache.get_or_render(layout, &ele) {
+                    cached.paint(ctx, x, y);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn visible_from_start_until_before_end() {
+        let ele = OverlayElement::create_text("hi".to_string(), 0., 0., (1., 1., 1., 1.), (0., 2.));
+
+        assert!(ele.is_visible(gst::ClockTime::from_seconds(0)));
+        assert!(ele.is_visible(gst::ClockTime::from_mseconds(1999)));
+        assert!(!ele.is_visible(gst::ClockTime::from_seconds(2)));
+        assert!(!ele.is_visible(gst::CLOCK_TIME_NONE));
+    }
+
+    #[test]
+    fn names_are_kept_through_json() {
+        let ele = OverlayElement::create_rectangle(0., 0., 1., 1., (1., 1., 1., 1.), (0., 2.));
+        assert_eq!(ele.name(), None);
+        assert!(!serde_json::to_string(&ele).unwrap().contains("name"));
+
+        let ele = ele.with_name("banner");
+        let json = serde_json::to_string(&ele).unwrap();
+        let back: OverlayElement = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.name(), Some("banner"));
+    }
 }
@@ -2,78 +2,232 @@ extern crate vid_overlay;
 
 use gst::prelude::*;
 
-use anyhow::Error;
+use vid_overlay::{
+    mqtt_options, serve_pipeline, Backend, Binding, Channel, GaugeKind, GaugeSource, GaugeStyle,
+    GpsLog, MapStyle, MqttOverlay, Output, OverlayElement, PipelineRunner, Scene, StatusReporter,
+    StatusTopics, SubtitleStyle, Subtitles, Units, VideoContext, WebRtcConfig, WebRtcSession,
+};
 
-use vid_overlay::{ErrorMessage, OverlayElement, VideoContext};
-
-fn draw_elements() -> gst::Pipeline {
-    let pipeline = VideoContext::new(1920i32, 1080i32, 30i32).unwrap();
+fn draw_elements(backend: Backend, output: Output) -> (gst::Pipeline, Scene) {
+    let pipeline = VideoContext::with_output(1920i32, 1080i32, 30i32, backend, output).unwrap();
     let overlay = pipeline.get_by_name("overlay").unwrap();
 
     let rect =
-        OverlayElement::create_rectangle(670., 100., 300., 100., (0.2, 0.8, 1.0, 1.), (3, 6));
+        OverlayElement::create_rectangle(670., 100., 300., 100., (0.2, 0.8, 1.0, 1.), (3., 6.));
     let rect2 =
-        OverlayElement::create_rectangle(1070., 200., 300., 100., (1.0, 0.3, 0.6, 1.), (5, 10));
+        OverlayElement::create_rectangle(1070., 200., 300., 100., (1.0, 0.3, 0.6, 1.), (5., 10.));
     let rect3 =
-        OverlayElement::create_rectangle(1070., 550., 300., 100., (0.1, 0.5, 1.0, 1.), (7, 13));
+        OverlayElement::create_rectangle(1070., 550., 300., 100., (0.1, 0.5, 1.0, 1.), (7., 13.));
     let rect4 =
-        OverlayElement::create_rectangle(1600., 700., 500., 50., (0.9, 0.5, 1.0, 1.), (4, 12));
+        OverlayElement::create_rectangle(1600., 700., 500., 50., (0.9, 0.5, 1.0, 1.), (4., 12.));
     let rect5 =
-        OverlayElement::create_rectangle(1800., 550., 900., 100., (0.1, 0.9, 1.0, 1.), (1, 8));
+        OverlayElement::create_rectangle(1800., 550., 900., 100., (0.1, 0.9, 1.0, 1.), (1., 8.));
 
-    let txt =
-        OverlayElement::create_text("Foo Bar".to_string(), 200., 0., (0.1, 0.5, 1.0, 1.), (0, 5));
+    let txt = OverlayElement::create_text(
+        "Foo Bar".to_string(),
+        200.,
+        0.,
+        (0.1, 0.5, 1.0, 1.),
+        (0., 5.),
+    );
     let txt2 = OverlayElement::create_text(
         "This is the longest paragraph ever to be typed and I can't think of anything else to type so I am just clickity clackity yappin' on keyboard".to_string(),
         200.,
         100.,
         (0.1, 0.5, 1.0, 1.),
-        (0, 5),
+        (0., 5.),
     );
 
-    VideoContext::draw_on(overlay, vec![rect, rect2, rect3, rect4, rect5, txt, txt2]);
+    let scene = Scene::new(vec![rect, rect2, rect3, rect4, rect5, txt, txt2]);
+    VideoContext::draw_on(overlay, scene.clone());
 
-    pipeline
+    (pipeline, scene)
 }
 
-fn main_loop(pipeline: gst::Pipeline) -> Result<(), Error> {
-    pipeline.set_state(gst::State::Playing)?;
-
-    let bus = pipeline
-        .get_bus()
-        .expect("Pipeline without bus. Shouldn't happen!");
-
-    for msg in bus.iter_timed(gst::CLOCK_TIME_NONE) {
-        use gst::MessageView;
-
-        match msg.view() {
-            MessageView::Eos(..) => break,
-            MessageView::Error(err) => {
-                pipeline.set_state(gst::State::Null)?;
-                return Err(ErrorMessage {
-                    src: msg
-                        .get_src()
-                        .map(|s| String::from(s.get_path_string()))
-                        .unwrap_or_else(|| String::from("None")),
-                    error: err.get_error().to_string(),
-                    debug: err.get_debug(),
-                    source: err.get_error(),
-                }
-                .into());
+fn main() {
+    // blend through overlay composition meta instead of drawing into every frame
+    let backend = if std::env::args().any(|arg| arg == "--composition") {
+        Backend::Composition
+    } else {
+        Backend::Cairo
+    };
+
+    let args: Vec<String> = std::env::args().collect();
+    let option = |name: &str| {
+        args.iter()
+            .position(|arg| arg == name)
+            .and_then(|idx| args.get(idx + 1))
+    };
+
+    // to a browser through the signalling server instead of a window, e.g.
+    // `--webrtc ws://127.0.0.1:8765 --peer viewer`
+    let webrtc = option("--webrtc").map(|server| {
+        let peer = option("--peer").map(String::as_str).unwrap_or("viewer");
+        let config = WebRtcConfig::new(server, peer);
+        match option("--stun") {
+            Some(stun) => config.with_stun_server(stun),
+            None => config,
+        }
+    });
+    let output = match webrtc {
+        Some(_) => Output::WebRtc,
+        None => Output::Window,
+    };
+
+    let (pipeline, scene) = draw_elements(backend, output);
+
+    // track map, speedometer, position and altitude from a gps.json log,
+    // e.g. `--gps scripts/gps.json`
+    if let Some(path) = option("--gps") {
+        match GpsLog::load(path) {
+            Ok(log) => {
+                scene.push(OverlayElement::create_track(
+                    log.clone(),
+                    MapStyle::default(),
+                    0,
+                    1560.,
+                    40.,
+                    320.,
+                    240.,
+                    (0., u32::MAX as f64),
+                ));
+                scene.push(OverlayElement::create_gauge(
+                    GaugeSource::Log {
+                        log: log.clone(),
+                        channel: Channel::Speed,
+                        units: Units::default(),
+                        offset_ms: 0,
+                    },
+                    GaugeStyle::for_channel(GaugeKind::Dial, Channel::Speed, Units::default()),
+                    1560.,
+                    300.,
+                    200.,
+                    200.,
+                    (0., u32::MAX as f64),
+                ));
+                scene.push(OverlayElement::create_telemetry(
+                    log,
+                    Units::default(),
+                    0,
+                    40.,
+                    900.,
+                    (1.0, 1.0, 1.0, 1.),
+                    (0., u32::MAX as f64),
+                ));
             }
-            _ => (),
+            Err(e) => eprintln!("No telemetry from {}: {}", path, e),
         }
     }
 
-    pipeline.set_state(gst::State::Null)?;
+    // SRT, WebVTT or ASS drawn like any other text, e.g. `--subtitles scripts/subtitles.srt`
+    if let Some(path) = option("--subtitles") {
+        match Subtitles::load(path) {
+            Ok(subtitles) => match VideoContext::frame_size(&pipeline) {
+                Some((width, height)) => {
+                    let style = SubtitleStyle::default();
+                    let elements =
+                        subtitles.to_elements(f64::from(width), f64::from(height), &style);
+                    scene.update(|scene| scene.extend(elements));
+                }
+                None => eprintln!("No subtitles from {}: frame size unknown", path),
+            },
+            Err(e) => eprintln!("No subtitles from {}: {}", path, e),
+        }
+    }
 
-    Ok(())
-}
+    // the text of the scene as soft subtitles, e.g. `--export-subtitles captions.vtt`
+    if let Some(path) = option("--export-subtitles") {
+        if let Err(e) = Subtitles::from_elements(&scene.snapshot()).save(path) {
+            eprintln!("Subtitles not exported to {}: {}", path, e);
+        }
+    }
 
-fn main() {
-    let pipeline = draw_elements();
-    match main_loop(pipeline) {
-        Ok(r) => r,
+    // EVM commands and IoT replies of the stations, e.g. `--mqtt 127.0.0.1:1883`
+    let mqtt = option("--mqtt")
+        .map(|addr| mqtt_options(&format!("vid_overlay-{}", std::process::id()), addr));
+    let _mqtt = match mqtt {
+        Some(Ok(options)) => {
+            scene.push(
+                OverlayElement::create_text(
+                    "EVM: -".to_string(),
+                    40.,
+                    40.,
+                    (1.0, 1.0, 1.0, 1.),
+                    (0., u32::MAX as f64),
+                )
+                .with_name("evm"),
+            );
+            scene.push(
+                OverlayElement::create_text(
+                    "IoT: -".to_string(),
+                    40.,
+                    70.,
+                    (1.0, 1.0, 1.0, 1.),
+                    (0., u32::MAX as f64),
+                )
+                .with_name("iot"),
+            );
+
+            Some(MqttOverlay::connect(
+                options,
+                scene.clone(),
+                vec![
+                    Binding::new("bw3/4/+/evm3", "Body.CommandType", "evm").with_format("EVM: {}"),
+                    Binding::new("bw3/4/+/iot", "MessageType", "iot").with_format("IoT: {}"),
+                ],
+            ))
+        }
+        Some(Err(e)) => {
+            eprintln!("No MQTT overlay: {}", e);
+            None
+        }
+        None => None,
+    };
+
+    // JSON-RPC over WebSocket, e.g. `--remote 127.0.0.1:9000`, snapshots go to
+    // `--snapshot-dir` or the current directory
+    if let Some(server) = remote_control::listen(remote_control::remote_arg(&args)) {
+        let snapshot_dir = option("--snapshot-dir").map_or(".", String::as_str);
+        serve_pipeline(server, pipeline.clone(), scene, snapshot_dir.into());
+    }
+
+    let _session = match webrtc {
+        Some(ref config) => match WebRtcSession::start(&pipeline, config) {
+            Ok(session) => Some(session),
+            Err(e) => {
+                eprintln!("Error! {}", e);
+                return;
+            }
+        },
+        None => None,
+    };
+
+    let mut runner = PipelineRunner::new(pipeline);
+
+    // presence, stats and errors of this pipeline, e.g. `--mqtt-status 127.0.0.1:1883`,
+    // as station 4 unless `--station` says otherwise
+    if let Some(addr) = option("--mqtt-status") {
+        let station = option("--station")
+            .and_then(|station| station.parse().ok())
+            .unwrap_or(4);
+        // a client id of its own, the broker drops connections sharing one
+        let client_id = format!("vid_overlay-status-{}", std::process::id());
+        match mqtt_options(&client_id, addr) {
+            Ok(options) => {
+                let topics = StatusTopics::station(station, &client_id);
+                runner = runner.report_to(StatusReporter::connect(options, station, topics));
+            }
+            Err(e) => eprintln!("No MQTT status: {}", e),
+        }
+    }
+
+    match runner.run() {
+        Ok(report) => {
+            report.warnings.iter().for_each(|w| eprintln!("{}", w));
+            if report.drain_timed_out {
+                eprintln!("Pipeline did not drain in time, stopped anyway");
+            }
+        }
         Err(e) => eprintln!("Error! {}", e),
     }
 }
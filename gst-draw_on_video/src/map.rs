@@ -0,0 +1,317 @@
+use std::f64::consts::{FRAC_PI_4, PI};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{GpsLog, GpsSample};
+
+// space kept free around the track inside the map box
+const MARGIN: f64 = 12.;
+const ARROW: f64 = 9.;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Projection {
+    // plain lat/lon, with longitudes shrunk to the track's latitude
+    Equirectangular,
+    // what web maps use, conformal but stretched towards the poles
+    WebMercator,
+}
+
+// colours and extras of a track element
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct MapStyle {
+    pub projection: Projection,
+    pub line: (f64, f64, f64, f64),
+    pub marker: (f64, f64, f64, f64),
+    pub background: (f64, f64, f64, f64),
+    pub north: bool,
+}
+
+impl Default for MapStyle {
+    fn default() -> Self {
+        MapStyle {
+            projection: Projection::Equirectangular,
+            line: (0.2, 0.8, 1.0, 1.),
+            marker: (1.0, 0.3, 0.2, 1.),
+            background: (0., 0., 0., 0.5),
+            north: true,
+        }
+    }
+}
+
+// lat/lon of a whole log mapped into a width x height box, north up
+struct Projector {
+    projection: Projection,
+    // longitude scale of the equirectangular projection
+    cos_lat: f64,
+    min: (f64, f64),
+    scale: f64,
+    offset: (f64, f64),
+    height: f64,
+}
+
+impl Projector {
+    fn new(projection: Projection, samples: &[GpsSample], width: f64, height: f64) -> Projector {
+        let mean_lat = samples.iter().map(|s| s.lat).sum::<f64>() / samples.len() as f64;
+        let mut projector = Projector {
+            projection,
+            cos_lat: mean_lat.to_radians().cos(),
+            min: (0., 0.),
+            scale: 1.,
+            offset: (0., 0.),
+            height,
+        };
+
+        let points: Vec<_> = samples
+            .iter()
+            .map(|s| projector.raw(s.lat, s.lon))
+            .collect();
+        let min_x = points.iter().map(|p| p.0).fold(f64::INFINITY, f64::min);
+        let max_x = points.iter().map(|p| p.0).fold(f64::NEG_INFINITY, f64::max);
+        let min_y = points.iter().map(|p| p.1).fold(f64::INFINITY, f64::min);
+        let max_y = points.iter().map(|p| p.1).fold(f64::NEG_INFINITY, f64::max);
+
+        // same scale on both axes, the track is centred along the shorter one
+        let inner = (
+            (width - 2. * MARGIN).max(1.),
+            (height - 2. * MARGIN).max(1.),
+        );
+        let span = ((max_x - min_x).max(1e-9), (max_y - min_y).max(1e-9));
+        projector.scale = (inner.0 / span.0).min(inner.1 / span.1);
+        projector.min = (min_x, min_y);
+        projector.offset = (
+            MARGIN + (inner.0 - span.0 * projector.scale) / 2.,
+            MARGIN + (inner.1 - span.1 * projector.scale) / 2.,
+        );
+
+        projector
+    }
+
+    // projected coordinates in radians, y pointing north
+    fn raw(&self, lat: f64, lon: f64) -> (f64, f64) {
+        let (lat, lon) = (lat.to_radians(), lon.to_radians());
+        match self.projection {
+            Projection::Equirectangular => (lon * self.cos_lat, lat),
+            Projection::WebMercator => {
+                // the poles are at infinity
+                let lat = lat.clamp(-1.484, 1.484);
+                (lon, (FRAC_PI_4 + lat / 2.).tan().ln())
+            }
+        }
+    }
+
+    fn project(&self, lat: f64, lon: f64) -> (f64, f64) {
+        let (x, y) = self.raw(lat, lon);
+        (
+            self.offset.0 + (x - self.min.0) * self.scale,
+            self.height - self.offset.1 - (y - self.min.1) * self.scale,
+        )
+    }
+}
+
+fn set_rgba(ctx: &cairo::Context, rgba: (f64, f64, f64, f64)) {
+    ctx.set_source_rgba(rgba.0, rgba.1, rgba.2, rgba.3);
+}
+
+fn polyline(ctx: &cairo::Context, points: &[(f64, f64)]) {
+    ctx.new_path();
+    for &(x, y) in points {
+        ctx.line_to(x, y);
+    }
+    ctx.stroke();
+}
+
+// arrow pointing up, rotated clockwise by `angle`
+fn arrow(ctx: &cairo::Context, x: f64, y: f64, angle: f64) {
+    ctx.save();
+    ctx.translate(x, y);
+    ctx.rotate(angle);
+    ctx.new_path();
+    ctx.move_to(0., -ARROW);
+    ctx.line_to(ARROW * 0.7, ARROW);
+    ctx.line_to(0., ARROW * 0.5);
+    ctx.line_to(-ARROW * 0.7, ARROW);
+    ctx.close_path();
+    ctx.fill();
+    ctx.restore();
+}
+
+// A track element's map: the whole log drawn faintly on the background once, and
+// on every frame the part travelled so far with an arrow at the current position.
+pub(crate) struct TrackMap {
+    base: cairo::ImageSurface,
+    projector: Projector,
+    points: Vec<(f64, f64)>,
+}
+
+impl TrackMap {
+    pub(crate) fn new(log: &GpsLog, style: &MapStyle, width: f64, height: f64) -> Option<TrackMap> {
+        let (w, h) = (width.ceil() as i32, height.ceil() as i32);
+        if w <= 0 || h <= 0 {
+            return None;
+        }
+
+        let samples = log.samples();
+        let projector = Projector::new(style.projection, samples, width, height);
+        let points: Vec<_> = samples
+            .iter()
+            .map(|s| projector.project(s.lat, s.lon))
+            .collect();
+
+        let base = cairo::ImageSurface::create(cairo::Format::ARgb32, w, h).ok()?;
+        {
+            let ctx = cairo::Context::new(&base);
+            set_rgba(&ctx, style.background);
+            ctx.rectangle(0., 0., width, height);
+            ctx.fill();
+
+            ctx.set_line_join(cairo::LineJoin::Round);
+            ctx.set_line_cap(cairo::LineCap::Round);
+            let (r, g, b, a) = style.line;
+            set_rgba(&ctx, (r, g, b, a * 0.35));
+            ctx.set_line_width(2.);
+            polyline(&ctx, &points);
+
+            if style.north {
+                let (x, y) = (width - MARGIN, MARGIN + ARROW);
+                set_rgba(&ctx, (1., 1., 1., 0.9));
+                arrow(&ctx, x, y + 8., 0.);
+
+                ctx.select_font_face("Sans", cairo::FontSlant::Normal, cairo::FontWeight::Bold);
+                ctx.set_font_size(11.);
+                let extents = ctx.text_extents("N");
+                ctx.move_to(x - extents.width / 2. - extents.x_bearing, y - 2.);
+                ctx.show_text("N");
+            }
+        }
+        base.flush();
+
+        Some(TrackMap {
+            base,
+            projector,
+            points,
+        })
+    }
+
+    // Replaces what `ctx` holds with the map at `now` (a log time): the part travelled
+    // by then on top of the whole track, and an arrow pointing where it is heading.
+    pub(crate) fn draw(&self, ctx: &cairo::Context, log: &GpsLog, style: &MapStyle, now: f64) {
+        ctx.save();
+        ctx.set_operator(cairo::Operator::Source);
+        ctx.set_source_surface(&self.base, 0., 0.);
+        ctx.paint();
+        ctx.restore();
+
+        let travelled = log
+            .samples()
+            .iter()
+            .take_while(|s| s.log_time as f64 <= now)
+            .count();
+        let current = match log.sample_at(now) {
+            Some(s) => self.projector.project(s.lat, s.lon),
+            None => match self.points.get(travelled.saturating_sub(1)) {
+                Some(&point) => point,
+                None => return,
+            },
+        };
+
+        let mut past = self.points[..travelled].to_vec();
+        past.push(current);
+        ctx.set_line_join(cairo::LineJoin::Round);
+        ctx.set_line_cap(cairo::LineCap::Round);
+        set_rgba(ctx, style.line);
+        ctx.set_line_width(3.);
+        polyline(ctx, &past);
+
+        // heading from the last point that is far enough away to give a direction
+        let heading = past
+            .iter()
+            .rev()
+            .find(|p| (p.0 - current.0).hypot(p.1 - current.1) > 0.5)
+            .map(|p| (current.0 - p.0).atan2(p.1 - current.1));
+
+        set_rgba(ctx, style.marker);
+        match heading {
+            Some(heading) => arrow(ctx, current.0, current.1, heading),
+            None => {
+                ctx.arc(current.0, current.1, ARROW * 0.6, 0., 2. * PI);
+                ctx.fill();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(lat: f64, lon: f64) -> GpsSample {
+        GpsSample {
+            log_time: 0,
+            lat,
+            lon,
+            alt: 0.,
+            speed: 0.,
+            speed_accu: 0.,
+            error: 0.,
+        }
+    }
+
+    fn close(a: (f64, f64), b: (f64, f64)) -> bool {
+        (a.0 - b.0).abs() < 1e-6 && (a.1 - b.1).abs() < 1e-6
+    }
+
+    #[test]
+    fn track_fills_the_box_inside_the_margin() {
+        // twice as tall as wide on the equator, so the height limits the scale
+        let samples = [at(0., 0.), at(0.02, 0.01)];
+        let projector = Projector::new(Projection::Equirectangular, &samples, 200., 124.);
+
+        // 100 px of height for 0.02°, the 50 px of the 0.01° of width centred in 176 px
+        assert!(close(projector.project(0., 0.), (75., 112.)));
+        assert!(close(projector.project(0.02, 0.01), (125., 12.)));
+    }
+
+    #[test]
+    fn north_is_up_and_east_is_right() {
+        let samples = [at(48., 11.), at(48.01, 11.01)];
+        let projector = Projector::new(Projection::WebMercator, &samples, 100., 100.);
+
+        let (x, y) = projector.project(48., 11.);
+        let north = projector.project(48.005, 11.);
+        let east = projector.project(48., 11.005);
+        assert!(north.1 < y && (north.0 - x).abs() < 1e-9);
+        assert!(east.0 > x && (east.1 - y).abs() < 1e-9);
+    }
+
+    #[test]
+    fn longitudes_shrink_away_from_the_equator() {
+        // 0.01° of longitude at 60° N is half as long as 0.01° of latitude
+        let samples = [at(60., 10.), at(60.01, 10.01)];
+        let projector = Projector::new(Projection::Equirectangular, &samples, 124., 124.);
+
+        let (x0, y0) = projector.project(60., 10.);
+        let (x1, y1) = projector.project(60.01, 10.01);
+        assert!(((x1 - x0) / (y0 - y1) - (60.005f64).to_radians().cos()).abs() < 1e-3);
+        assert!((y0 - y1 - 100.).abs() < 1e-6);
+    }
+
+    #[test]
+    fn a_single_point_lands_in_the_box() {
+        let projector = Projector::new(Projection::Equirectangular, &[at(1., 2.)], 100., 60.);
+
+        let (x, y) = projector.project(1., 2.);
+        assert!(x.is_finite() && y.is_finite());
+        assert!((MARGIN..=100. - MARGIN).contains(&x));
+        assert!((MARGIN..=60. - MARGIN).contains(&y));
+    }
+
+    #[test]
+    fn mercator_keeps_the_poles_finite() {
+        let samples = [at(89.9, 0.), at(-89.9, 1.)];
+        let projector = Projector::new(Projection::WebMercator, &samples, 100., 100.);
+
+        let (x, y) = projector.project(90., 0.);
+        assert!(x.is_finite() && y.is_finite());
+        assert!(y >= MARGIN - 1e-9);
+    }
+}
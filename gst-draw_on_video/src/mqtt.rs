@@ -0,0 +1,440 @@
+use std::borrow::Cow;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::Error;
+use derive_more::{Display, Error};
+use rumqttc::{Client, Event, MqttOptions, Outgoing, Packet, Publish, QoS, RecvTimeoutError};
+use serde_json::Value;
+
+use crate::{OverlayElement, Scene};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+// waits between reconnection attempts, doubled after every failure
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Display, Error)]
+#[display(fmt = "Invalid MQTT broker port in {}", _0)]
+pub struct InvalidPort(#[error(not(source))] pub String);
+
+// "host:port" or "host", 1883 being the default port
+pub fn mqtt_options(client_id: &str, addr: &str) -> Result<MqttOptions, Error> {
+    let (host, port) = match addr.rfind(':') {
+        Some(idx) => {
+            let port = addr[idx + 1..]
+                .parse()
+                .map_err(|_| InvalidPort(addr.to_string()))?;
+            (&addr[..idx], port)
+        }
+        None => (addr, 1883),
+    };
+
+    Ok(MqttOptions::new(client_id, host, port))
+}
+
+// The event loop of a client, on a thread of its own. `on_connect` runs on every
+// (re)connection, to subscribe and announce, and `on_publish` with every message
+// published on the subscribed topics. Reconnects with a growing delay when the
+// broker goes away.
+pub(crate) struct MqttConnection {
+    client: Client,
+    stopping: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl MqttConnection {
+    // `linger` is how long `stop` keeps the loop going for queued messages to go out
+    pub(crate) fn spawn<C, P>(
+        options: MqttOptions,
+        linger: Duration,
+        mut on_connect: C,
+        mut on_publish: P,
+    ) -> Self
+    where
+        C: FnMut(&Client) + Send + 'static,
+        P: FnMut(&Client, &Publish) + Send + 'static,
+    {
+        let (client, mut connection) = Client::new(options, 16);
+        let stopping = Arc::new(AtomicBool::new(false));
+
+        let thread_client = client.clone();
+        let stopping_clone = stopping.clone();
+        let thread = thread::spawn(move || {
+            let client = thread_client;
+            let mut backoff = MIN_BACKOFF;
+            let mut stop_deadline: Option<Instant> = None;
+
+            loop {
+                if stopping_clone.load(Ordering::SeqCst) {
+                    let deadline = *stop_deadline.get_or_insert_with(|| Instant::now() + linger);
+                    if Instant::now() >= deadline {
+                        break;
+                    }
+                }
+
+                match connection.recv_timeout(POLL_INTERVAL) {
+                    Ok(Ok(Event::Incoming(Packet::ConnAck(_)))) => {
+                        backoff = MIN_BACKOFF;
+                        on_connect(&client);
+                    }
+                    Ok(Ok(Event::Incoming(Packet::Publish(publish)))) => {
+                        on_publish(&client, &publish);
+                    }
+                    Ok(Ok(Event::Outgoing(Outgoing::Disconnect))) => break,
+                    Ok(Ok(_)) => (),
+                    Ok(Err(err)) => {
+                        if stopping_clone.load(Ordering::SeqCst) {
+                            break;
+                        }
+                        eprintln!("MQTT connection lost: {}, retrying in {:?}", err, backoff);
+                        sleep_while_running(&stopping_clone, backoff);
+                        backoff = (backoff * 2).min(MAX_BACKOFF);
+                    }
+                    Err(RecvTimeoutError::Timeout) => (),
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+        });
+
+        MqttConnection {
+            client,
+            stopping,
+            thread: Some(thread),
+        }
+    }
+
+    pub(crate) fn client(&self) -> &Client {
+        &self.client
+    }
+
+    // messages published just before still go out, within `linger`
+    pub(crate) fn stop(&mut self) {
+        let thread = match self.thread.take() {
+            Some(thread) => thread,
+            None => return,
+        };
+
+        let _ = self.client.try_disconnect();
+        self.stopping.store(true, Ordering::SeqCst);
+        let _ = thread.join();
+    }
+}
+
+impl Drop for MqttConnection {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+// sleeps in short steps so stopping is not held up by a long backoff
+fn sleep_while_running(stopping: &AtomicBool, duration: Duration) {
+    let mut slept = Duration::from_secs(0);
+    while slept < duration && !stopping.load(Ordering::SeqCst) {
+        thread::sleep(POLL_INTERVAL);
+        slept += POLL_INTERVAL;
+    }
+}
+
+// Follows `path` into a JSON value: "a.b.0.c" or a JSON pointer like "/a/b/0/c",
+// the empty path being the whole value. Strings holding JSON are descended into,
+// as in the "Body" of EVM messages.
+pub fn lookup<'a>(value: &'a Value, path: &str) -> Option<Cow<'a, Value>> {
+    let segments: Vec<&str> = match path.strip_prefix('/') {
+        Some(pointer) => pointer.split('/').collect(),
+        None if path.is_empty() => Vec::new(),
+        None => path.split('.').collect(),
+    };
+
+    let mut current = Cow::Borrowed(value);
+    for segment in segments {
+        if let Value::String(ref s) = *current {
+            current = Cow::Owned(serde_json::from_str(s).ok()?);
+        }
+
+        current = match current {
+            Cow::Borrowed(value) => Cow::Borrowed(child(value, segment)?),
+            Cow::Owned(value) => Cow::Owned(child(&value, segment)?.clone()),
+        };
+    }
+
+    Some(current)
+}
+
+fn child<'a>(value: &'a Value, segment: &str) -> Option<&'a Value> {
+    match value {
+        Value::Object(map) => map.get(segment),
+        Value::Array(items) => items.get(segment.parse::<usize>().ok()?),
+        _ => None,
+    }
+}
+
+// Shows a value of the payloads published on `topic` (wildcards allowed) in the
+// scene elements named `element`: as the text of a text element, or as the value
+// of a live gauge. The last value stays up until a new one arrives.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Binding {
+    pub topic: String,
+    pub path: String,
+    // see `OverlayElement::with_name`
+    pub element: String,
+    // "{}" is replaced with the value
+    pub format: String,
+    // decimals of numbers, as they come when None
+    pub precision: Option<usize>,
+}
+
+impl Binding {
+    pub fn new(topic: &str, path: &str, element: &str) -> Self {
+        Binding {
+            topic: topic.to_string(),
+            path: path.to_string(),
+            element: element.to_string(),
+            format: "{}".to_string(),
+            precision: None,
+        }
+    }
+
+    pub fn with_format(mut self, format: &str) -> Self {
+        self.format = format.to_string();
+        self
+    }
+
+    pub fn with_precision(mut self, precision: usize) -> Self {
+        self.precision = Some(precision);
+        self
+    }
+
+    fn text(&self, value: &Value) -> String {
+        let value = match (value, self.precision) {
+            (Value::Number(n), Some(precision)) => match n.as_f64() {
+                Some(n) => format!("{:.*}", precision, n),
+                None => n.to_string(),
+            },
+            (Value::String(s), _) => s.clone(),
+            (value, _) => value.to_string(),
+        };
+
+        self.format.replace("{}", &value)
+    }
+
+    // updates the bound elements, true when one changed
+    pub fn apply(&self, topic: &str, payload: &[u8], elements: &mut [OverlayElement]) -> bool {
+        if !rumqttc::matches(topic, &self.topic) {
+            return false;
+        }
+
+        // payloads that are not JSON can still be shown whole
+        let payload = match serde_json::from_slice(payload) {
+            Ok(payload) => payload,
+            Err(_) => Value::String(String::from_utf8_lossy(payload).into_owned()),
+        };
+        let value = match lookup(&payload, &self.path) {
+            Some(value) => value,
+            None => return false,
+        };
+
+        let mut changed = false;
+        for element in elements
+            .iter_mut()
+            .filter(|element| element.name() == Some(self.element.as_str()))
+        {
+            changed |= match element {
+                OverlayElement::Gauge(_) => {
+                    let number = match *value {
+                        Value::String(ref s) => s.trim().parse().ok(),
+                        ref value => value.as_f64(),
+                    };
+                    match number {
+                        Some(number) => element.set_value(number),
+                        None => false,
+                    }
+                }
+                _ => element.set_content(self.text(&value)),
+            };
+        }
+
+        changed
+    }
+}
+
+// Subscribes to the topics of `bindings` and keeps the scene up to date with what
+// is published on them, subscribing again after every reconnection.
+pub struct MqttOverlay {
+    connection: MqttConnection,
+}
+
+impl MqttOverlay {
+    pub fn connect(options: MqttOptions, scene: Scene, bindings: Vec<Binding>) -> MqttOverlay {
+        let mut topics: Vec<String> = bindings.iter().map(|b| b.topic.clone()).collect();
+        topics.sort();
+        topics.dedup();
+
+        let subscribe = move |client: &Client| {
+            for topic in &topics {
+                if let Err(err) = client.try_subscribe(topic.as_str(), QoS::AtMostOnce) {
+                    eprintln!("Not subscribed to {}: {}", topic, err);
+                }
+            }
+        };
+        let update = move |_: &Client, publish: &Publish| {
+            scene.update(|elements| {
+                for binding in &bindings {
+                    binding.apply(&publish.topic, &publish.payload, elements);
+                }
+            });
+        };
+
+        MqttOverlay {
+            connection: MqttConnection::spawn(options, Duration::from_secs(0), subscribe, update),
+        }
+    }
+
+    pub fn stop(&mut self) {
+        self.connection.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{GaugeKind, GaugeSource, GaugeStyle};
+
+    fn label(content: &str, name: &str) -> OverlayElement {
+        OverlayElement::create_text(content.to_string(), 0., 0., (1., 1., 1., 1.), (0., 1.))
+            .with_name(name)
+    }
+
+    fn gauge(value: Option<f64>) -> OverlayElement {
+        let style = GaugeStyle::new(GaugeKind::Bar, 0., 100., "Speed", "km/h");
+        OverlayElement::create_gauge(
+            GaugeSource::Live { value },
+            style,
+            0.,
+            0.,
+            100.,
+            20.,
+            (0., 1.),
+        )
+        .with_name("speed")
+    }
+
+    #[test]
+    fn broker_addresses() {
+        let options = mqtt_options("a", "broker.local:8883").unwrap();
+        assert_eq!(options.broker_address(), ("broker.local".to_string(), 8883));
+        assert_eq!(options.client_id(), "a");
+
+        let options = mqtt_options("a", "127.0.0.1").unwrap();
+        assert_eq!(options.broker_address(), ("127.0.0.1".to_string(), 1883));
+
+        assert!(mqtt_options("a", "127.0.0.1:mqtt").is_err());
+        assert!(mqtt_options("a", "127.0.0.1:").is_err());
+        assert!(mqtt_options("a", "127.0.0.1:70000").is_err());
+    }
+
+    #[test]
+    fn dotted_paths_and_pointers() {
+        let value = json!({"a": {"b": [10, {"c": "deep"}]}, "x.y": 1});
+
+        assert_eq!(*lookup(&value, "a.b.0").unwrap(), json!(10));
+        assert_eq!(*lookup(&value, "a.b.1.c").unwrap(), json!("deep"));
+        assert_eq!(*lookup(&value, "/a/b/1/c").unwrap(), json!("deep"));
+        assert_eq!(*lookup(&value, "/x.y").unwrap(), json!(1));
+        assert_eq!(*lookup(&value, "").unwrap(), value);
+
+        assert!(matches!(lookup(&value, "a.b"), Some(Cow::Borrowed(_))));
+        assert!(lookup(&value, "a.b.2").is_none());
+        assert!(lookup(&value, "a.b.first").is_none());
+        assert!(lookup(&value, "a.missing").is_none());
+        assert!(lookup(&value, "a.b.0.c").is_none());
+    }
+
+    #[test]
+    fn strings_holding_json_are_descended_into() {
+        let value = json!({"Body": "{\"CommandType\": \"Start\", \"Args\": [1, 2]}"});
+
+        assert_eq!(*lookup(&value, "Body.CommandType").unwrap(), json!("Start"));
+        assert_eq!(*lookup(&value, "/Body/Args/1").unwrap(), json!(2));
+        assert_eq!(
+            *lookup(&value, "Body").unwrap(),
+            json!("{\"CommandType\": \"Start\", \"Args\": [1, 2]}")
+        );
+        assert!(lookup(&json!({"Body": "not json"}), "Body.CommandType").is_none());
+    }
+
+    #[test]
+    fn text_elements_show_the_value() {
+        let binding =
+            Binding::new("bw3/4/+/evm3", "Body.CommandType", "evm").with_format("EVM: {}");
+        let mut elements = vec![label("IoT: -", "iot"), label("EVM: -", "evm")];
+
+        let payload = br#"{"Body": "{\"CommandType\": \"Start\"}"}"#;
+        assert!(binding.apply("bw3/4/7/evm3", payload, &mut elements));
+        assert_eq!(elements[1].content(), Some("EVM: Start"));
+        assert_eq!(elements[0].content(), Some("IoT: -"));
+
+        assert!(!binding.apply("bw3/4/7/iot", payload, &mut elements));
+        assert!(!binding.apply("bw3/4/7/evm3", br#"{"Body": {}}"#, &mut elements));
+        assert_eq!(elements[1].content(), Some("EVM: Start"));
+
+        // payloads that are not JSON are shown whole
+        let binding = Binding::new("status", "", "iot");
+        assert!(binding.apply("status", b"offline", &mut elements));
+        assert_eq!(elements[0].content(), Some("offline"));
+    }
+
+    #[test]
+    fn numbers_are_formatted() {
+        let binding = Binding::new("temp", "t", "iot")
+            .with_format("{} °C")
+            .with_precision(1);
+        let mut elements = vec![label("", "iot")];
+
+        binding.apply("temp", br#"{"t": 21.46}"#, &mut elements);
+        assert_eq!(elements[0].content(), Some("21.5 °C"));
+
+        binding.apply("temp", br#"{"t": true}"#, &mut elements);
+        assert_eq!(elements[0].content(), Some("true °C"));
+    }
+
+    #[test]
+    fn gauges_take_numbers() {
+        let binding = Binding::new("car/speed", "", "speed");
+        let mut elements = vec![label("-", "other"), gauge(None)];
+
+        assert!(binding.apply("car/speed", b"12.5", &mut elements));
+        assert!(elements[1] == gauge(Some(12.5)));
+
+        assert!(binding.apply("car/speed", b"\" 40 \"", &mut elements));
+        assert!(elements[1] == gauge(Some(40.)));
+
+        assert!(!binding.apply("car/speed", b"fast", &mut elements));
+        assert!(elements[1] == gauge(Some(40.)));
+    }
+
+    #[test]
+    fn elements_are_found_by_name_wherever_they_are() {
+        let binding = Binding::new("t", "", "clock");
+        let mut elements = vec![label("", "clock"), label("", "other")];
+
+        binding.apply("t", b"\"12:00\"", &mut elements);
+        assert_eq!(elements[0].content(), Some("12:00"));
+
+        // elements added in front don't change what is bound
+        elements.insert(0, label("", "title"));
+        binding.apply("t", b"\"12:01\"", &mut elements);
+        assert_eq!(elements[1].content(), Some("12:01"));
+        assert_eq!(elements[0].content(), Some(""));
+
+        // every element with the name is updated, none without it
+        elements.push(label("", "clock"));
+        assert!(binding.apply("t", b"\"12:02\"", &mut elements));
+        assert_eq!(elements[3].content(), Some("12:02"));
+        assert!(!Binding::new("t", "", "missing").apply("t", b"1", &mut elements));
+    }
+}
@@ -8,19 +8,12 @@ use std::sync::{Arc, Mutex};
 use anyhow::Error;
 use derive_more::{Display, Error};
 
+use vid_overlay::PipelineRunner;
+
 #[derive(Debug, Display, Error)]
 #[display(fmt = "Missing element {}", _0)]
 struct MissingElement(#[error(not(source))] &'static str);
 
-#[derive(Debug, Display, Error)]
-#[display(fmt = "Received error from {}: {} (debug: {:?})", src, error, debug)]
-struct ErrorMessage {
-    src: String,
-    error: String,
-    debug: Option<String>,
-    source: glib::Error,
-}
-
 struct DrawingContext {
     layout: glib::SendUniqueCell<LayoutWrapper>,
     info: Option<gst_video::VideoInfo>,
@@ -151,43 +144,9 @@ fn create_pipeline() -> Result<gst::Pipeline, Error> {
     Ok(pipeline)
 }
 
-fn main_loop(pipeline: gst::Pipeline) -> Result<(), Error> {
-    pipeline.set_state(gst::State::Playing)?;
-
-    let bus = pipeline
-        .get_bus()
-        .expect("Pipeline without bus. Shouldn't happen!");
-
-    for msg in bus.iter_timed(gst::CLOCK_TIME_NONE) {
-        use gst::MessageView;
-
-        match msg.view() {
-            MessageView::Eos(..) => break,
-            MessageView::Error(err) => {
-                pipeline.set_state(gst::State::Null)?;
-                return Err(ErrorMessage {
-                    src: msg
-                        .get_src()
-                        .map(|s| String::from(s.get_path_string()))
-                        .unwrap_or_else(|| String::from("None")),
-                    error: err.get_error().to_string(),
-                    debug: err.get_debug(),
-                    source: err.get_error(),
-                }
-                .into());
-            }
-            _ => (),
-        }
-    }
-
-    pipeline.set_state(gst::State::Null)?;
-
-    Ok(())
-}
-
 fn main() {
-    match create_pipeline().and_then(main_loop) {
-        Ok(r) => r,
+    match create_pipeline().and_then(|pipeline| PipelineRunner::new(pipeline).run()) {
+        Ok(report) => report.warnings.iter().for_each(|w| eprintln!("{}", w)),
         Err(e) => eprintln!("Error! {}", e),
     }
 }
@@ -0,0 +1,89 @@
+use std::sync::Mutex;
+
+use gst::prelude::*;
+
+use crate::yuv::draw_scene_yuv;
+use crate::{
+    draw_scene, is_yuv, new_layout, CairoFrame, LayoutWrapper, RenderCache, Scene, YuvFrame,
+};
+
+struct ProbeContext {
+    layout: glib::SendUniqueCell<LayoutWrapper>,
+    info: Option<gst_video::VideoInfo>,
+    cache: RenderCache,
+    // set once a frame could not be mapped, so the error is only reported once
+    failed: bool,
+}
+
+// Draws the scene straight into the buffers flowing through `pad`. Nothing is
+// added to the pipeline, the frame layout is taken from the caps seen on the pad.
+pub(crate) fn probe_on(pad: &gst::Pad, scene: Scene) -> Option<gst::PadProbeId> {
+    let info = pad
+        .get_current_caps()
+        .and_then(|caps| gst_video::VideoInfo::from_caps(&caps).ok());
+
+    let prober = Mutex::new(ProbeContext {
+        layout: glib::SendUniqueCell::new(new_layout()).unwrap(),
+        info,
+        cache: RenderCache::new(),
+        failed: false,
+    });
+
+    pad.add_probe(
+        gst::PadProbeType::BUFFER | gst::PadProbeType::EVENT_DOWNSTREAM,
+        move |_, probe_info| {
+            let mut prober = prober.lock().unwrap();
+            let prober = &mut *prober;
+
+            match probe_info.data {
+                Some(gst::PadProbeData::Event(ref event)) => {
+                    if let gst::EventView::Caps(caps) = event.view() {
+                        prober.info = gst_video::VideoInfo::from_caps(caps.get_caps()).ok();
+                        prober.failed = false;
+                    }
+                }
+                Some(gst::PadProbeData::Buffer(ref mut buffer)) => {
+                    let info = match prober.info {
+                        Some(ref info) => info,
+                        None => return gst::PadProbeReturn::Ok,
+                    };
+                    if prober.failed {
+                        return gst::PadProbeReturn::Ok;
+                    }
+
+                    let timestamp = buffer.get_pts();
+                    let layout = prober.layout.borrow();
+                    prober.cache.begin_frame();
+
+                    let elements = scene.lock();
+
+                    // YUV frames are blended into directly instead of going through RGB
+                    let drawn = if is_yuv(info.format()) {
+                        YuvFrame::map(buffer.make_mut(), info).and_then(|mut frame| {
+                            draw_scene_yuv(
+                                &mut frame,
+                                &layout,
+                                &mut prober.cache,
+                                &elements,
+                                timestamp,
+                            )
+                        })
+                    } else {
+                        CairoFrame::map(buffer.make_mut(), info).map(|frame| {
+                            let ctx = frame.context();
+                            draw_scene(&ctx, &layout, &mut prober.cache, &elements, timestamp);
+                        })
+                    };
+
+                    if let Err(err) = drawn {
+                        eprintln!("Not drawing on {:?}: {}", info.format(), err);
+                        prober.failed = true;
+                    }
+                }
+                _ => (),
+            }
+
+            gst::PadProbeReturn::Ok
+        },
+    )
+}
@@ -0,0 +1,189 @@
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use gst::prelude::*;
+use remote_control::{seconds, RemoteRequest, RemoteServer, RpcError, POLL_INTERVAL};
+use serde_json::{json, Value};
+
+use crate::{snapshot, ImageFormat, OverlayElement, Scene};
+
+// Handles the "overlay.*" calls against `scene`: set, add, clear and get. Other
+// calls are given back so the caller can handle them.
+pub fn handle_overlay_request(scene: &Scene, request: RemoteRequest) -> Option<RemoteRequest> {
+    let elements = || -> Result<Vec<OverlayElement>, RpcError> {
+        let elements = request
+            .params
+            .get("elements")
+            .cloned()
+            .unwrap_or(Value::Null);
+        serde_json::from_value(elements).map_err(|err| RpcError::invalid_params(&err.to_string()))
+    };
+
+    let result = match request.method.as_str() {
+        "overlay.set" => elements().map(|elements| scene.set(elements)),
+        "overlay.add" => elements().map(|elements| scene.update(|scene| scene.extend(elements))),
+        "overlay.clear" => {
+            scene.clear();
+            Ok(())
+        }
+        "overlay.get" => {
+            let elements = serde_json::to_value(scene.snapshot())
+                .map_err(|err| RpcError::internal(&err.to_string()));
+            request.respond(elements.map(|elements| json!({ "elements": elements })));
+            return None;
+        }
+        _ => return Some(request),
+    };
+
+    let count = scene.len();
+    request.respond(result.map(|_| json!({ "count": count })));
+    None
+}
+
+fn seek_to(pipeline: &gst::Pipeline, seconds: f64) -> Result<Value, RpcError> {
+    if !seconds.is_finite() || seconds < 0. {
+        return Err(RpcError::invalid_params("position must be positive"));
+    }
+
+    let position = gst::ClockTime::from_nseconds((seconds * 1e9) as u64);
+    pipeline
+        .seek_simple(gst::SeekFlags::FLUSH | gst::SeekFlags::ACCURATE, position)
+        .map(|_| Value::Null)
+        .map_err(|err| RpcError::internal(&err.to_string()))
+}
+
+fn set_rate(pipeline: &gst::Pipeline, rate: f64) -> Result<Value, RpcError> {
+    if rate <= 0. || !rate.is_finite() {
+        return Err(RpcError::invalid_params("rate must be above zero"));
+    }
+
+    let position = pipeline
+        .query_position::<gst::ClockTime>()
+        .unwrap_or_else(|| gst::ClockTime::from_seconds(0));
+    pipeline
+        .seek(
+            rate,
+            gst::SeekFlags::FLUSH | gst::SeekFlags::ACCURATE,
+            gst::SeekType::Set,
+            position,
+            gst::SeekType::None,
+            gst::CLOCK_TIME_NONE,
+        )
+        .map(|_| Value::Null)
+        .map_err(|err| RpcError::internal(&err.to_string()))
+}
+
+fn set_state(pipeline: &gst::Pipeline, state: gst::State) -> Result<Value, RpcError> {
+    pipeline
+        .set_state(state)
+        .map(|_| Value::Null)
+        .map_err(|err| RpcError::internal(&err.to_string()))
+}
+
+// "snapshot" with an optional "format" of png or jpeg, answered with the file
+// written once the frame is encoded
+fn snapshot_request(request: RemoteRequest, pipeline: &gst::Pipeline, dir: &Path) {
+    let format = match request.params.get("format").and_then(Value::as_str) {
+        Some(name) => match ImageFormat::from_name(name) {
+            Some(format) => format,
+            None => {
+                let err = RpcError::invalid_params("format must be png or jpeg");
+                return request.respond(Err(err));
+            }
+        },
+        None => ImageFormat::Png,
+    };
+
+    snapshot(pipeline.upcast_ref(), dir, format, move |path| {
+        let result = path
+            .map(|path| json!({ "path": path.display().to_string() }))
+            .map_err(|err| RpcError::internal(&err.to_string()));
+        request.respond(result);
+    });
+}
+
+// Serves `server` for a pipeline run elsewhere (e.g. by `PipelineRunner`): plays,
+// pauses, seeks, changes rate, takes snapshots and updates `scene`, and pushes
+// "state-changed" and once a second "position" events. Snapshots are written to
+// `snapshot_dir`. Returns when the pipeline is shut down.
+pub fn serve_pipeline(
+    server: RemoteServer,
+    pipeline: gst::Pipeline,
+    scene: Scene,
+    snapshot_dir: PathBuf,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        // snapshots are encoded in the background and report back on this context
+        let context = glib::MainContext::new();
+        context.with_thread_default(|| serve(&server, &pipeline, &scene, &snapshot_dir, &context))
+    })
+}
+
+fn serve(
+    server: &RemoteServer,
+    pipeline: &gst::Pipeline,
+    scene: &Scene,
+    snapshot_dir: &Path,
+    context: &glib::MainContext,
+) {
+    let mut state = pipeline.get_current_state();
+    let mut last_position = Instant::now();
+    let mut started = false;
+
+    loop {
+        if let Some(request) = server.recv_timeout(POLL_INTERVAL) {
+            if let Some(request) = handle_overlay_request(scene, request) {
+                if request.method == "snapshot" {
+                    snapshot_request(request, pipeline, snapshot_dir);
+                } else {
+                    let result = match request.method.as_str() {
+                        "play" => set_state(pipeline, gst::State::Playing),
+                        "pause" => set_state(pipeline, gst::State::Paused),
+                        "seek" => request
+                            .param_f64("position")
+                            .and_then(|position| seek_to(pipeline, position)),
+                        "rate" => request
+                            .param_f64("rate")
+                            .and_then(|rate| set_rate(pipeline, rate)),
+                        "status" => Ok(json!({
+                            "state": format!("{:?}", pipeline.get_current_state()),
+                            "position": seconds(pipeline.query_position::<gst::ClockTime>()),
+                            "duration": seconds(pipeline.query_duration::<gst::ClockTime>()),
+                        })),
+                        method => Err(RpcError::method_not_found(method)),
+                    };
+                    request.respond(result);
+                }
+            }
+        }
+        // finished snapshots answer their requests from here
+        while context.iteration(false) {}
+
+        let current = pipeline.get_current_state();
+        if current != state {
+            server.notify(
+                "state-changed",
+                json!({"old": format!("{:?}", state), "new": format!("{:?}", current)}),
+            );
+            state = current;
+        }
+
+        // the runner sets the pipeline back to Null once it is done
+        started |= current != gst::State::Null;
+        if started && current == gst::State::Null {
+            break;
+        }
+
+        if last_position.elapsed() >= Duration::from_secs(1) {
+            last_position = Instant::now();
+            server.notify(
+                "position",
+                json!({
+                    "position": seconds(pipeline.query_position::<gst::ClockTime>()),
+                    "duration": seconds(pipeline.query_duration::<gst::ClockTime>()),
+                }),
+            );
+        }
+    }
+}
@@ -0,0 +1,294 @@
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use anyhow::Error;
+use derive_more::{Display, Error};
+
+use gst::prelude::*;
+use serde_json::{json, Value};
+
+use crate::StatusReporter;
+
+// name of the application message used to request a shutdown through the bus
+const SHUTDOWN_MESSAGE: &str = "vid-overlay-shutdown";
+
+// how long a graceful shutdown may wait for the EOS to reach the sinks
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+// only one Ctrl-C handler can exist per process, it is installed by the first
+// runner and forwards to the buses of all runners currently running
+static CTRL_C_HANDLER: OnceLock<Result<(), String>> = OnceLock::new();
+static CTRL_C_BUSES: Mutex<Vec<gst::Bus>> = Mutex::new(Vec::new());
+
+#[derive(Debug, Display, Error)]
+#[display(fmt = "Unable to install Ctrl-C handler: {}", _0)]
+pub struct CtrlCError(#[error(not(source))] pub String);
+
+#[derive(Debug, Display, Error)]
+#[display(fmt = "Received error from {}: {} (debug: {:?})", src, error, debug)]
+pub struct ErrorMessage {
+    pub src: String,
+    pub error: String,
+    pub debug: Option<String>,
+    pub source: glib::Error,
+}
+
+#[derive(Debug, Clone, Display)]
+#[display(
+    fmt = "Received warning from {}: {} (debug: {:?})",
+    src,
+    warning,
+    debug
+)]
+pub struct WarningMessage {
+    pub src: String,
+    pub warning: String,
+    pub debug: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunOutcome {
+    // the stream finished on its own
+    Eos,
+    // `RunnerHandle::shutdown` was called
+    Shutdown,
+    // the process received Ctrl-C
+    Interrupted,
+}
+
+#[derive(Debug)]
+pub struct RunReport {
+    pub outcome: RunOutcome,
+    pub warnings: Vec<WarningMessage>,
+    pub state_changes: Vec<(gst::State, gst::State)>,
+    pub latency_recalculations: u32,
+    pub clock_losses: u32,
+    // the EOS did not reach the sinks within the shutdown timeout
+    pub drain_timed_out: bool,
+    pub elapsed: Duration,
+}
+
+// cloneable handle that can stop a running pipeline from any thread
+#[derive(Clone)]
+pub struct RunnerHandle {
+    bus: gst::Bus,
+}
+
+impl RunnerHandle {
+    pub fn shutdown(&self) {
+        self.request(RunOutcome::Shutdown);
+    }
+
+    fn request(&self, outcome: RunOutcome) {
+        let s = gst::Structure::builder(SHUTDOWN_MESSAGE)
+            .field("interrupted", &(outcome == RunOutcome::Interrupted))
+            .build();
+        let _ = self.bus.post(&gst::message::Application::new(s));
+    }
+}
+
+pub struct PipelineRunner {
+    pipeline: gst::Pipeline,
+    handle: RunnerHandle,
+    ctrl_c: bool,
+    reporter: Option<StatusReporter>,
+}
+
+impl PipelineRunner {
+    pub fn new(pipeline: gst::Pipeline) -> Self {
+        let bus = pipeline
+            .get_bus()
+            .expect("Pipeline without bus. Shouldn't happen!");
+
+        PipelineRunner {
+            pipeline,
+            handle: RunnerHandle { bus },
+            ctrl_c: true,
+            reporter: None,
+        }
+    }
+
+    // disable the Ctrl-C handler, e.g. when the binary installs its own
+    pub fn handle_ctrl_c(mut self, enable: bool) -> Self {
+        self.ctrl_c = enable;
+        self
+    }
+
+    // publishes stats, warnings, errors and state changes while running, and
+    // presence until the run is over
+    pub fn report_to(mut self, reporter: StatusReporter) -> Self {
+        self.reporter = Some(reporter);
+        self
+    }
+
+    pub fn handle(&self) -> RunnerHandle {
+        self.handle.clone()
+    }
+
+    pub fn pipeline(&self) -> &gst::Pipeline {
+        &self.pipeline
+    }
+
+    pub fn run(mut self) -> Result<RunReport, Error> {
+        if self.ctrl_c {
+            install_ctrl_c_handler()?;
+            CTRL_C_BUSES.lock().unwrap().push(self.handle.bus.clone());
+        }
+
+        let started = Instant::now();
+        let mut report = RunReport {
+            outcome: RunOutcome::Eos,
+            warnings: Vec::new(),
+            state_changes: Vec::new(),
+            latency_recalculations: 0,
+            clock_losses: 0,
+            drain_timed_out: false,
+            elapsed: Duration::default(),
+        };
+
+        let result = self
+            .pipeline
+            .set_state(gst::State::Playing)
+            .map_err(Error::from)
+            .and_then(|_| self.main_loop(&mut report));
+        if self.ctrl_c {
+            let bus = &self.handle.bus;
+            CTRL_C_BUSES.lock().unwrap().retain(|b| b != bus);
+        }
+        if let Some(ref reporter) = self.reporter {
+            match result {
+                Ok(()) => reporter.event(
+                    "finished",
+                    json!({ "Outcome": format!("{:?}", report.outcome) }),
+                ),
+                Err(ref err) => match err.downcast_ref::<ErrorMessage>() {
+                    Some(msg) => reporter.error(&msg.src, &msg.error, msg.debug.as_deref()),
+                    None => reporter.error("None", &err.to_string(), None),
+                },
+            }
+        }
+        self.pipeline.set_state(gst::State::Null)?;
+        report.elapsed = started.elapsed();
+
+        result.map(|_| report)
+    }
+
+    fn notify(&self, kind: &str, data: Value) {
+        if let Some(ref reporter) = self.reporter {
+            reporter.event(kind, data);
+        }
+    }
+
+    fn main_loop(&mut self, report: &mut RunReport) -> Result<(), Error> {
+        use gst::MessageView;
+
+        let mut deadline: Option<Instant> = None;
+
+        loop {
+            if deadline.map(|d| Instant::now() >= d).unwrap_or(false) {
+                report.drain_timed_out = true;
+                return Ok(());
+            }
+
+            if let Some(ref mut reporter) = self.reporter {
+                reporter.tick(&self.pipeline);
+            }
+
+            let msg = match self.handle.bus.timed_pop(gst::MSECOND * 100) {
+                Some(msg) => msg,
+                None => continue,
+            };
+
+            match msg.view() {
+                MessageView::Eos(..) => return Ok(()),
+                MessageView::Error(err) => {
+                    return Err(ErrorMessage {
+                        src: source_path(&msg),
+                        error: err.get_error().to_string(),
+                        debug: err.get_debug(),
+                        source: err.get_error(),
+                    }
+                    .into());
+                }
+                MessageView::Warning(warning) => {
+                    let warning = WarningMessage {
+                        src: source_path(&msg),
+                        warning: warning.get_error().to_string(),
+                        debug: warning.get_debug(),
+                    };
+                    self.notify(
+                        "warning",
+                        json!({"Source": warning.src, "Message": warning.warning}),
+                    );
+                    report.warnings.push(warning);
+                }
+                MessageView::StateChanged(state_changed) => {
+                    if state_changed
+                        .get_src()
+                        .map(|s| s == self.pipeline)
+                        .unwrap_or(false)
+                    {
+                        let (old, new) = (state_changed.get_old(), state_changed.get_current());
+                        report.state_changes.push((old, new));
+                        self.notify(
+                            "state-changed",
+                            json!({"Old": format!("{:?}", old), "New": format!("{:?}", new)}),
+                        );
+                    }
+                }
+                MessageView::Latency(..) => {
+                    self.pipeline.recalculate_latency()?;
+                    report.latency_recalculations += 1;
+                }
+                MessageView::ClockLost(..) => {
+                    // select a new clock by going through PAUSED
+                    self.pipeline.set_state(gst::State::Paused)?;
+                    self.pipeline.set_state(gst::State::Playing)?;
+                    report.clock_losses += 1;
+                }
+                MessageView::Application(app) => {
+                    let s = match app.get_structure() {
+                        Some(s) if s.get_name() == SHUTDOWN_MESSAGE => s,
+                        _ => continue,
+                    };
+
+                    // a second request while draining stops immediately
+                    if deadline.is_some() {
+                        return Ok(());
+                    }
+
+                    report.outcome = match s.get_some::<bool>("interrupted") {
+                        Ok(true) => RunOutcome::Interrupted,
+                        _ => RunOutcome::Shutdown,
+                    };
+
+                    // let the EOS flow through so sinks and muxers can finish cleanly
+                    self.pipeline.send_event(gst::event::Eos::new());
+                    deadline = Some(Instant::now() + SHUTDOWN_TIMEOUT);
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+fn install_ctrl_c_handler() -> Result<(), CtrlCError> {
+    CTRL_C_HANDLER
+        .get_or_init(|| {
+            ctrlc::set_handler(|| {
+                for bus in CTRL_C_BUSES.lock().unwrap().iter() {
+                    let handle = RunnerHandle { bus: bus.clone() };
+                    handle.request(RunOutcome::Interrupted);
+                }
+            })
+            .map_err(|err| err.to_string())
+        })
+        .clone()
+        .map_err(CtrlCError)
+}
+
+fn source_path(msg: &gst::Message) -> String {
+    msg.get_src()
+        .map(|s| String::from(s.get_path_string()))
+        .unwrap_or_else(|| String::from("None"))
+}
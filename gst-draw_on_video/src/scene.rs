@@ -0,0 +1,57 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::OverlayElement;
+
+// The elements drawn by a backend, shared so they can be changed while the
+// pipeline runs. Clones refer to the same elements.
+#[derive(Clone, Default)]
+pub struct Scene {
+    elements: Arc<Mutex<Vec<OverlayElement>>>,
+}
+
+impl Scene {
+    pub fn new(elements: Vec<OverlayElement>) -> Self {
+        Scene {
+            elements: Arc::new(Mutex::new(elements)),
+        }
+    }
+
+    pub fn set(&self, elements: Vec<OverlayElement>) {
+        *self.lock() = elements;
+    }
+
+    pub fn push(&self, element: OverlayElement) {
+        self.lock().push(element);
+    }
+
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    pub fn update<F: FnOnce(&mut Vec<OverlayElement>)>(&self, f: F) {
+        f(&mut self.lock());
+    }
+
+    pub fn snapshot(&self) -> Vec<OverlayElement> {
+        self.lock().clone()
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    // held for the duration of one frame by the backends
+    pub(crate) fn lock(&self) -> MutexGuard<Vec<OverlayElement>> {
+        self.elements.lock().unwrap()
+    }
+}
+
+impl From<Vec<OverlayElement>> for Scene {
+    fn from(elements: Vec<OverlayElement>) -> Self {
+        Scene::new(elements)
+    }
+}
@@ -0,0 +1,136 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::Error;
+use derive_more::{Display, Error};
+use gst::prelude::*;
+
+#[derive(Debug, Display, Error)]
+#[display(fmt = "No video frame to capture")]
+pub struct NoSample;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Png,
+    Jpeg,
+}
+
+impl ImageFormat {
+    pub fn from_name(name: &str) -> Option<ImageFormat> {
+        match name.to_lowercase().as_str() {
+            "png" => Some(ImageFormat::Png),
+            "jpg" | "jpeg" => Some(ImageFormat::Jpeg),
+            _ => None,
+        }
+    }
+
+    fn caps(self) -> gst::Caps {
+        match self {
+            ImageFormat::Png => gst::Caps::new_simple("image/png", &[]),
+            ImageFormat::Jpeg => gst::Caps::new_simple("image/jpeg", &[]),
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Jpeg => "jpg",
+        }
+    }
+}
+
+fn is_video(sample: &gst::Sample) -> bool {
+    sample
+        .get_caps()
+        .and_then(|caps| {
+            caps.get_structure(0)
+                .map(|s| s.get_name().starts_with("video/"))
+        })
+        .unwrap_or(false)
+}
+
+// The last frame rendered by `element` or by the first sink inside it that keeps
+// one in its "last-sample" property. Audio sinks are skipped.
+pub fn last_sample(element: &gst::Element) -> Option<gst::Sample> {
+    if element.has_property("last-sample", None) {
+        let sample = element
+            .get_property("last-sample")
+            .ok()
+            .and_then(|value| value.get::<gst::Sample>().ok().flatten());
+        if let Some(sample) = sample.filter(is_video) {
+            return Some(sample);
+        }
+    }
+
+    let bin = element.downcast_ref::<gst::Bin>()?;
+    bin.get_children().iter().find_map(last_sample)
+}
+
+// e.g. 0h01m02.345s, safe to use in file names
+fn format_position(time: gst::ClockTime) -> String {
+    match time.nseconds() {
+        Some(ns) => {
+            let ms = ns / 1_000_000;
+            format!(
+                "{}h{:02}m{:02}.{:03}s",
+                ms / 3_600_000,
+                ms / 60_000 % 60,
+                ms / 1000 % 60,
+                ms % 1000
+            )
+        }
+        None => "unknown".to_string(),
+    }
+}
+
+// Writes the last frame shown by `element` into `dir` as
+// snapshot-<local time>-<stream position>.<png|jpg> and hands the path to `done`.
+// The frame is encoded in the background and `done` is called from the thread
+// default main context, so that context has to be running or iterated.
+pub fn snapshot<F>(element: &gst::Element, dir: &Path, format: ImageFormat, done: F)
+where
+    F: FnOnce(Result<PathBuf, Error>) + Send + 'static,
+{
+    let sample = match last_sample(element) {
+        Some(sample) => sample,
+        None => return done(Err(NoSample.into())),
+    };
+    let position = sample
+        .get_buffer()
+        .map(|buffer| buffer.get_pts())
+        .unwrap_or(gst::CLOCK_TIME_NONE);
+
+    let dir = dir.to_path_buf();
+    gst_video::convert_sample_async(&sample, &format.caps(), 5 * gst::SECOND, move |image| {
+        let path = image
+            .map_err(Error::from)
+            .and_then(|image| save(&image, &dir, format, position));
+        done(path)
+    });
+}
+
+fn save(
+    image: &gst::Sample,
+    dir: &Path,
+    format: ImageFormat,
+    position: gst::ClockTime,
+) -> Result<PathBuf, Error> {
+    let buffer = image.get_buffer().ok_or(NoSample)?;
+    let map = buffer.map_readable()?;
+
+    let now = glib::DateTime::new_now_local()
+        .format("%Y%m%d-%H%M%S")
+        .map(|s| s.to_string())
+        .unwrap_or_default();
+    let path = dir.join(format!(
+        "snapshot-{}-{}.{}",
+        now,
+        format_position(position),
+        format.extension()
+    ));
+
+    fs::create_dir_all(dir)?;
+    fs::write(&path, map.as_slice())?;
+
+    Ok(path)
+}
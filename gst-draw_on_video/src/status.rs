@@ -0,0 +1,249 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use gst::prelude::*;
+use remote_control::seconds;
+use rumqttc::{Client, LastWill, MqttOptions, Publish, QoS};
+use serde_json::{json, Value};
+
+use crate::mqtt::MqttConnection;
+
+// how long `stop` waits for the offline message to go out
+const STOP_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Where a pipeline's status goes. The defaults follow the station clients:
+// presence on "bw3/presence", the rest under "bw3/<station>/<client id>/".
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusTopics {
+    pub presence: String,
+    pub stats: String,
+    pub events: String,
+    // payloads published here are echoed on `pong`
+    pub ping: String,
+    pub pong: String,
+}
+
+impl StatusTopics {
+    pub fn station(station: u32, client_id: &str) -> Self {
+        let base = format!("bw3/{}/{}", station, client_id);
+
+        StatusTopics {
+            presence: "bw3/presence".to_string(),
+            stats: format!("{}/stats", base),
+            events: format!("{}/events", base),
+            ping: format!("{}/ping", base),
+            pong: format!("{}/pong", base),
+        }
+    }
+}
+
+fn presence(client_id: &str, station: u32, online: bool) -> String {
+    json!({
+        "ClientId": client_id,
+        "StationId": station,
+        "Status": if online { 1 } else { 0 },
+    })
+    .to_string()
+}
+
+fn unix_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+// frames rendered and dropped by the first video sink in `element`, read from
+// the "stats" property base sinks keep
+pub fn sink_stats(element: &gst::Element) -> Option<(u64, u64)> {
+    if element.has_property("stats", None) && is_video_sink(element) {
+        let stats = element
+            .get_property("stats")
+            .ok()
+            .and_then(|value| value.get::<gst::Structure>().ok().flatten());
+        if let Some(stats) = stats {
+            let rendered = stats.get_some::<u64>("rendered").unwrap_or(0);
+            let dropped = stats.get_some::<u64>("dropped").unwrap_or(0);
+            return Some((rendered, dropped));
+        }
+    }
+
+    let bin = element.downcast_ref::<gst::Bin>()?;
+    bin.get_children().iter().find_map(sink_stats)
+}
+
+fn is_video_sink(element: &gst::Element) -> bool {
+    element
+        .get_static_pad("sink")
+        .and_then(|pad| pad.get_current_caps())
+        .and_then(|caps| {
+            caps.get_structure(0)
+                .map(|s| s.get_name().starts_with("video/"))
+        })
+        .unwrap_or(false)
+}
+
+// Announces a pipeline on the presence topic, with a last will saying it went
+// offline, and publishes its stats and events. Pings are answered for as long as
+// it is connected; it reconnects as `MqttConnection` does when the broker goes away.
+pub struct StatusReporter {
+    connection: MqttConnection,
+    client_id: String,
+    station: u32,
+    topics: StatusTopics,
+    interval: Duration,
+    last_stats: Option<(Instant, u64)>,
+    stopped: bool,
+}
+
+impl StatusReporter {
+    pub fn connect(mut options: MqttOptions, station: u32, topics: StatusTopics) -> Self {
+        let client_id = options.client_id();
+        options.set_last_will(LastWill::new(
+            topics.presence.as_str(),
+            presence(&client_id, station, false),
+            QoS::AtLeastOnce,
+            false,
+        ));
+
+        let online = presence(&client_id, station, true);
+        let announced = topics.clone();
+        let announce = move |client: &Client| {
+            let _ = client.try_publish(
+                announced.presence.as_str(),
+                QoS::AtLeastOnce,
+                false,
+                online.as_bytes(),
+            );
+            if let Err(err) = client.try_subscribe(announced.ping.as_str(), QoS::AtMostOnce) {
+                eprintln!("Not subscribed to {}: {}", announced.ping, err);
+            }
+        };
+        let (ping, pong) = (topics.ping.clone(), topics.pong.clone());
+        let answer = move |client: &Client, publish: &Publish| {
+            if rumqttc::matches(&publish.topic, &ping) {
+                let _ = client.try_publish(
+                    pong.as_str(),
+                    QoS::AtMostOnce,
+                    false,
+                    publish.payload.to_vec(),
+                );
+            }
+        };
+
+        StatusReporter {
+            connection: MqttConnection::spawn(options, STOP_TIMEOUT, announce, answer),
+            client_id,
+            station,
+            topics,
+            interval: Duration::from_secs(5),
+            last_stats: None,
+            stopped: false,
+        }
+    }
+
+    // how often `tick` publishes stats, every 5 seconds by default
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    pub fn topics(&self) -> &StatusTopics {
+        &self.topics
+    }
+
+    // meant to be called from the loop driving the pipeline, stats go out once
+    // the interval has passed
+    pub fn tick(&mut self, pipeline: &gst::Pipeline) {
+        let due = match self.last_stats {
+            Some((at, _)) => at.elapsed() >= self.interval,
+            None => true,
+        };
+        if due {
+            let stats = self.stats(pipeline);
+            self.publish(&self.topics.stats, stats);
+        }
+    }
+
+    // state, position and frame counts, the frame rate being the one since the
+    // previous call
+    pub fn stats(&mut self, pipeline: &gst::Pipeline) -> Value {
+        let now = Instant::now();
+        let (rendered, dropped) = sink_stats(pipeline.upcast_ref()).unwrap_or((0, 0));
+
+        let fps = match self.last_stats {
+            Some((at, previous)) if rendered >= previous => {
+                let elapsed = now.duration_since(at).as_secs_f64();
+                if elapsed > 0. {
+                    json!((rendered - previous) as f64 / elapsed)
+                } else {
+                    Value::Null
+                }
+            }
+            _ => Value::Null,
+        };
+        self.last_stats = Some((now, rendered));
+
+        json!({
+            "ClientId": self.client_id,
+            "StationId": self.station,
+            "Time": unix_ms(),
+            "State": format!("{:?}", pipeline.get_current_state()),
+            "Position": seconds(pipeline.query_position::<gst::ClockTime>()),
+            "Duration": seconds(pipeline.query_duration::<gst::ClockTime>()),
+            "Fps": fps,
+            "Rendered": rendered,
+            "Dropped": dropped,
+        })
+    }
+
+    // e.g. event("state-changed", json!({"Old": "Paused", "New": "Playing"}))
+    pub fn event(&self, kind: &str, data: Value) {
+        let event = json!({
+            "ClientId": self.client_id,
+            "StationId": self.station,
+            "Time": unix_ms(),
+            "Event": kind,
+            "Data": data,
+        });
+        self.publish(&self.topics.events, event);
+    }
+
+    pub fn error(&self, src: &str, message: &str, debug: Option<&str>) {
+        self.event(
+            "error",
+            json!({"Source": src, "Message": message, "Debug": debug}),
+        );
+    }
+
+    // dropped while disconnected, there is nobody to tell anyway
+    fn publish(&self, topic: &str, payload: Value) {
+        let _ = self.connection.client().try_publish(
+            topic,
+            QoS::AtMostOnce,
+            false,
+            payload.to_string(),
+        );
+    }
+
+    // says goodbye on the presence topic, the last will only covers crashes
+    pub fn stop(&mut self) {
+        if self.stopped {
+            return;
+        }
+        self.stopped = true;
+
+        let _ = self.connection.client().try_publish(
+            self.topics.presence.as_str(),
+            QoS::AtLeastOnce,
+            false,
+            presence(&self.client_id, self.station, false),
+        );
+        self.connection.stop();
+    }
+}
+
+impl Drop for StatusReporter {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
@@ -0,0 +1,845 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+use anyhow::Error;
+use derive_more::{Display, Error};
+
+use crate::{new_layout, OverlayElement};
+
+// the script resolution ASS assumes when PlayResX/Y are missing
+const DEFAULT_PLAY_RES: (f64, f64) = (384., 288.);
+
+#[derive(Debug, Display, Error)]
+#[display(fmt = "Invalid subtitle timestamp {}", _0)]
+pub struct InvalidTimestamp(#[error(not(source))] pub String);
+
+#[derive(Debug, Display, Error)]
+#[display(fmt = "Unknown subtitle format {}", _0)]
+pub struct UnknownFormat(#[error(not(source))] pub String);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubtitleFormat {
+    Srt,
+    WebVtt,
+    Ass,
+}
+
+impl SubtitleFormat {
+    // by file extension, .ssa files are read as ASS
+    pub fn from_path(path: &Path) -> Option<SubtitleFormat> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "srt" => Some(SubtitleFormat::Srt),
+            "vtt" => Some(SubtitleFormat::WebVtt),
+            "ass" | "ssa" => Some(SubtitleFormat::Ass),
+            _ => None,
+        }
+    }
+}
+
+// One subtitle. `alignment` is numpad style as in ASS: 1 to 3 along the bottom,
+// 4 to 6 across the middle and 7 to 9 along the top. `position` is the point the
+// text is aligned to, in fractions of the frame.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cue {
+    pub start: f64,
+    pub end: f64,
+    pub text: String,
+    pub alignment: u8,
+    pub position: Option<(f64, f64)>,
+    pub rgba: Option<(f64, f64, f64, f64)>,
+}
+
+impl Cue {
+    fn new(start: f64, end: f64) -> Cue {
+        Cue {
+            start,
+            end,
+            text: String::new(),
+            alignment: 2,
+            position: None,
+            rgba: None,
+        }
+    }
+}
+
+// how cues without styling of their own are drawn
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SubtitleStyle {
+    pub rgba: (f64, f64, f64, f64),
+    // drawn behind every line, like textoverlay's shaded background
+    pub background: Option<(f64, f64, f64, f64)>,
+    // distance from the frame edges in fractions of the frame
+    pub margin: f64,
+}
+
+impl Default for SubtitleStyle {
+    fn default() -> Self {
+        SubtitleStyle {
+            rgba: (1., 1., 1., 1.),
+            background: Some((0., 0., 0., 0.5)),
+            margin: 0.05,
+        }
+    }
+}
+
+// h:mm:ss.fff, mm:ss.fff, the decimal separator may be a comma as in SRT
+fn parse_timestamp(timestamp: &str) -> Result<f64, Error> {
+    let invalid = || InvalidTimestamp(timestamp.to_string());
+
+    let mut seconds = 0.;
+    for part in timestamp.trim().split(':') {
+        let value: f64 = part.replace(',', ".").parse().map_err(|_| invalid())?;
+        seconds = seconds * 60. + value;
+    }
+
+    if !seconds.is_finite() || seconds < 0. {
+        return Err(invalid().into());
+    }
+    Ok(seconds)
+}
+
+// hh:mm:ss,mmm for SRT, hh:mm:ss.mmm for WebVTT
+fn format_timestamp(seconds: f64, separator: char) -> String {
+    let ms = (seconds.max(0.) * 1000.).round() as u64;
+    format!(
+        "{:02}:{:02}:{:02}{}{:03}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        separator,
+        ms % 1000
+    )
+}
+
+// a blank line would end an SRT or WebVTT cue early
+fn without_blank_lines(text: &str) -> String {
+    text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// "#rrggbb" as used by <font color>
+fn parse_html_color(color: &str) -> Option<(f64, f64, f64, f64)> {
+    let hex = color.trim().trim_matches('"').strip_prefix('#')?;
+    if hex.len() != 6 {
+        return None;
+    }
+
+    let value = u32::from_str_radix(hex, 16).ok()?;
+    let channel = |shift: u32| f64::from((value >> shift) & 0xff) / 255.;
+    Some((channel(16), channel(8), channel(0), 1.))
+}
+
+// &HAABBGGRR or &HBBGGRR, alpha 0 being opaque
+fn parse_ass_color(color: &str) -> Option<(f64, f64, f64, f64)> {
+    let hex = color
+        .trim()
+        .trim_start_matches('&')
+        .trim_start_matches(['H', 'h'])
+        .trim_end_matches('&');
+
+    let value = u32::from_str_radix(hex, 16).ok()?;
+    let channel = |shift: u32| f64::from((value >> shift) & 0xff) / 255.;
+    Some((channel(0), channel(8), channel(16), 1. - channel(24)))
+}
+
+fn decode_entities(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+}
+
+// Drops <b>, <i>, <font> and other tags, keeping the colour of the first <font>.
+// Bold, italic and underline can't be shown by plain text elements.
+fn strip_tags(text: &str, cue: &mut Cue) -> String {
+    let mut plain = String::new();
+    let mut rest = text;
+
+    while let Some(open) = rest.find('<') {
+        plain.push_str(&rest[..open]);
+        let close = match rest[open..].find('>') {
+            Some(close) => open + close,
+            None => {
+                rest = &rest[open..];
+                break;
+            }
+        };
+
+        let tag = &rest[open + 1..close];
+        if let Some(color) = tag.to_lowercase().strip_prefix("font color=") {
+            if cue.rgba.is_none() {
+                cue.rgba = parse_html_color(color);
+            }
+        }
+        rest = &rest[close + 1..];
+    }
+    plain.push_str(rest);
+
+    plain
+}
+
+// applies and removes {...} override blocks, from ASS and often found in SRT too
+fn apply_overrides(text: &str, cue: &mut Cue, play_res: (f64, f64)) -> String {
+    let mut plain = String::new();
+    let mut rest = text;
+
+    while let Some(open) = rest.find('{') {
+        plain.push_str(&rest[..open]);
+        let close = match rest[open..].find('}') {
+            Some(close) => open + close,
+            None => {
+                rest = &rest[open..];
+                break;
+            }
+        };
+
+        for tag in rest[open + 1..close].split('\\').map(str::trim) {
+            if let Some(alignment) = tag.strip_prefix("an") {
+                if let Ok(alignment @ 1..=9) = alignment.parse::<u8>() {
+                    cue.alignment = alignment;
+                }
+            } else if let Some(pos) = tag.strip_prefix("pos(") {
+                let mut coords = pos.trim_end_matches(')').split(',').map(str::trim);
+                if let (Some(Ok(x)), Some(Ok(y))) = (
+                    coords.next().map(str::parse::<f64>),
+                    coords.next().map(str::parse::<f64>),
+                ) {
+                    cue.position = Some((x / play_res.0, y / play_res.1));
+                }
+            } else if let Some(color) = tag.strip_prefix("1c").or_else(|| tag.strip_prefix('c')) {
+                if let Some(rgba) = parse_ass_color(color) {
+                    cue.rgba = Some(rgba);
+                }
+            }
+        }
+        rest = &rest[close + 1..];
+    }
+    plain.push_str(rest);
+
+    plain
+}
+
+// The cues of a subtitle file, turned into timed text elements by `to_elements`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Subtitles {
+    pub cues: Vec<Cue>,
+}
+
+impl Subtitles {
+    pub fn parse(text: &str, format: SubtitleFormat) -> Result<Subtitles, Error> {
+        let text = text.trim_start_matches('\u{feff}');
+        match format {
+            SubtitleFormat::Srt => Subtitles::from_srt(text),
+            SubtitleFormat::WebVtt => Subtitles::from_vtt(text),
+            SubtitleFormat::Ass => Subtitles::from_ass(text),
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Subtitles, Error> {
+        let path = path.as_ref();
+        let format = SubtitleFormat::from_path(path)
+            .ok_or_else(|| UnknownFormat(path.display().to_string()))?;
+
+        Subtitles::parse(&fs::read_to_string(path)?, format)
+    }
+
+    // Blocks separated by blank lines, the timing line followed by the text. Index
+    // lines, WEBVTT headers, NOTE and STYLE blocks have no timing line and are skipped.
+    fn blocks(text: &str) -> Vec<Vec<&str>> {
+        let mut blocks = vec![Vec::new()];
+        for line in text.lines() {
+            if line.trim().is_empty() {
+                blocks.push(Vec::new());
+            } else {
+                blocks.last_mut().unwrap().push(line);
+            }
+        }
+
+        blocks
+            .into_iter()
+            .filter_map(|block| {
+                let timing = block.iter().position(|line| line.contains("-->"))?;
+                Some(block[timing..].to_vec())
+            })
+            .collect()
+    }
+
+    fn timing(line: &str) -> Result<(f64, f64, &str), Error> {
+        let (start, rest) = line.split_at(line.find("-->").unwrap());
+        let rest = rest[3..].trim_start();
+        let (end, settings) = rest.split_at(rest.find(char::is_whitespace).unwrap_or(rest.len()));
+
+        Ok((parse_timestamp(start)?, parse_timestamp(end)?, settings))
+    }
+
+    pub fn from_srt(text: &str) -> Result<Subtitles, Error> {
+        let mut cues = Vec::new();
+
+        for block in Subtitles::blocks(text) {
+            let (start, end, _) = Subtitles::timing(block[0])?;
+            let mut cue = Cue::new(start, end);
+
+            let text = block[1..].join("\n");
+            // SRT has no script resolution, players resolve \pos like ASS does
+            let text = apply_overrides(&text, &mut cue, DEFAULT_PLAY_RES);
+            cue.text = decode_entities(&strip_tags(&text, &mut cue));
+            cues.push(cue);
+        }
+
+        Ok(Subtitles { cues })
+    }
+
+    pub fn from_vtt(text: &str) -> Result<Subtitles, Error> {
+        let mut cues = Vec::new();
+
+        for block in Subtitles::blocks(text) {
+            let (start, end, settings) = Subtitles::timing(block[0])?;
+            let mut cue = Cue::new(start, end);
+
+            // only percentages are honoured, line numbers depend on the renderer
+            let percent = |value: &str| {
+                let value = value.split(',').next()?.strip_suffix('%')?;
+                value.parse::<f64>().ok().map(|value| value / 100.)
+            };
+            let (mut x, mut y) = (None, None);
+            let mut column = 1;
+            for setting in settings.split_whitespace() {
+                let (name, value) = match setting.find(':') {
+                    Some(idx) => (&setting[..idx], &setting[idx + 1..]),
+                    None => continue,
+                };
+                match name {
+                    "position" => x = percent(value),
+                    "line" => y = percent(value),
+                    "align" => {
+                        column = match value {
+                            "start" | "left" => 0,
+                            "end" | "right" => 2,
+                            _ => 1,
+                        }
+                    }
+                    _ => (),
+                }
+            }
+
+            // a line near the top aligns the text's top edge, as in a browser
+            let row = match y {
+                Some(y) if y < 0.5 => 6,
+                _ => 0,
+            };
+            cue.alignment = row + column + 1;
+            if x.is_some() || y.is_some() {
+                let default_x = [0.05, 0.5, 0.95][column as usize];
+                cue.position = Some((x.unwrap_or(default_x), y.unwrap_or(0.95)));
+            }
+
+            cue.text = decode_entities(&strip_tags(&block[1..].join("\n"), &mut cue));
+            cues.push(cue);
+        }
+
+        Ok(Subtitles { cues })
+    }
+
+    // [Script Info] PlayResX/Y, the colour and alignment of [V4+ Styles] and the
+    // Dialogue lines of [Events] with \pos, \an and \c overrides
+    pub fn from_ass(text: &str) -> Result<Subtitles, Error> {
+        let mut section = String::new();
+        let mut play_res = DEFAULT_PLAY_RES;
+        let mut style_format: Vec<String> = Vec::new();
+        let mut event_format: Vec<String> = Vec::new();
+        let mut styles: HashMap<String, (Option<(f64, f64, f64, f64)>, u8)> = HashMap::new();
+        let mut cues = Vec::new();
+
+        for line in text.lines() {
+            let line = line.trim();
+            if line.starts_with('[') {
+                section = line.to_lowercase();
+                continue;
+            }
+
+            let (key, value) = match line.find(':') {
+                Some(idx) => (line[..idx].trim(), line[idx + 1..].trim()),
+                None => continue,
+            };
+            let fields = |format: &[String]| -> Vec<String> {
+                value
+                    .splitn(format.len().max(1), ',')
+                    .map(|field| field.trim().to_string())
+                    .collect()
+            };
+            let field = |format: &[String], values: &[String], name: &str| {
+                format
+                    .iter()
+                    .position(|f| f.eq_ignore_ascii_case(name))
+                    .and_then(|idx| values.get(idx).cloned())
+            };
+
+            match (section.as_str(), key) {
+                ("[script info]", "PlayResX") => play_res.0 = value.parse().unwrap_or(play_res.0),
+                ("[script info]", "PlayResY") => play_res.1 = value.parse().unwrap_or(play_res.1),
+                ("[v4+ styles]", "Format") | ("[v4 styles]", "Format") => {
+                    style_format = value.split(',').map(|f| f.trim().to_string()).collect();
+                }
+                ("[v4+ styles]", "Style") | ("[v4 styles]", "Style") => {
+                    let values = fields(&style_format);
+                    let name = field(&style_format, &values, "Name").unwrap_or_default();
+                    let rgba = field(&style_format, &values, "PrimaryColour")
+                        .and_then(|color| parse_ass_color(&color));
+                    let alignment = field(&style_format, &values, "Alignment")
+                        .and_then(|alignment| alignment.parse::<u8>().ok())
+                        .unwrap_or(2);
+
+                    // SSA numbers the middle row 9 to 11 and the top row 5 to 7
+                    let alignment = if section == "[v4 styles]" {
+                        match alignment {
+                            5..=7 => alignment + 2,
+                            9..=11 => alignment - 5,
+                            _ => alignment,
+                        }
+                    } else {
+                        alignment
+                    };
+                    styles.insert(name, (rgba, alignment.clamp(1, 9)));
+                }
+                ("[events]", "Format") => {
+                    event_format = value.split(',').map(|f| f.trim().to_string()).collect();
+                }
+                ("[events]", "Dialogue") => {
+                    let values = fields(&event_format);
+                    let timestamp = |name| {
+                        field(&event_format, &values, name)
+                            .ok_or_else(|| InvalidTimestamp(line.to_string()))
+                    };
+                    let start = parse_timestamp(&timestamp("Start")?)?;
+                    let end = parse_timestamp(&timestamp("End")?)?;
+                    let mut cue = Cue::new(start, end);
+
+                    let style = field(&event_format, &values, "Style").unwrap_or_default();
+                    let style = styles.get(style.trim_start_matches('*'));
+                    if let Some(&(rgba, alignment)) = style.or_else(|| styles.get("Default")) {
+                        cue.rgba = rgba;
+                        cue.alignment = alignment;
+                    }
+
+                    let text = field(&event_format, &values, "Text").unwrap_or_default();
+                    let text = apply_overrides(&text, &mut cue, play_res);
+                    cue.text = text
+                        .replace("\\N", "\n")
+                        .replace("\\n", "\n")
+                        .replace("\\h", "\u{a0}");
+                    cues.push(cue);
+                }
+                _ => (),
+            }
+        }
+
+        cues.sort_by(|a, b| a.start.partial_cmp(&b.start).unwrap());
+        Ok(Subtitles { cues })
+    }
+
+    // The text elements of a scene as cues. Elements shown over the same window
+    // become the lines of one cue, top to bottom. Styling and positions are left out.
+    pub fn from_elements(elements: &[OverlayElement]) -> Subtitles {
+        let mut texts: Vec<_> = elements
+            .iter()
+            .filter_map(|ele| Some((ele.present_time(), ele.position().1, ele.content()?)))
+            .filter(|(_, _, content)| !content.trim().is_empty())
+            .collect();
+        texts.sort_by(|a, b| {
+            let key = |text: &((f64, f64), f64, &str)| ((text.0).0, (text.0).1, text.1);
+            key(a)
+                .partial_cmp(&key(b))
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        let mut cues: Vec<Cue> = Vec::new();
+        for ((start, end), _, content) in texts {
+            match cues.last_mut() {
+                Some(cue) if cue.start == start && cue.end == end => {
+                    cue.text.push('\n');
+                    cue.text.push_str(content);
+                }
+                _ => {
+                    let mut cue = Cue::new(start, end);
+                    cue.text = content.to_string();
+                    cues.push(cue);
+                }
+            }
+        }
+
+        Subtitles { cues }
+    }
+
+    pub fn to_srt(&self) -> String {
+        let mut srt = String::new();
+        for (index, cue) in self.cues.iter().enumerate() {
+            let _ = write!(
+                srt,
+                "{}\n{} --> {}\n{}\n\n",
+                index + 1,
+                format_timestamp(cue.start, ','),
+                format_timestamp(cue.end, ','),
+                without_blank_lines(&cue.text)
+            );
+        }
+
+        srt
+    }
+
+    pub fn to_vtt(&self) -> String {
+        let mut vtt = String::from("WEBVTT\n\n");
+        for cue in &self.cues {
+            let text = cue
+                .text
+                .replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;");
+
+            let _ = write!(
+                vtt,
+                "{} --> {}\n{}\n\n",
+                format_timestamp(cue.start, '.'),
+                format_timestamp(cue.end, '.'),
+                without_blank_lines(&text)
+            );
+        }
+
+        vtt
+    }
+
+    // SRT or WebVTT depending on the extension of `path`
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let path = path.as_ref();
+        let content = match SubtitleFormat::from_path(path) {
+            Some(SubtitleFormat::Srt) => self.to_srt(),
+            Some(SubtitleFormat::WebVtt) => self.to_vtt(),
+            _ => return Err(UnknownFormat(path.display().to_string()).into()),
+        };
+
+        fs::write(path, content)?;
+        Ok(())
+    }
+
+    // Every line of every cue as a text element of its own, so lines of a centred
+    // cue are centred on their own. Text is measured with the font the backends use.
+    pub fn to_elements(
+        &self,
+        width: f64,
+        height: f64,
+        style: &SubtitleStyle,
+    ) -> Vec<OverlayElement> {
+        let layout = new_layout();
+        let mut elements = Vec::new();
+
+        for cue in &self.cues {
+            let alignment = cue.alignment.clamp(1, 9);
+            let column = f64::from((alignment - 1) % 3) / 2.;
+            let row = match alignment {
+                7..=9 => 0.,
+                4..=6 => 0.5,
+                _ => 1.,
+            };
+            let (anchor_x, anchor_y) = cue.position.unwrap_or((
+                style.margin + column * (1. - 2. * style.margin),
+                style.margin + row * (1. - 2. * style.margin),
+            ));
+
+            let lines: Vec<_> = cue
+                .text
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .collect();
+            let sizes: Vec<_> = lines
+                .iter()
+                .map(|line| {
+                    layout.set_text(line);
+                    let (w, h) = layout.get_pixel_size();
+                    (f64::from(w), f64::from(h))
+                })
+                .collect();
+
+            let total: f64 = sizes.iter().map(|size| size.1).sum();
+            let mut y = anchor_y * height - total * row;
+            for (line, (w, h)) in lines.iter().zip(sizes) {
+                let x = anchor_x * width - w * column;
+                if let Some(background) = style.background {
+                    elements.push(OverlayElement::create_rectangle(
+                        x - 4.,
+                        y,
+                        w + 8.,
+                        h,
+                        background,
+                        (cue.start, cue.end),
+                    ));
+                }
+                elements.push(OverlayElement::create_text(
+                    line.to_string(),
+                    x,
+                    y,
+                    cue.rgba.unwrap_or(style.rgba),
+                    (cue.start, cue.end),
+                ));
+                y += h;
+            }
+        }
+
+        elements
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: (f64, f64, f64, f64), b: (f64, f64, f64, f64)) -> bool {
+        [(a.0, b.0), (a.1, b.1), (a.2, b.2), (a.3, b.3)]
+            .iter()
+            .all(|(a, b)| (a - b).abs() < 1e-6)
+    }
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(parse_timestamp("00:01:02,500").unwrap(), 62.5);
+        assert_eq!(parse_timestamp("00:01:02.500").unwrap(), 62.5);
+        assert_eq!(parse_timestamp("01:02.25").unwrap(), 62.25);
+        assert_eq!(parse_timestamp(" 1:00:00.000 ").unwrap(), 3600.);
+        assert_eq!(parse_timestamp("0:00:01.50").unwrap(), 1.5);
+
+        assert!(parse_timestamp("").is_err());
+        assert!(parse_timestamp("00:xx:01").is_err());
+        assert!(parse_timestamp("-5").is_err());
+        assert!(parse_timestamp("inf").is_err());
+    }
+
+    #[test]
+    fn ass_colours_are_bgr_with_inverted_alpha() {
+        let red = parse_ass_color("&H0000FF&").unwrap();
+        assert!(close(red, (1., 0., 0., 1.)));
+
+        let blue = parse_ass_color("&H00FF0000").unwrap();
+        assert!(close(blue, (0., 0., 1., 1.)));
+
+        let half = parse_ass_color("&h8000FF00&").unwrap();
+        assert!(close(half, (0., 1., 0., 1. - 128. / 255.)));
+
+        assert_eq!(parse_ass_color("&Hzz&"), None);
+        assert_eq!(parse_ass_color(""), None);
+    }
+
+    #[test]
+    fn tags_are_stripped_keeping_the_first_font_colour() {
+        let mut cue = Cue::new(0., 1.);
+        let text = strip_tags(
+            "<b>bold</b> <font color=\"#ff0000\">red</font> <font color=\"#0000ff\">blue</font>",
+            &mut cue,
+        );
+
+        assert_eq!(text, "bold red blue");
+        assert!(close(cue.rgba.unwrap(), (1., 0., 0., 1.)));
+
+        // an unclosed tag is kept as text
+        assert_eq!(strip_tags("a <i>b</i> <c", &mut cue), "a b <c");
+    }
+
+    #[test]
+    fn overrides_set_alignment_position_and_colour() {
+        let mut cue = Cue::new(0., 1.);
+        let text = apply_overrides(
+            r"{\an8\pos(192,72)}top{\i1} text{\c&H00FF00&}",
+            &mut cue,
+            (384., 288.),
+        );
+
+        assert_eq!(text, "top text");
+        assert_eq!(cue.alignment, 8);
+        assert_eq!(cue.position, Some((0.5, 0.25)));
+        assert!(close(cue.rgba.unwrap(), (0., 1., 0., 1.)));
+
+        let mut cue = Cue::new(0., 1.);
+        apply_overrides(r"{\an0\1c&HFF0000&}x", &mut cue, (1., 1.));
+        assert_eq!(cue.alignment, 2);
+        assert!(close(cue.rgba.unwrap(), (0., 0., 1., 1.)));
+
+        assert_eq!(apply_overrides("a {b", &mut cue, (1., 1.)), "a {b");
+    }
+
+    #[test]
+    fn vtt_settings_place_the_cue() {
+        let vtt = "WEBVTT\n\n\
+            00:00:01.000 --> 00:00:02.000 position:10% line:20% align:start\nTop left\n\n\
+            00:00:02.000 --> 00:00:03.000 line:80%\nLow\n\n\
+            00:00:03.000 --> 00:00:04.000 align:end line:-1\nRight\n";
+        let cues = Subtitles::from_vtt(vtt).unwrap().cues;
+        assert_eq!(cues.len(), 3);
+
+        assert_eq!((cues[0].start, cues[0].end), (1., 2.));
+        assert_eq!(cues[0].text, "Top left");
+        assert_eq!(cues[0].alignment, 7);
+        assert_eq!(cues[0].position, Some((0.1, 0.2)));
+
+        assert_eq!(cues[1].alignment, 2);
+        assert_eq!(cues[1].position, Some((0.5, 0.8)));
+
+        // line numbers are left to the default placement
+        assert_eq!(cues[2].alignment, 3);
+        assert_eq!(cues[2].position, None);
+    }
+
+    #[test]
+    fn ssa_alignment_is_remapped() {
+        let ssa = "[Script Info]\nScriptType: v4.00\n\n\
+            [V4 Styles]\nFormat: Name, PrimaryColour, Alignment\n\
+            Style: Top, &H0000FF, 6\nStyle: Middle, &H0000FF, 9\nStyle: Bottom, &H0000FF, 3\n\n\
+            [Events]\nFormat: Marked, Start, End, Style, Text\n\
+            Dialogue: Marked=0,0:00:01.00,0:00:02.00,Top,a\n\
+            Dialogue: Marked=0,0:00:01.00,0:00:02.00,Middle,b\n\
+            Dialogue: Marked=0,0:00:01.00,0:00:02.00,Bottom,c, with a comma\n";
+        let cues = Subtitles::parse(ssa, SubtitleFormat::Ass).unwrap().cues;
+
+        let alignments: Vec<_> = cues.iter().map(|cue| cue.alignment).collect();
+        assert_eq!(alignments, vec![8, 4, 3]);
+        assert_eq!(cues[2].text, "c, with a comma");
+        assert!(close(cues[0].rgba.unwrap(), (1., 0., 0., 1.)));
+    }
+
+    #[test]
+    fn ass_keeps_numpad_alignment() {
+        let ass = "[V4+ Styles]\nFormat: Name, Alignment\nStyle: Default, 9\n\n\
+            [Events]\nFormat: Layer, Start, End, Style, Text\n\
+            Dialogue: 0,0:00:02.00,0:00:03.00,Default,second\n\
+            Dialogue: 0,0:00:01.00,0:00:02.00,*Default,first\\Nline\n";
+        let cues = Subtitles::parse(ass, SubtitleFormat::Ass).unwrap().cues;
+
+        assert_eq!(cues[0].text, "first\nline");
+        assert_eq!(cues[0].alignment, 9);
+        assert_eq!(cues[1].start, 2.);
+    }
+
+    #[test]
+    fn byte_order_mark_is_skipped() {
+        let srt = "\u{feff}1\n00:00:01,000 --> 00:00:02,500\n<i>Hi</i> &amp; bye\n";
+        let cues = Subtitles::parse(srt, SubtitleFormat::Srt).unwrap().cues;
+        assert_eq!(cues.len(), 1);
+        assert_eq!(cues[0].text, "Hi & bye");
+        assert_eq!((cues[0].start, cues[0].end), (1., 2.5));
+
+        // the section header on the first line is only found without it
+        let ass = "\u{feff}[Script Info]\nPlayResX: 1920\nPlayResY: 1080\n\n\
+            [Events]\nFormat: Layer, Start, End, Style, Text\n\
+            Dialogue: 0,0:00:01.00,0:00:02.00,Default,{\\pos(960,540)}centre\n";
+        let cues = Subtitles::parse(ass, SubtitleFormat::Ass).unwrap().cues;
+        assert_eq!(cues[0].position, Some((0.5, 0.5)));
+    }
+
+    #[test]
+    fn timestamps_are_rounded_to_milliseconds() {
+        assert_eq!(format_timestamp(0., ','), "00:00:00,000");
+        assert_eq!(format_timestamp(1.0004, ','), "00:00:01,000");
+        assert_eq!(format_timestamp(1.0006, '.'), "00:00:01.001");
+        assert_eq!(format_timestamp(59.9996, ','), "00:01:00,000");
+        assert_eq!(format_timestamp(3599.9999, '.'), "01:00:00.000");
+        assert_eq!(format_timestamp(36_000. + 62.5, ','), "10:01:02,500");
+        assert_eq!(format_timestamp(-1., ','), "00:00:00,000");
+    }
+
+    fn text(content: &str, y: f64, present_time: (f64, f64)) -> OverlayElement {
+        OverlayElement::create_text(content.to_string(), 0., y, (1., 1., 1., 1.), present_time)
+    }
+
+    #[test]
+    fn elements_are_grouped_by_window_and_ordered_by_height() {
+        let elements = vec![
+            text("second", 20., (3., 4.)),
+            text("bottom", 40., (1., 2.)),
+            OverlayElement::create_rectangle(0., 0., 10., 10., (0., 0., 0., 1.), (1., 2.)),
+            text("  ", 30., (1., 2.)),
+            text("top", 10., (1., 2.)),
+        ];
+        let cues = Subtitles::from_elements(&elements).cues;
+
+        let texts: Vec<_> = cues.iter().map(|cue| cue.text.as_str()).collect();
+        assert_eq!(texts, vec!["top\nbottom", "second"]);
+        assert_eq!((cues[0].start, cues[0].end), (1., 2.));
+        assert_eq!((cues[1].start, cues[1].end), (3., 4.));
+    }
+
+    fn two_cues() -> Subtitles {
+        let mut first = Cue::new(1., 2.5);
+        first.text = "a\n\nb".to_string();
+        let mut second = Cue::new(3661.001, 3662.);
+        second.text = "x < y & z".to_string();
+        Subtitles {
+            cues: vec![first, second],
+        }
+    }
+
+    #[test]
+    fn srt_output_drops_blank_lines() {
+        assert_eq!(
+            two_cues().to_srt(),
+            "1\n00:00:01,000 --> 00:00:02,500\na\nb\n\n\
+             2\n01:01:01,001 --> 01:01:02,000\nx < y & z\n\n"
+        );
+    }
+
+    #[test]
+    fn vtt_output_escapes_markup() {
+        assert_eq!(
+            two_cues().to_vtt(),
+            "WEBVTT\n\n\
+             00:00:01.000 --> 00:00:02.500\na\nb\n\n\
+             01:01:01.001 --> 01:01:02.000\nx &lt; y &amp; z\n\n"
+        );
+    }
+
+    #[test]
+    fn written_subtitles_read_back() {
+        let subtitles = two_cues();
+        for (text, format) in &[
+            (subtitles.to_srt(), SubtitleFormat::Srt),
+            (subtitles.to_vtt(), SubtitleFormat::WebVtt),
+        ] {
+            let cues = Subtitles::parse(text, *format).unwrap().cues;
+            assert_eq!(cues.len(), 2);
+            assert_eq!(cues[0].text, "a\nb");
+            assert_eq!(cues[1].text, "x < y & z");
+            assert_eq!((cues[1].start, cues[1].end), (3661.001, 3662.));
+        }
+    }
+
+    #[test]
+    fn srt_positions_use_the_default_play_res() {
+        let srt = "1\n00:00:01,000 --> 00:00:02,000\n{\\pos(192,72)}{\\an7}top\n";
+        let cues = Subtitles::from_srt(srt).unwrap().cues;
+        assert_eq!(cues[0].text, "top");
+        assert_eq!(cues[0].position, Some((0.5, 0.25)));
+        assert_eq!(cues[0].alignment, 7);
+    }
+
+    #[test]
+    fn alignment_out_of_range_is_clamped() {
+        let mut cue = Cue::new(0., 1.);
+        cue.text = "x".to_string();
+        let style = SubtitleStyle {
+            background: None,
+            ..SubtitleStyle::default()
+        };
+
+        let place = |alignment| {
+            let subtitles = Subtitles {
+                cues: vec![Cue {
+                    alignment,
+                    ..cue.clone()
+                }],
+            };
+            subtitles.to_elements(640., 480., &style)[0].position()
+        };
+        assert_eq!(place(0), place(1));
+        assert_eq!(place(200), place(9));
+    }
+}
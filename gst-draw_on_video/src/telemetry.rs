@@ -0,0 +1,361 @@
+use std::convert::TryFrom;
+use std::fs;
+use std::path::Path;
+
+use anyhow::Error;
+use derive_more::{Display, Error};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Display, Error)]
+#[display(fmt = "No GPS samples")]
+pub struct EmptyLog;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SpeedUnit {
+    MetersPerSecond,
+    KilometersPerHour,
+    MilesPerHour,
+    Knots,
+}
+
+impl SpeedUnit {
+    fn meters_per_second(self) -> f64 {
+        match self {
+            SpeedUnit::MetersPerSecond => 1.,
+            SpeedUnit::KilometersPerHour => 1000. / 3600.,
+            SpeedUnit::MilesPerHour => 1609.344 / 3600.,
+            SpeedUnit::Knots => 1852. / 3600.,
+        }
+    }
+
+    pub fn convert(self, speed: f64, to: SpeedUnit) -> f64 {
+        speed * self.meters_per_second() / to.meters_per_second()
+    }
+
+    pub fn suffix(self) -> &'static str {
+        match self {
+            SpeedUnit::MetersPerSecond => "m/s",
+            SpeedUnit::KilometersPerHour => "km/h",
+            SpeedUnit::MilesPerHour => "mph",
+            SpeedUnit::Knots => "kn",
+        }
+    }
+}
+
+// altitude and position error, both are logged in meters
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DistanceUnit {
+    Meters,
+    Feet,
+}
+
+impl DistanceUnit {
+    pub fn convert(self, meters: f64) -> f64 {
+        match self {
+            DistanceUnit::Meters => meters,
+            DistanceUnit::Feet => meters / 0.3048,
+        }
+    }
+
+    pub fn suffix(self) -> &'static str {
+        match self {
+            DistanceUnit::Meters => "m",
+            DistanceUnit::Feet => "ft",
+        }
+    }
+}
+
+// how telemetry is displayed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Units {
+    pub speed: SpeedUnit,
+    pub distance: DistanceUnit,
+}
+
+impl Default for Units {
+    fn default() -> Self {
+        Units {
+            speed: SpeedUnit::KilometersPerHour,
+            distance: DistanceUnit::Meters,
+        }
+    }
+}
+
+// one entry of the "GPS" array of gps.json, `log_time` is in unix milliseconds
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GpsSample {
+    pub log_time: u64,
+    pub lat: f64,
+    pub lon: f64,
+    pub alt: f64,
+    pub speed: f64,
+    #[serde(default)]
+    pub speed_accu: f64,
+    #[serde(default)]
+    pub error: f64,
+}
+
+impl GpsSample {
+    // linear between `self` and `next`, `t` going from 0 to 1
+    fn lerp(&self, next: &GpsSample, t: f64) -> GpsSample {
+        let mix = |a: f64, b: f64| a + (b - a) * t;
+
+        GpsSample {
+            log_time: self.log_time + ((next.log_time - self.log_time) as f64 * t) as u64,
+            lat: mix(self.lat, next.lat),
+            lon: mix(self.lon, next.lon),
+            alt: mix(self.alt, next.alt),
+            speed: mix(self.speed, next.speed),
+            speed_accu: mix(self.speed_accu, next.speed_accu),
+            error: mix(self.error, next.error),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct GpsFile {
+    #[serde(rename = "GPS")]
+    gps: Vec<GpsSample>,
+}
+
+// The samples of a gps.json log ordered by time, one per log time, never empty.
+// The log doesn't say which unit speeds are in, they are taken as meters per
+// second like the distances unless `with_speed_unit` says otherwise.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "UncheckedLog")]
+pub struct GpsLog {
+    samples: Vec<GpsSample>,
+    speed_unit: SpeedUnit,
+}
+
+// a deserialized log before `GpsLog::new` has sorted and checked the samples
+#[derive(Deserialize)]
+struct UncheckedLog {
+    samples: Vec<GpsSample>,
+    speed_unit: SpeedUnit,
+}
+
+impl TryFrom<UncheckedLog> for GpsLog {
+    type Error = Error;
+
+    fn try_from(log: UncheckedLog) -> Result<GpsLog, Error> {
+        Ok(GpsLog::new(log.samples)?.with_speed_unit(log.speed_unit))
+    }
+}
+
+impl GpsLog {
+    pub fn new(mut samples: Vec<GpsSample>) -> Result<GpsLog, Error> {
+        // the logger repeats samples, the last one of a log time wins
+        samples.sort_by_key(|sample| sample.log_time);
+        samples.reverse();
+        samples.dedup_by_key(|sample| sample.log_time);
+        samples.reverse();
+
+        if samples.is_empty() {
+            return Err(EmptyLog.into());
+        }
+
+        Ok(GpsLog {
+            samples,
+            speed_unit: SpeedUnit::MetersPerSecond,
+        })
+    }
+
+    pub fn from_json(json: &str) -> Result<GpsLog, Error> {
+        let file: GpsFile = serde_json::from_str(json)?;
+        GpsLog::new(file.gps)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<GpsLog, Error> {
+        GpsLog::from_json(&fs::read_to_string(path)?)
+    }
+
+    pub fn with_speed_unit(mut self, unit: SpeedUnit) -> GpsLog {
+        self.speed_unit = unit;
+        self
+    }
+
+    pub fn speed_unit(&self) -> SpeedUnit {
+        self.speed_unit
+    }
+
+    pub fn samples(&self) -> &[GpsSample] {
+        &self.samples
+    }
+
+    pub fn start(&self) -> u64 {
+        self.samples[0].log_time
+    }
+
+    pub fn end(&self) -> u64 {
+        self.samples[self.samples.len() - 1].log_time
+    }
+
+    // interpolated sample at a log time, None outside of the log
+    pub fn sample_at(&self, log_time: f64) -> Option<GpsSample> {
+        if log_time < self.start() as f64 || log_time > self.end() as f64 {
+            return None;
+        }
+
+        let next = self
+            .samples
+            .partition_point(|sample| (sample.log_time as f64) <= log_time);
+        if next >= self.samples.len() {
+            return self.samples.last().copied();
+        }
+
+        let (prev, next) = (&self.samples[next - 1], &self.samples[next]);
+        let t = (log_time - prev.log_time as f64) / (next.log_time - prev.log_time) as f64;
+        Some(prev.lerp(next, t))
+    }
+
+    // `offset_ms` is the stream time the first sample was logged at, negative
+    // when the recording started after the logger
+    pub fn sample_at_stream_time(&self, stream_ms: f64, offset_ms: i64) -> Option<GpsSample> {
+        self.sample_at(self.start() as f64 + stream_ms - offset_ms as f64)
+    }
+
+    // speed, position and altitude in `units`, one per line
+    pub fn describe(&self, sample: &GpsSample, units: Units) -> String {
+        let speed = self.speed_unit.convert(sample.speed, units.speed);
+        let lat = if sample.lat < 0. { 'S' } else { 'N' };
+        let lon = if sample.lon < 0. { 'W' } else { 'E' };
+
+        format!(
+            "{:.1} {}\n{:.5}°{} {:.5}°{}\nalt {:.0} {} ±{:.0} {}",
+            speed,
+            units.speed.suffix(),
+            sample.lat.abs(),
+            lat,
+            sample.lon.abs(),
+            lon,
+            units.distance.convert(sample.alt),
+            units.distance.suffix(),
+            units.distance.convert(sample.error),
+            units.distance.suffix()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(log_time: u64, lat: f64, speed: f64) -> GpsSample {
+        GpsSample {
+            log_time,
+            lat,
+            lon: 8.,
+            alt: 100.,
+            speed,
+            speed_accu: 0.,
+            error: 2.,
+        }
+    }
+
+    fn log() -> GpsLog {
+        GpsLog::new(vec![
+            sample(1000, 50., 10.),
+            sample(2000, 51., 20.),
+            sample(3000, 53., 0.),
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn samples_are_sorted_and_the_last_repeat_wins() {
+        let log = GpsLog::new(vec![
+            sample(3000, 3., 0.),
+            sample(1000, 1., 0.),
+            sample(2000, 2., 0.),
+            sample(1000, 1.5, 0.),
+            sample(3000, 3.5, 0.),
+        ])
+        .unwrap();
+
+        let lats: Vec<_> = log.samples().iter().map(|s| s.lat).collect();
+        assert_eq!(lats, vec![1.5, 2., 3.5]);
+        assert_eq!((log.start(), log.end()), (1000, 3000));
+    }
+
+    #[test]
+    fn empty_logs_are_refused() {
+        assert!(GpsLog::new(vec![]).is_err());
+        assert!(GpsLog::from_json(r#"{"GPS": []}"#).is_err());
+
+        let unchecked = r#"{"samples": [], "speed_unit": "Knots"}"#;
+        assert!(serde_json::from_str::<GpsLog>(unchecked).is_err());
+    }
+
+    #[test]
+    fn deserializing_goes_through_new() {
+        let json = serde_json::to_string(&log().with_speed_unit(SpeedUnit::Knots)).unwrap();
+        let log: GpsLog = serde_json::from_str(&json).unwrap();
+        assert_eq!(log.speed_unit(), SpeedUnit::Knots);
+        assert_eq!(log.samples().len(), 3);
+
+        let unsorted = r#"{"samples": [
+            {"logTime": 2000, "lat": 2, "lon": 0, "alt": 0, "speed": 0},
+            {"logTime": 1000, "lat": 1, "lon": 0, "alt": 0, "speed": 0}
+        ], "speed_unit": "MetersPerSecond"}"#;
+        let log: GpsLog = serde_json::from_str(unsorted).unwrap();
+        assert_eq!(log.start(), 1000);
+    }
+
+    #[test]
+    fn samples_are_interpolated_between_log_times() {
+        let log = log();
+
+        let at = log.sample_at(1500.).unwrap();
+        assert_eq!(at.log_time, 1500);
+        assert!((at.lat - 50.5).abs() < 1e-9);
+        assert!((at.speed - 15.).abs() < 1e-9);
+
+        let at = log.sample_at(2750.).unwrap();
+        assert!((at.lat - 52.5).abs() < 1e-9);
+        assert!((at.speed - 5.).abs() < 1e-9);
+
+        assert_eq!(log.sample_at(2000.), Some(log.samples()[1]));
+    }
+
+    #[test]
+    fn nothing_outside_of_the_log() {
+        let log = log();
+
+        assert_eq!(log.sample_at(999.9), None);
+        assert_eq!(log.sample_at(3000.1), None);
+        assert_eq!(log.sample_at(1000.), Some(log.samples()[0]));
+        assert_eq!(log.sample_at(3000.), Some(log.samples()[2]));
+
+        let single = GpsLog::new(vec![sample(5000, 1., 1.)]).unwrap();
+        assert_eq!(single.sample_at(5000.), Some(single.samples()[0]));
+        assert_eq!(single.sample_at(5001.), None);
+    }
+
+    #[test]
+    fn stream_time_is_shifted_by_the_offset() {
+        let log = log();
+
+        // the logger started with the recording
+        assert_eq!(log.sample_at_stream_time(0., 0), log.sample_at(1000.));
+        assert_eq!(log.sample_at_stream_time(500., 0), log.sample_at(1500.));
+
+        // the first sample was logged 2 s into the recording
+        assert_eq!(log.sample_at_stream_time(1999., 2000), None);
+        assert_eq!(log.sample_at_stream_time(2500., 2000), log.sample_at(1500.));
+
+        // the recording started 1.5 s after the logger
+        assert_eq!(log.sample_at_stream_time(0., -1500), log.sample_at(2500.));
+        assert_eq!(log.sample_at_stream_time(600., -1500), None);
+    }
+
+    #[test]
+    fn speeds_are_converted() {
+        let mph = SpeedUnit::MilesPerHour.convert(10., SpeedUnit::KilometersPerHour);
+        assert!((mph - 16.09344).abs() < 1e-9);
+        let ms = SpeedUnit::MetersPerSecond.convert(10., SpeedUnit::KilometersPerHour);
+        assert!((ms - 36.).abs() < 1e-9);
+        assert!((DistanceUnit::Feet.convert(0.3048) - 1.).abs() < 1e-9);
+    }
+}
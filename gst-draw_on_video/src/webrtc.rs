@@ -0,0 +1,352 @@
+use std::io;
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::Error;
+use derive_more::{Display, Error};
+use gst::prelude::*;
+use serde_json::{json, Value};
+use tungstenite::{Message, WebSocket};
+
+use crate::MissingElement;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+// how long the server gets to answer the hello and the session request
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Display, Error)]
+#[display(fmt = "Signalling failed: {}", _0)]
+pub struct SignallingError(#[error(not(source))] pub String);
+
+#[derive(Debug, Display, Error)]
+#[display(fmt = "webrtcbin did not create an offer")]
+pub struct NoOffer;
+
+// where to find the viewer, who we are to the signalling server and, for
+// anything beyond the LAN, a STUN server like stun://stun.l.google.com:19302
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WebRtcConfig {
+    // e.g. ws://127.0.0.1:8765
+    pub server: String,
+    pub our_id: String,
+    pub peer_id: String,
+    pub stun_server: Option<String>,
+}
+
+impl WebRtcConfig {
+    pub fn new(server: &str, peer_id: &str) -> Self {
+        WebRtcConfig {
+            server: server.to_string(),
+            our_id: format!("vid_overlay-{}", std::process::id()),
+            peer_id: peer_id.to_string(),
+            stun_server: None,
+        }
+    }
+
+    pub fn with_our_id(mut self, our_id: &str) -> Self {
+        self.our_id = our_id.to_string();
+        self
+    }
+
+    pub fn with_stun_server(mut self, stun_server: &str) -> Self {
+        self.stun_server = Some(stun_server.to_string());
+        self
+    }
+}
+
+// VP8 into a `webrtcbin` named "webrtc", fed by `upstream`
+pub(crate) fn add_webrtc_sink(
+    pipeline: &gst::Pipeline,
+    upstream: &gst::Element,
+) -> Result<gst::Element, Error> {
+    let make = |factory: &'static str, name: Option<&str>| {
+        gst::ElementFactory::make(factory, name).map_err(|_| MissingElement(factory))
+    };
+
+    let queue = make("queue", None)?;
+    let encoder = make("vp8enc", None)?;
+    let payloader = make("rtpvp8pay", None)?;
+    let capsfilter = make("capsfilter", None)?;
+    let webrtcbin = make("webrtcbin", Some("webrtc"))?;
+
+    // realtime encoding, a keyframe every second or so for viewers joining late
+    encoder.set_property("deadline", &1i64)?;
+    encoder.set_property("keyframe-max-dist", &30i32)?;
+    let caps = gst::Caps::builder("application/x-rtp")
+        .field("media", &"video")
+        .field("encoding-name", &"VP8")
+        .field("payload", &96i32)
+        .build();
+    capsfilter.set_property("caps", &caps)?;
+    webrtcbin.set_property_from_str("bundle-policy", "max-bundle");
+
+    pipeline.add_many(&[&queue, &encoder, &payloader, &capsfilter, &webrtcbin])?;
+    gst::Element::link_many(&[
+        upstream,
+        &queue,
+        &encoder,
+        &payloader,
+        &capsfilter,
+        &webrtcbin,
+    ])?;
+
+    Ok(webrtcbin)
+}
+
+type Outgoing = Arc<Mutex<mpsc::Sender<String>>>;
+
+fn send(outgoing: &Outgoing, msg: Value) {
+    let _ = outgoing.lock().unwrap().send(msg.to_string());
+}
+
+// "ws://host:port/path" to "host:port", 80 being the default port
+fn host_port(url: &str) -> String {
+    let rest = url.split("://").nth(1).unwrap_or(url);
+    let authority = rest.split('/').next().unwrap_or(rest);
+    if authority.contains(':') {
+        authority.to_string()
+    } else {
+        format!("{}:80", authority)
+    }
+}
+
+fn text(msg: Message) -> Option<String> {
+    match msg {
+        Message::Text(text) => Some(text.to_string()),
+        // the Python server and clients send bytes
+        Message::Binary(bytes) => Some(String::from_utf8_lossy(&bytes).into_owned()),
+        _ => None,
+    }
+}
+
+// waits for `expected`, skipping the "Hello" the server keeps sending as a heartbeat
+fn expect(ws: &mut WebSocket<TcpStream>, expected: &str) -> Result<(), Error> {
+    let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+    while Instant::now() < deadline {
+        let msg = match ws.read()? {
+            Message::Close(_) => return Err(SignallingError("connection closed".into()).into()),
+            msg => match text(msg) {
+                Some(msg) => msg,
+                None => continue,
+            },
+        };
+
+        if msg == expected {
+            return Ok(());
+        }
+        if msg.starts_with("ERROR") {
+            return Err(SignallingError(msg).into());
+        }
+        if msg != "Hello" {
+            return Err(SignallingError(format!("expected {}, got {}", expected, msg)).into());
+        }
+    }
+
+    Err(SignallingError(format!("no {} from the server", expected)).into())
+}
+
+// Streams the `webrtcbin` of a pipeline built with `Output::WebRtc` to a peer of
+// the signalling server. After the "Hello <id>" handshake a session is asked for
+// with "SESSION <peer id>", then SDP and ICE go back and forth as JSON:
+// {"sdp": {"type": "offer", "sdp": "..."}} and
+// {"ice": {"candidate": "...", "sdpMLineIndex": 0}}. We always make the offer.
+pub struct WebRtcSession {
+    running: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl WebRtcSession {
+    // connects before the pipeline starts so the offer is made once it is
+    // playing, fails when the server or the peer is not there
+    pub fn start(pipeline: &gst::Pipeline, config: &WebRtcConfig) -> Result<Self, Error> {
+        let webrtcbin = pipeline
+            .get_by_name("webrtc")
+            .ok_or(MissingElement("webrtcbin"))?;
+        if let Some(ref stun_server) = config.stun_server {
+            webrtcbin.set_property("stun-server", stun_server)?;
+        }
+
+        let stream = TcpStream::connect(host_port(&config.server))?;
+        stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+        let (mut ws, _) = tungstenite::client(config.server.as_str(), stream)
+            .map_err(|err| anyhow::anyhow!("{}", err))?;
+
+        ws.send(Message::text(format!("Hello {}", config.our_id)))?;
+        expect(&mut ws, "Hello")?;
+        ws.send(Message::text(format!("SESSION {}", config.peer_id)))?;
+        expect(&mut ws, "SESSION_OK")?;
+        ws.get_ref().set_read_timeout(Some(POLL_INTERVAL))?;
+
+        let (sender, outgoing) = mpsc::channel();
+        let sender: Outgoing = Arc::new(Mutex::new(sender));
+
+        let weak = webrtcbin.downgrade();
+        let sender_clone = sender.clone();
+        webrtcbin.connect("on-negotiation-needed", false, move |_| {
+            if let Some(webrtcbin) = weak.upgrade() {
+                create_offer(&webrtcbin, sender_clone.clone());
+            }
+            None
+        })?;
+
+        webrtcbin.connect("on-ice-candidate", false, move |values| {
+            let mline_index = values[1].get_some::<u32>().ok()?;
+            let candidate = values[2].get::<String>().ok().flatten()?;
+            send(
+                &sender,
+                json!({"ice": {"candidate": candidate, "sdpMLineIndex": mline_index}}),
+            );
+            None
+        })?;
+
+        let running = Arc::new(AtomicBool::new(true));
+        let running_clone = running.clone();
+        let weak = webrtcbin.downgrade();
+        let thread = thread::spawn(move || {
+            let result = signalling_loop(&mut ws, &weak, &outgoing, &running_clone);
+            let _ = ws.close(None);
+
+            // the stream is of no use without signalling, fail the pipeline
+            if let (Err(err), Some(webrtcbin)) = (result, weak.upgrade()) {
+                gst::gst_element_error!(
+                    webrtcbin,
+                    gst::ResourceError::Failed,
+                    (err.to_string().as_str())
+                );
+            }
+        });
+
+        Ok(WebRtcSession {
+            running,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn stop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for WebRtcSession {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn signalling_loop(
+    ws: &mut WebSocket<TcpStream>,
+    webrtcbin: &glib::WeakRef<gst::Element>,
+    outgoing: &mpsc::Receiver<String>,
+    running: &AtomicBool,
+) -> Result<(), Error> {
+    while running.load(Ordering::SeqCst) {
+        match ws.read() {
+            Ok(Message::Close(_)) => {
+                return Err(SignallingError("server closed the connection".into()).into())
+            }
+            Ok(msg) => {
+                if let (Some(msg), Some(webrtcbin)) = (text(msg), webrtcbin.upgrade()) {
+                    handle_message(&webrtcbin, &msg)?;
+                }
+            }
+            Err(tungstenite::Error::Io(ref err))
+                if err.kind() == io::ErrorKind::WouldBlock
+                    || err.kind() == io::ErrorKind::TimedOut => {}
+            Err(err) => return Err(err.into()),
+        }
+
+        while let Ok(msg) = outgoing.try_recv() {
+            ws.send(Message::text(msg))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn handle_message(webrtcbin: &gst::Element, msg: &str) -> Result<(), Error> {
+    if msg == "Hello" {
+        return Ok(());
+    }
+    if msg.starts_with("ERROR") {
+        return Err(SignallingError(msg.to_string()).into());
+    }
+
+    let msg: Value = match serde_json::from_str(msg) {
+        Ok(msg) => msg,
+        // plain text is for whoever is at the other end, not for webrtcbin
+        Err(_) => return Ok(()),
+    };
+
+    if let Some(sdp) = msg.get("sdp") {
+        let kind = sdp.get("type").and_then(Value::as_str);
+        let sdp = sdp.get("sdp").and_then(Value::as_str);
+        match (kind, sdp) {
+            (Some("answer"), Some(sdp)) => {
+                let sdp = gst_sdp::SDPMessage::parse_buffer(sdp.as_bytes())
+                    .map_err(|_| SignallingError("unparseable SDP answer".into()))?;
+                let answer = gst_webrtc::WebRTCSessionDescription::new(
+                    gst_webrtc::WebRTCSDPType::Answer,
+                    sdp,
+                );
+                webrtcbin.emit("set-remote-description", &[&answer, &None::<gst::Promise>])?;
+            }
+            _ => eprintln!("Ignoring SDP that is not an answer: {}", msg),
+        }
+    } else if let Some(ice) = msg.get("ice") {
+        let candidate = ice.get("candidate").and_then(Value::as_str);
+        let mline_index = ice.get("sdpMLineIndex").and_then(Value::as_u64);
+        if let (Some(candidate), Some(mline_index)) = (candidate, mline_index) {
+            webrtcbin.emit("add-ice-candidate", &[&(mline_index as u32), &candidate])?;
+        }
+    }
+
+    Ok(())
+}
+
+fn create_offer(webrtcbin: &gst::Element, outgoing: Outgoing) {
+    let weak = webrtcbin.downgrade();
+    let promise = gst::Promise::with_change_func(move |reply| {
+        let webrtcbin = match weak.upgrade() {
+            Some(webrtcbin) => webrtcbin,
+            None => return,
+        };
+
+        if let Err(err) = send_offer(&webrtcbin, reply, &outgoing) {
+            gst::gst_element_error!(
+                webrtcbin,
+                gst::LibraryError::Failed,
+                (format!("Could not send the offer: {}", err).as_str())
+            );
+        }
+    });
+
+    if let Err(err) = webrtcbin.emit("create-offer", &[&None::<gst::Structure>, &promise]) {
+        eprintln!("Could not create an offer: {}", err);
+    }
+}
+
+fn send_offer(
+    webrtcbin: &gst::Element,
+    reply: Result<Option<&gst::StructureRef>, gst::PromiseError>,
+    outgoing: &Outgoing,
+) -> Result<(), Error> {
+    let offer = match reply {
+        Ok(Some(reply)) => reply
+            .get::<gst_webrtc::WebRTCSessionDescription>("offer")?
+            .ok_or(NoOffer)?,
+        _ => return Err(NoOffer.into()),
+    };
+
+    webrtcbin.emit("set-local-description", &[&offer, &None::<gst::Promise>])?;
+    let sdp = offer.get_sdp().as_text()?;
+    send(outgoing, json!({"sdp": {"type": "offer", "sdp": sdp}}));
+
+    Ok(())
+}
@@ -0,0 +1,439 @@
+use anyhow::Error;
+use raster::{over, Matrix};
+
+use crate::{FrameMapError, OverlayElement, RenderCache, UnsupportedFormat};
+
+// how chroma is stored for the YUV formats overlays can be blended into
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ChromaLayout {
+    // separate U and V planes at a quarter of the resolution
+    Planar {
+        u: u32,
+        v: u32,
+    },
+    // one interleaved plane at a quarter of the resolution
+    SemiPlanar {
+        u: usize,
+        v: usize,
+    },
+    // 4:2:2 macro pixels of two luma samples and one U/V pair
+    Packed {
+        y0: usize,
+        y1: usize,
+        u: usize,
+        v: usize,
+    },
+}
+
+fn chroma_layout(format: gst_video::VideoFormat) -> Option<ChromaLayout> {
+    use gst_video::VideoFormat;
+
+    match format {
+        VideoFormat::I420 => Some(ChromaLayout::Planar { u: 1, v: 2 }),
+        VideoFormat::Yv12 => Some(ChromaLayout::Planar { u: 2, v: 1 }),
+        VideoFormat::Nv12 => Some(ChromaLayout::SemiPlanar { u: 0, v: 1 }),
+        VideoFormat::Nv21 => Some(ChromaLayout::SemiPlanar { u: 1, v: 0 }),
+        VideoFormat::Yuy2 => Some(ChromaLayout::Packed {
+            y0: 0,
+            u: 1,
+            y1: 2,
+            v: 3,
+        }),
+        VideoFormat::Uyvy => Some(ChromaLayout::Packed {
+            u: 0,
+            y0: 1,
+            v: 2,
+            y1: 3,
+        }),
+        _ => None,
+    }
+}
+
+pub fn is_yuv(format: gst_video::VideoFormat) -> bool {
+    chroma_layout(format).is_some()
+}
+
+// premultiplied colour with 8 bit alpha, as stored in cairo's ARGB32
+#[derive(Debug, Default, Clone, Copy)]
+struct Premultiplied {
+    r: u32,
+    g: u32,
+    b: u32,
+    a: u32,
+}
+
+impl Premultiplied {
+    fn from_argb32(data: &[u8]) -> Self {
+        let px = u32::from_ne_bytes([data[0], data[1], data[2], data[3]]);
+
+        Premultiplied {
+            a: px >> 24,
+            r: (px >> 16) & 0xff,
+            g: (px >> 8) & 0xff,
+            b: px & 0xff,
+        }
+    }
+
+    fn add(&mut self, other: Premultiplied) {
+        self.r += other.r;
+        self.g += other.g;
+        self.b += other.b;
+        self.a += other.a;
+    }
+
+    fn average(self, count: u32) -> Self {
+        let round = count / 2;
+
+        Premultiplied {
+            r: (self.r + round) / count,
+            g: (self.g + round) / count,
+            b: (self.b + round) / count,
+            a: (self.a + round) / count,
+        }
+    }
+
+    fn luma(self, matrix: Matrix) -> i32 {
+        matrix.luma(self.r as i32, self.g as i32, self.b as i32, self.a as i32)
+    }
+
+    fn chroma(self, matrix: Matrix) -> (i32, i32) {
+        matrix.chroma(self.r as i32, self.g as i32, self.b as i32, self.a as i32)
+    }
+}
+
+// a premultiplied ARGB32 image placed at (x, y) on the frame
+pub struct Overlay<'a> {
+    pub data: &'a [u8],
+    pub stride: usize,
+    pub width: u32,
+    pub height: u32,
+    pub x: i32,
+    pub y: i32,
+}
+
+impl<'a> Overlay<'a> {
+    // pixel covering frame position (fx, fy), transparent outside the overlay
+    fn at(&self, fx: i32, fy: i32) -> Premultiplied {
+        let (sx, sy) = (fx - self.x, fy - self.y);
+        if sx < 0 || sy < 0 || sx as u32 >= self.width || sy as u32 >= self.height {
+            return Premultiplied::default();
+        }
+
+        let idx = sy as usize * self.stride + sx as usize * 4;
+        Premultiplied::from_argb32(&self.data[idx..idx + 4])
+    }
+}
+
+// A YUV frame mapped for blending overlays without going through RGB. Luma is
+// blended per pixel, chroma per sample from the average of the pixels it covers,
+// both with the colour matrix of the stream.
+pub struct YuvFrame<'a> {
+    frame: gst_video::VideoFrameRef<&'a mut gst::BufferRef>,
+    layout: ChromaLayout,
+    matrix: Matrix,
+}
+
+impl<'a> YuvFrame<'a> {
+    pub fn map(
+        buffer: &'a mut gst::BufferRef,
+        info: &gst_video::VideoInfo,
+    ) -> Result<YuvFrame<'a>, Error> {
+        let layout = chroma_layout(info.format()).ok_or(UnsupportedFormat(info.format()))?;
+        let frame = gst_video::VideoFrameRef::from_buffer_ref_writable(buffer, info)
+            .map_err(|_| FrameMapError)?;
+
+        Ok(YuvFrame {
+            frame,
+            layout,
+            matrix: raster::matrix(info),
+        })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.frame.width()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.frame.height()
+    }
+
+    pub fn blend(&mut self, overlay: &Overlay) -> Result<(), Error> {
+        let (width, height) = (self.width() as i32, self.height() as i32);
+
+        // part of the frame covered by the overlay
+        let x0 = overlay.x.max(0);
+        let y0 = overlay.y.max(0);
+        let x1 = (overlay.x + overlay.width as i32).min(width);
+        let y1 = (overlay.y + overlay.height as i32).min(height);
+        if x0 >= x1 || y0 >= y1 {
+            return Ok(());
+        }
+
+        match self.layout {
+            ChromaLayout::Planar { u, v } => {
+                self.blend_luma(overlay, (x0, y0, x1, y1))?;
+                let samples =
+                    chroma_samples(overlay, (x0, y0, x1, y1), (width, height), self.matrix);
+                self.blend_chroma_plane(u, 1, 0, &samples, |(u, _)| u)?;
+                self.blend_chroma_plane(v, 1, 0, &samples, |(_, v)| v)?;
+            }
+            ChromaLayout::SemiPlanar { u, v } => {
+                self.blend_luma(overlay, (x0, y0, x1, y1))?;
+                let samples =
+                    chroma_samples(overlay, (x0, y0, x1, y1), (width, height), self.matrix);
+                self.blend_chroma_plane(1, 2, u, &samples, |(u, _)| u)?;
+                self.blend_chroma_plane(1, 2, v, &samples, |(_, v)| v)?;
+            }
+            ChromaLayout::Packed {
+                y0: l0,
+                y1: l1,
+                u,
+                v,
+            } => {
+                let matrix = self.matrix;
+                let stride = self.frame.plane_stride()[0] as usize;
+                let data = self.frame.plane_data_mut(0)?;
+
+                // one macro pixel per pair of columns, rows are not subsampled
+                for fy in y0..y1 {
+                    let row = &mut data[fy as usize * stride..];
+
+                    for pair in (x0 / 2)..((x1 + 1) / 2) {
+                        let left = overlay.at(pair * 2, fy);
+                        let right = if pair * 2 + 1 < width {
+                            overlay.at(pair * 2 + 1, fy)
+                        } else {
+                            Premultiplied::default()
+                        };
+
+                        let px = &mut row[pair as usize * 4..pair as usize * 4 + 4];
+                        px[l0] = over(px[l0], left.luma(matrix), left.a);
+                        px[l1] = over(px[l1], right.luma(matrix), right.a);
+
+                        let mut sum = left;
+                        sum.add(right);
+                        let avg = sum.average(2);
+                        let (cu, cv) = avg.chroma(matrix);
+                        px[u] = over(px[u], cu, avg.a);
+                        px[v] = over(px[v], cv, avg.a);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn blend_luma(&mut self, overlay: &Overlay, area: (i32, i32, i32, i32)) -> Result<(), Error> {
+        let (x0, y0, x1, y1) = area;
+        let matrix = self.matrix;
+        let stride = self.frame.plane_stride()[0] as usize;
+        let data = self.frame.plane_data_mut(0)?;
+
+        for fy in y0..y1 {
+            let row = &mut data[fy as usize * stride..];
+            for fx in x0..x1 {
+                let src = overlay.at(fx, fy);
+                if src.a == 0 {
+                    continue;
+                }
+
+                row[fx as usize] = over(row[fx as usize], src.luma(matrix), src.a);
+            }
+        }
+
+        Ok(())
+    }
+
+    // writes one chroma component, `step`/`offset` select it inside interleaved planes
+    fn blend_chroma_plane<F: Fn((i32, i32)) -> i32>(
+        &mut self,
+        plane: u32,
+        step: usize,
+        offset: usize,
+        samples: &[ChromaSample],
+        component: F,
+    ) -> Result<(), Error> {
+        let stride = self.frame.plane_stride()[plane as usize] as usize;
+        let data = self.frame.plane_data_mut(plane)?;
+
+        for sample in samples {
+            if sample.alpha == 0 {
+                continue;
+            }
+
+            let idx = sample.y as usize * stride + sample.x as usize * step + offset;
+            data[idx] = over(data[idx], component(sample.value), sample.alpha);
+        }
+
+        Ok(())
+    }
+}
+
+struct ChromaSample {
+    x: i32,
+    y: i32,
+    value: (i32, i32),
+    alpha: u32,
+}
+
+// 4:2:0 chroma for every sample touched by the overlay, averaged over its 2x2 block
+fn chroma_samples(
+    overlay: &Overlay,
+    area: (i32, i32, i32, i32),
+    frame_size: (i32, i32),
+    matrix: Matrix,
+) -> Vec<ChromaSample> {
+    let (x0, y0, x1, y1) = area;
+    let (width, height) = frame_size;
+    let factor = 2;
+    let mut samples = Vec::new();
+
+    for cy in (y0 / factor)..((y1 + factor - 1) / factor) {
+        for cx in (x0 / factor)..((x1 + factor - 1) / factor) {
+            let mut sum = Premultiplied::default();
+            let mut count = 0;
+
+            for fy in (cy * factor)..((cy + 1) * factor).min(height) {
+                for fx in (cx * factor)..((cx + 1) * factor).min(width) {
+                    sum.add(overlay.at(fx, fy));
+                    count += 1;
+                }
+            }
+
+            let avg = sum.average(count);
+            samples.push(ChromaSample {
+                x: cx,
+                y: cy,
+                value: avg.chroma(matrix),
+                alpha: avg.a,
+            });
+        }
+    }
+
+    samples
+}
+
+// draws the elements visible at `timestamp` straight into a YUV frame
+pub(crate) fn draw_scene_yuv(
+    frame: &mut YuvFrame,
+    layout: &pango::Layout,
+    cache: &mut RenderCache,
+    overlay_element: &[OverlayElement],
+    timestamp: gst::ClockTime,
+) -> Result<(), Error> {
+    for ele in overlay_element.iter() {
+        let ele = match ele.at(timestamp) {
+            Some(ele) => ele,
+            None => continue,
+        };
+
+        let (x, y) = ele.position();
+        if let Some(cached) = cache.get_or_render(layout, &ele) {
+            let (width, height) = (cached.width as u32, cached.height as u32);
+            let blended = cached.with_pixels(|data, stride| {
+                frame.blend(&Overlay {
+                    data,
+                    stride,
+                    width,
+                    height,
+                    x: x.floor() as i32,
+                    y: y.floor() as i32,
+                })
+            });
+
+            if let Some(result) = blended {
+                result?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: u32 = 0xffff_0000;
+
+    // black 4x4 I420 frame
+    fn i420(colorimetry: &str) -> (gst::Buffer, gst_video::VideoInfo) {
+        gst::init().unwrap();
+        let info = gst_video::VideoInfo::builder(gst_video::VideoFormat::I420, 4, 4)
+            .colorimetry(&colorimetry.parse().unwrap())
+            .build()
+            .unwrap();
+
+        let mut buffer = gst::Buffer::with_size(info.size()).unwrap();
+        {
+            let mut map = buffer.get_mut().unwrap().map_writable().unwrap();
+            let (y, chroma) = map.split_at_mut(info.offset()[1]);
+            y.iter_mut().for_each(|p| *p = 16);
+            chroma.iter_mut().for_each(|p| *p = 128);
+        }
+
+        (buffer, info)
+    }
+
+    // `argb` is a premultiplied ARGB32 image, `width` pixels per row
+    fn blend(buffer: &mut gst::Buffer, info: &gst_video::VideoInfo, argb: &[u32], width: u32) {
+        let data: Vec<u8> = argb
+            .iter()
+            .flat_map(|px| px.to_ne_bytes().to_vec())
+            .collect();
+        let overlay = Overlay {
+            data: &data,
+            stride: width as usize * 4,
+            width,
+            height: argb.len() as u32 / width,
+            x: 0,
+            y: 0,
+        };
+
+        let mut frame = YuvFrame::map(buffer.get_mut().unwrap(), info).unwrap();
+        frame.blend(&overlay).unwrap();
+    }
+
+    // sample (x, y) of a plane
+    fn sample(
+        buffer: &gst::Buffer,
+        info: &gst_video::VideoInfo,
+        plane: u32,
+        x: usize,
+        y: usize,
+    ) -> u8 {
+        let frame = gst_video::VideoFrameRef::from_buffer_ref_readable(buffer, info).unwrap();
+        let stride = frame.plane_stride()[plane as usize] as usize;
+        frame.plane_data(plane).unwrap()[y * stride + x]
+    }
+
+    #[test]
+    fn opaque_block_takes_the_colour_of_the_stream_matrix() {
+        for &(colorimetry, matrix) in [("bt601", Matrix::Bt601), ("bt709", Matrix::Bt709)].iter() {
+            let (mut buffer, info) = i420(colorimetry);
+            blend(&mut buffer, &info, &[RED; 4], 2);
+
+            let luma = matrix.luma(255, 0, 0, 255) as u8;
+            let (u, v) = matrix.chroma(255, 0, 0, 255);
+            assert_eq!(sample(&buffer, &info, 0, 1, 1), luma, "{}", colorimetry);
+            assert_eq!(sample(&buffer, &info, 0, 2, 1), 16, "{}", colorimetry);
+            assert_eq!(sample(&buffer, &info, 1, 0, 0), u as u8, "{}", colorimetry);
+            assert_eq!(sample(&buffer, &info, 2, 0, 0), v as u8, "{}", colorimetry);
+            assert_eq!(sample(&buffer, &info, 1, 1, 0), 128, "{}", colorimetry);
+        }
+    }
+
+    #[test]
+    fn chroma_averages_the_pixels_of_its_block() {
+        let (mut buffer, info) = i420("bt601");
+        // one red pixel in the top left 2x2 block
+        blend(&mut buffer, &info, &[RED, 0, 0, 0], 2);
+
+        // full strength luma, chroma at a quarter of the alpha
+        assert_eq!(sample(&buffer, &info, 0, 0, 0), 82);
+        assert_eq!(sample(&buffer, &info, 0, 1, 0), 16);
+        assert_eq!(sample(&buffer, &info, 1, 0, 0), 118);
+        assert_eq!(sample(&buffer, &info, 2, 0, 0), 155);
+    }
+}
@@ -0,0 +1,184 @@
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use gst::prelude::*;
+use serde_json::{json, Value};
+use signalling_server::{Config, Server};
+use tungstenite::stream::MaybeTlsStream;
+use tungstenite::{Message, WebSocket};
+use vid_overlay::{WebRtcConfig, WebRtcSession};
+
+type Peer = WebSocket<MaybeTlsStream<TcpStream>>;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// what `Output::WebRtc` builds, without the overlay in front
+const PIPELINE: &str = "videotestsrc is-live=true ! videoconvert ! vp8enc deadline=1 \
+    ! rtpvp8pay ! application/x-rtp,media=video,encoding-name=VP8,payload=96 \
+    ! webrtcbin name=webrtc bundle-policy=max-bundle";
+
+// None when the plugins of the pipeline are not installed
+fn pipeline() -> Option<gst::Pipeline> {
+    gst::init().unwrap();
+
+    let missing = ["webrtcbin", "vp8enc", "rtpvp8pay"]
+        .iter()
+        .find(|factory| gst::ElementFactory::find(factory).is_none());
+    if let Some(factory) = missing {
+        eprintln!("Skipped, no {}", factory);
+        return None;
+    }
+
+    let pipeline = gst::parse_launch(PIPELINE).unwrap();
+    Some(pipeline.downcast::<gst::Pipeline>().unwrap())
+}
+
+fn start_server() -> String {
+    let server = Arc::new(Server::bind("127.0.0.1:0", Config::default()).unwrap());
+    let url = format!("ws://{}", server.local_addr().unwrap());
+    thread::spawn(move || server.run());
+    url
+}
+
+// a viewer registered with the server, as the browser page would be
+fn connect_peer(url: &str, id: &str) -> Peer {
+    let (mut ws, _) = tungstenite::connect(url).unwrap();
+    if let MaybeTlsStream::Plain(ref stream) = ws.get_ref() {
+        stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+    }
+
+    ws.send(Message::text(format!("Hello {}", id))).unwrap();
+    assert_eq!(recv(&mut ws), "Hello");
+    ws
+}
+
+fn recv(ws: &mut Peer) -> String {
+    loop {
+        match ws.read().unwrap() {
+            Message::Text(text) => return text.to_string(),
+            Message::Binary(bytes) => return String::from_utf8(bytes.to_vec()).unwrap(),
+            _ => continue,
+        }
+    }
+}
+
+// the next JSON message, skipping heartbeats
+fn recv_json(ws: &mut Peer) -> Value {
+    loop {
+        let msg = recv(ws);
+        if msg != "Hello" {
+            return serde_json::from_str(&msg).unwrap();
+        }
+    }
+}
+
+// The stub peer has no media of its own, it takes the offer back with the
+// roles of an answer. Enough for webrtcbin to complete the negotiation.
+fn answer_for(offer: &str) -> String {
+    offer
+        .replace("a=setup:actpass", "a=setup:active")
+        .replace("a=sendrecv", "a=recvonly")
+        .replace("a=sendonly", "a=recvonly")
+}
+
+fn signaling_state(webrtcbin: &gst::Element) -> gst_webrtc::WebRTCSignalingState {
+    webrtcbin
+        .get_property("signaling-state")
+        .unwrap()
+        .get_some::<gst_webrtc::WebRTCSignalingState>()
+        .unwrap()
+}
+
+#[test]
+fn offer_is_answered_through_the_signalling_server() {
+    let pipeline = match pipeline() {
+        Some(pipeline) => pipeline,
+        None => return,
+    };
+    let url = start_server();
+    let mut viewer = connect_peer(&url, "viewer");
+
+    let config = WebRtcConfig::new(&url, "viewer").with_our_id("streamer");
+    let mut session = WebRtcSession::start(&pipeline, &config).unwrap();
+    pipeline.set_state(gst::State::Playing).unwrap();
+
+    // candidates may come before or after the offer
+    let mut candidates = 0;
+    let offer = loop {
+        let msg = recv_json(&mut viewer);
+        if let Some(sdp) = msg.get("sdp") {
+            break sdp.clone();
+        }
+        assert!(msg["ice"]["candidate"].is_string(), "unexpected {}", msg);
+        candidates += 1;
+    };
+    assert_eq!(offer["type"], "offer");
+    let offer = offer["sdp"].as_str().unwrap();
+    assert!(offer.contains("VP8/90000"));
+
+    let answer = json!({"sdp": {"type": "answer", "sdp": answer_for(offer)}});
+    viewer.send(Message::text(answer.to_string())).unwrap();
+
+    // gathering goes on after the offer, every candidate is forwarded
+    while candidates == 0 {
+        assert!(recv_json(&mut viewer)["ice"]["candidate"].is_string());
+        candidates += 1;
+    }
+
+    let webrtcbin = pipeline.get_by_name("webrtc").unwrap();
+    let deadline = Instant::now() + TIMEOUT;
+    while signaling_state(&webrtcbin) != gst_webrtc::WebRTCSignalingState::Stable {
+        assert!(Instant::now() < deadline, "answer not applied");
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    // a message that is neither SDP nor ICE is not an error
+    viewer.send(Message::text("hi")).unwrap();
+    thread::sleep(Duration::from_millis(200));
+    let bus = pipeline.get_bus().unwrap();
+    assert!(bus.pop_filtered(&[gst::MessageType::Error]).is_none());
+
+    session.stop();
+    pipeline.set_state(gst::State::Null).unwrap();
+}
+
+#[test]
+fn unknown_peer_fails_the_start() {
+    let pipeline = match pipeline() {
+        Some(pipeline) => pipeline,
+        None => return,
+    };
+    let url = start_server();
+
+    let config = WebRtcConfig::new(&url, "nobody").with_our_id("streamer");
+    let err = WebRtcSession::start(&pipeline, &config).err().unwrap();
+    assert!(err.to_string().contains("ERROR"), "{}", err);
+}
+
+#[test]
+fn peer_leaving_fails_the_pipeline() {
+    let pipeline = match pipeline() {
+        Some(pipeline) => pipeline,
+        None => return,
+    };
+    let url = start_server();
+    let viewer = connect_peer(&url, "viewer");
+
+    let config = WebRtcConfig::new(&url, "viewer").with_our_id("streamer");
+    let _session = WebRtcSession::start(&pipeline, &config).unwrap();
+    pipeline.set_state(gst::State::Playing).unwrap();
+    drop(viewer);
+
+    let bus = pipeline.get_bus().unwrap();
+    let msg = bus
+        .timed_pop_filtered(gst::ClockTime::from_seconds(15), &[gst::MessageType::Error])
+        .expect("no error after the peer left");
+    match msg.view() {
+        gst::MessageView::Error(err) => assert!(err.get_error().to_string().contains("left")),
+        _ => unreachable!(),
+    }
+
+    pipeline.set_state(gst::State::Null).unwrap();
+}
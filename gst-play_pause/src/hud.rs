@@ -0,0 +1,115 @@
+use std::time::{Duration, Instant};
+
+use gst::prelude::*;
+use vid_overlay::{OverlayElement, Scene, VideoContext};
+
+// fully visible for this long after the last command, then faded out
+const SHOW_FOR: Duration = Duration::from_secs(3);
+const FADE_FOR: Duration = Duration::from_secs(1);
+
+// elements are timed in stream seconds, the HUD ignores the stream time
+const ALWAYS: (f64, f64) = (0., u32::MAX as f64);
+
+const X: f64 = 20.;
+const Y: f64 = 20.;
+
+// what the HUD shows, filled in by the controller
+pub struct HudStatus<'a> {
+    pub playing: bool,
+    pub position: Option<gst::ClockTime>,
+    pub duration: Option<gst::ClockTime>,
+    pub rate: f64,
+    pub last_command: &'a str,
+}
+
+fn format_time(time: Option<gst::ClockTime>) -> String {
+    match time.and_then(|t| t.nseconds()) {
+        Some(ns) => {
+            let tenths = ns / 100_000_000;
+            format!(
+                "{:02}:{:02}:{:02}.{}",
+                tenths / 36_000,
+                tenths / 600 % 60,
+                tenths / 10 % 60,
+                tenths % 10
+            )
+        }
+        None => "--:--:--.-".to_string(),
+    }
+}
+
+// Status line drawn into the frames at the sink, using the pad probe backend of
+// vid_overlay so it works on any pipeline with raw video in front of the sink.
+pub struct Hud {
+    scene: Scene,
+    shown_at: Option<Instant>,
+}
+
+impl Hud {
+    pub fn attach(sink: &gst::Element) -> Option<Hud> {
+        let pad = sink.get_static_pad("sink")?;
+        let scene = Scene::default();
+        VideoContext::draw_on_pad(&pad, scene.clone())?;
+
+        Some(Hud {
+            scene,
+            shown_at: None,
+        })
+    }
+
+    // restarts the fade out
+    pub fn show(&mut self) {
+        self.shown_at = Some(Instant::now());
+    }
+
+    fn alpha(&self) -> f64 {
+        let elapsed = match self.shown_at {
+            Some(shown_at) => shown_at.elapsed(),
+            None => return 0.,
+        };
+
+        if elapsed <= SHOW_FOR {
+            1.
+        } else {
+            let fading = (elapsed - SHOW_FOR).as_secs_f64() / FADE_FOR.as_secs_f64();
+            (1. - fading).max(0.)
+        }
+    }
+
+    // redraws the status line, true when the fade out has just finished and the
+    // HUD is gone
+    pub fn update(&mut self, status: &HudStatus) -> bool {
+        let alpha = self.alpha();
+        if alpha <= 0. {
+            let hidden = !self.scene.is_empty();
+            if hidden {
+                self.scene.clear();
+            }
+            self.shown_at = None;
+            return hidden;
+        }
+
+        let state = if status.playing { "Playing" } else { "Paused" };
+        let line = format!(
+            "{}  {} / {}  {}x  [{}]",
+            state,
+            format_time(status.position),
+            format_time(status.duration),
+            status.rate,
+            status.last_command
+        );
+
+        self.scene.set(vec![
+            OverlayElement::create_rectangle(
+                X - 8.,
+                Y - 6.,
+                520.,
+                32.,
+                (0., 0., 0., 0.6 * alpha),
+                ALWAYS,
+            ),
+            OverlayElement::create_text(line, X, Y, (1., 1., 1., alpha), ALWAYS),
+        ]);
+        false
+    }
+}
@@ -0,0 +1,331 @@
+use std::io::{self, Read, Write};
+use std::iter::Peekable;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::panic;
+use std::sync::mpsc;
+use std::thread;
+
+use termios::{tcsetattr, Termios, ECHO, ICANON, ISIG, TCSANOW, VMIN, VTIME};
+
+use crate::Command;
+
+// tenths of a second to wait for the rest of an escape sequence, terminals send
+// them in one go so this only has to cover slow links
+const ESCAPE_TIMEOUT: u8 = 1;
+
+pub const HELP: &str = "\
+Keys:
+  p, space       play / pause
+  n / N          step one frame forward / backward
+  l / h, -> / <- seek 5s forward / backward
+  L / H, up/down seek 60s forward / backward
+  g / G, home/end jump to start / end
+  k              toggle keyframe / accurate seeking
+  + / -          faster / slower (0.25x to 4x)
+  r              reverse playback direction
+  =              normal speed
+  > / <          next / previous playlist entry
+  a / b          mark loop start / end (A-B repeat)
+  c              clear the A-B loop
+  o              toggle looping the whole stream
+  s              save a snapshot of the current frame
+  e              send EOS
+  ?              show this help
+  q, ctrl-c      quit";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    // ESC on its own, nothing followed it within VTIME
+    Escape,
+    Interrupt,
+}
+
+impl Key {
+    pub fn command(self) -> Option<Command> {
+        match self {
+            Key::Char(' ') => Some(Command::Toggle),
+            Key::Char(c) => Command::from_key(c),
+            Key::Right => Some(Command::SeekRelative(5_000)),
+            Key::Left => Some(Command::SeekRelative(-5_000)),
+            Key::Up => Some(Command::SeekRelative(60_000)),
+            Key::Down => Some(Command::SeekRelative(-60_000)),
+            Key::Home => Some(Command::SeekStart),
+            Key::End => Some(Command::SeekEnd),
+            Key::Escape => None,
+            Key::Interrupt => Some(Command::Quit),
+        }
+    }
+}
+
+// Puts the terminal into raw mode: no line buffering, no echo and ctrl-c is
+// delivered as a key. Output processing is left alone so println! keeps working.
+// Reads come back empty after VTIME without input, which is how a lone ESC is
+// told from the start of an escape sequence.
+// The previous settings come back when this is dropped or the program panics.
+pub struct RawTerminal {
+    original: Termios,
+}
+
+impl RawTerminal {
+    pub fn enable() -> io::Result<RawTerminal> {
+        let fd = io::stdin().as_raw_fd();
+        let original = Termios::from_fd(fd)?;
+
+        let mut raw = original;
+        raw.c_lflag &= !(ICANON | ECHO | ISIG);
+        raw.c_cc[VMIN] = 0;
+        raw.c_cc[VTIME] = ESCAPE_TIMEOUT;
+        tcsetattr(fd, TCSANOW, &raw)?;
+
+        // a panic skips the main thread's drop when it happens elsewhere
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            let _ = tcsetattr(fd, TCSANOW, &original);
+            previous(info);
+        }));
+
+        Ok(RawTerminal { original })
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        let _ = tcsetattr(io::stdin().as_raw_fd(), TCSANOW, &self.original);
+    }
+}
+
+// Bytes of the input, `None` for a read that came back empty. With `idle` an
+// empty read only means nothing was typed for a moment (a raw terminal with
+// VMIN 0), otherwise it is the end of the input.
+fn read_bytes<R: Read>(mut input: R, idle: bool) -> impl Iterator<Item = Option<u8>> {
+    let mut buf = [0; 1];
+
+    std::iter::from_fn(move || loop {
+        match input.read(&mut buf) {
+            Ok(0) if idle => return Some(None),
+            Ok(0) => return None,
+            Ok(_) => return Some(Some(buf[0])),
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => return None,
+        }
+    })
+}
+
+// reads the rest of an escape sequence, ESC has already been consumed
+fn escape_sequence<I: Iterator<Item = Option<u8>>>(bytes: &mut Peekable<I>) -> Option<Key> {
+    // whatever does not start a sequence is left for the next key
+    match bytes.peek() {
+        Some(Some(b'[')) | Some(Some(b'O')) => {
+            bytes.next();
+        }
+        _ => return Some(Key::Escape),
+    }
+
+    let mut param = 0;
+    loop {
+        // a sequence cut short by a pause is dropped
+        let b = bytes.next()??;
+        match b {
+            b'0'..=b'9' => param = param * 10 + u32::from(b - b'0'),
+            b'A' => return Some(Key::Up),
+            b'B' => return Some(Key::Down),
+            b'C' => return Some(Key::Right),
+            b'D' => return Some(Key::Left),
+            b'H' => return Some(Key::Home),
+            b'F' => return Some(Key::End),
+            b'~' => {
+                return match param {
+                    1 | 7 => Some(Key::Home),
+                    4 | 8 => Some(Key::End),
+                    _ => None,
+                };
+            }
+            _ => return None,
+        }
+    }
+}
+
+fn parse_keys<I: Iterator<Item = Option<u8>>>(bytes: I) -> impl Iterator<Item = Key> {
+    let mut bytes = bytes.peekable();
+
+    std::iter::from_fn(move || loop {
+        let key = match bytes.next()? {
+            Some(0x03) => Some(Key::Interrupt),
+            Some(0x1b) => escape_sequence(&mut bytes),
+            Some(b) if b.is_ascii() => Some(Key::Char(b as char)),
+            _ => None,
+        };
+
+        if key.is_some() {
+            return key;
+        }
+    })
+}
+
+// turns raw terminal bytes into keys, unknown sequences are dropped. An empty
+// read ends the input.
+pub fn keys<R: Read>(input: R) -> impl Iterator<Item = Key> {
+    parse_keys(read_bytes(input, false))
+}
+
+// like `keys`, for a terminal in raw mode whose empty reads mark pauses in typing
+pub fn timed_keys<R: Read>(input: R) -> impl Iterator<Item = Key> {
+    parse_keys(read_bytes(input, true))
+}
+
+// whether reads of `fd` return empty after a timeout, as `RawTerminal` sets it up
+fn reads_time_out(fd: RawFd) -> bool {
+    Termios::from_fd(fd)
+        .map(|t| t.c_lflag & ICANON == 0 && t.c_cc[VMIN] == 0)
+        .unwrap_or(false)
+}
+
+// Reads keys from stdin on its own thread and forwards their commands, so whoever
+// polls the bus is never blocked waiting for input. Stops once the receiver is gone.
+pub fn spawn_reader(commands: mpsc::Sender<Command>) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let keys: Box<dyn Iterator<Item = Key>> = if reads_time_out(io::stdin().as_raw_fd()) {
+            Box::new(timed_keys(io::stdin()))
+        } else {
+            Box::new(keys(io::stdin()))
+        };
+
+        for key in keys {
+            if key == Key::Char('?') {
+                println!("{}", HELP);
+                let _ = io::stdout().flush();
+                continue;
+            }
+
+            let command = match key.command() {
+                Some(command) => command,
+                None => continue,
+            };
+
+            let quit = command == Command::Quit;
+            if commands.send(command).is_err() || quit {
+                break;
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &[u8]) -> Vec<Key> {
+        keys(input).collect()
+    }
+
+    // a terminal answering reads with `chunks` one byte at a time, an empty chunk
+    // is a read that timed out
+    struct Typing(Vec<Vec<u8>>);
+
+    impl Read for Typing {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            if self.0[0].is_empty() {
+                self.0.remove(0);
+                return Ok(0);
+            }
+
+            buf[0] = self.0[0].remove(0);
+            if self.0[0].is_empty() {
+                self.0.remove(0);
+            }
+            Ok(1)
+        }
+    }
+
+    fn typed(chunks: &[&[u8]]) -> Vec<Key> {
+        timed_keys(Typing(chunks.iter().map(|c| c.to_vec()).collect())).collect()
+    }
+
+    #[test]
+    fn plain_keys_and_ctrl_c() {
+        assert_eq!(
+            parse(b"p \x03"),
+            vec![Key::Char('p'), Key::Char(' '), Key::Interrupt]
+        );
+    }
+
+    #[test]
+    fn arrows_home_and_end() {
+        assert_eq!(
+            parse(b"\x1b[A\x1b[B\x1b[C\x1b[D\x1bOH\x1bOF\x1b[1~\x1b[4~\x1b[7~\x1b[8~"),
+            vec![
+                Key::Up,
+                Key::Down,
+                Key::Right,
+                Key::Left,
+                Key::Home,
+                Key::End,
+                Key::Home,
+                Key::End,
+                Key::Home,
+                Key::End,
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_sequences_and_bytes_are_dropped() {
+        assert_eq!(
+            parse(b"\x1b[5~\x1b[Zq\xc3\xa9g"),
+            vec![Key::Char('q'), Key::Char('g')]
+        );
+    }
+
+    #[test]
+    fn lone_escape_keeps_the_next_key() {
+        assert_eq!(parse(b"\x1bq"), vec![Key::Escape, Key::Char('q')]);
+        assert_eq!(parse(b"\x1b"), vec![Key::Escape]);
+    }
+
+    #[test]
+    fn pause_after_escape_ends_it() {
+        assert_eq!(
+            typed(&[b"\x1b", b"", b"[A"]),
+            vec![Key::Escape, Key::Char('['), Key::Char('A')]
+        );
+        assert_eq!(
+            typed(&[b"\x1b[A", b"", b"p"]),
+            vec![Key::Up, Key::Char('p')]
+        );
+    }
+
+    #[test]
+    fn pauses_between_keys_are_skipped() {
+        assert_eq!(
+            typed(&[b"", b"p", b"", b"", b"q"]),
+            vec![Key::Char('p'), Key::Char('q')]
+        );
+    }
+
+    #[test]
+    fn sequence_cut_by_a_pause_is_dropped() {
+        assert_eq!(
+            typed(&[b"\x1b[", b"", b"Aq"]),
+            vec![Key::Char('A'), Key::Char('q')]
+        );
+    }
+
+    #[test]
+    fn keys_map_to_commands() {
+        assert_eq!(Key::Char(' ').command(), Some(Command::Toggle));
+        assert_eq!(Key::Left.command(), Some(Command::SeekRelative(-5_000)));
+        assert_eq!(Key::End.command(), Some(Command::SeekEnd));
+        assert_eq!(Key::Interrupt.command(), Some(Command::Quit));
+        assert_eq!(Key::Escape.command(), None);
+    }
+}
@@ -0,0 +1,1274 @@
+extern crate gstreamer as gst;
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use anyhow::Error;
+use derive_more::{Display, Error};
+use gst::prelude::*;
+use remote_control::{seconds, RemoteServer, RpcError};
+use serde_json::{json, Value};
+use vid_overlay::{ImageFormat, StatusReporter};
+
+mod hud;
+mod input;
+mod options;
+mod playlist;
+mod remote;
+
+pub use hud::{Hud, HudStatus};
+pub use input::{keys, spawn_reader, timed_keys, Key, RawTerminal, HELP};
+pub use options::{InitialState, Options, SnapshotFormat};
+pub use playlist::{playbin, EmptyPlaylist, Playlist, SharedPlaylist};
+
+#[derive(Debug, Display, Error)]
+#[display(fmt = "Missing element {}", _0)]
+pub struct MissingElement(#[error(not(source))] pub String);
+
+#[derive(Debug, Display, Error)]
+#[display(fmt = "Seek to {} failed", _0)]
+pub struct SeekFailed(#[error(not(source))] pub gst::ClockTime);
+
+#[derive(Debug, Display, Error)]
+#[display(fmt = "Duration unknown")]
+pub struct NoDuration;
+
+#[derive(Debug, Display, Error)]
+#[display(fmt = "Not playing a playlist")]
+pub struct NoPlaylist;
+
+#[derive(Debug, Display, Error)]
+#[display(fmt = "Loop shorter than {:?}", _0)]
+pub struct LoopTooShort(#[error(not(source))] pub Duration);
+
+#[derive(Debug, Display, Error)]
+#[display(fmt = "Invalid playback rate {}", _0)]
+pub struct InvalidRate(#[error(not(source))] pub f64);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Play,
+    Pause,
+    Toggle,
+    // number of frames to advance, the pipeline is paused first
+    Step(u64),
+    StepBack(u64),
+    Seek(gst::ClockTime),
+    // milliseconds from the current position, negative values go back
+    SeekRelative(i64),
+    SeekStart,
+    SeekEnd,
+    SetSeekMode(SeekMode),
+    ToggleSeekMode,
+    SetRate(f64),
+    // next/previous entry of RATES, keeping the direction
+    Faster,
+    Slower,
+    Reverse,
+    // playlist entries
+    Next,
+    Previous,
+    // A-B repeat between positions marked during playback
+    SetLoopStart,
+    SetLoopEnd,
+    ClearLoop,
+    // repeat the whole stream
+    ToggleLoop,
+    // writes the frame on screen to an image file
+    Snapshot,
+    // ends the stream so the sinks can finish cleanly
+    Eos,
+    Quit,
+}
+
+impl Command {
+    // the keys both front-ends understand
+    pub fn from_key(key: char) -> Option<Command> {
+        match key {
+            'p' => Some(Command::Toggle),
+            'n' => Some(Command::Step(1)),
+            'N' => Some(Command::StepBack(1)),
+            'l' => Some(Command::SeekRelative(5_000)),
+            'h' => Some(Command::SeekRelative(-5_000)),
+            'L' => Some(Command::SeekRelative(60_000)),
+            'H' => Some(Command::SeekRelative(-60_000)),
+            'g' => Some(Command::SeekStart),
+            'G' => Some(Command::SeekEnd),
+            'k' => Some(Command::ToggleSeekMode),
+            '+' => Some(Command::Faster),
+            '-' => Some(Command::Slower),
+            'r' => Some(Command::Reverse),
+            'a' => Some(Command::SetLoopStart),
+            'b' => Some(Command::SetLoopEnd),
+            'c' => Some(Command::ClearLoop),
+            'o' => Some(Command::ToggleLoop),
+            '>' => Some(Command::Next),
+            '<' => Some(Command::Previous),
+            '=' => Some(Command::SetRate(1.0)),
+            's' => Some(Command::Snapshot),
+            'e' => Some(Command::Eos),
+            'q' => Some(Command::Quit),
+            _ => None,
+        }
+    }
+}
+
+// playback speeds offered by Faster/Slower, reverse playback uses the same steps
+pub const RATES: [f64; 5] = [0.25, 0.5, 1.0, 2.0, 4.0];
+
+// shorter A-B loops would keep seeking back without playing anything
+pub const MIN_LOOP: Duration = Duration::from_millis(100);
+
+// the marks of an A-B loop in order, or None when they are too close together
+fn loop_bounds(a: gst::ClockTime, b: gst::ClockTime) -> Option<(gst::ClockTime, gst::ClockTime)> {
+    let (start, end) = if a <= b { (a, b) } else { (b, a) };
+    let length = end.nseconds()?.checked_sub(start.nseconds()?)?;
+
+    if length < MIN_LOOP.as_nanos() as u64 {
+        None
+    } else {
+        Some((start, end))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeekMode {
+    // decode up to the exact position, slower but frame exact
+    Accurate,
+    // jump to the nearest keyframe in the seek direction
+    KeyUnit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    Continue,
+    Quit,
+}
+
+// What happened during playback, for the front-end to show. Remote clients and
+// the MQTT reporter are told through their own notifications.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlaybackEvent {
+    // playlist entry, `index` counting from 0
+    Track {
+        index: usize,
+        len: usize,
+        uri: String,
+    },
+    Snapshot(PathBuf),
+    SeekMode(SeekMode),
+    Looping(bool),
+    Eos,
+    StateChanged {
+        old: gst::State,
+        new: gst::State,
+    },
+}
+
+impl fmt::Display for PlaybackEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PlaybackEvent::Track { index, len, uri } => {
+                write!(f, "Playing {}/{}: {}", index + 1, len, uri)
+            }
+            PlaybackEvent::Snapshot(path) => write!(f, "Snapshot {}", path.display()),
+            PlaybackEvent::SeekMode(mode) => write!(f, "Seek mode {:?}", mode),
+            PlaybackEvent::Looping(looping) => {
+                write!(f, "Looping {}", if *looping { "on" } else { "off" })
+            }
+            PlaybackEvent::Eos => write!(f, "Received EOS"),
+            PlaybackEvent::StateChanged { old, new } => {
+                write!(f, "Pipeline state changed from {:?} to {:?}", old, new)
+            }
+        }
+    }
+}
+
+// Wraps a pipeline with the usual playback controls. Commands can be sent from any
+// thread through `sender()` and are applied by whoever drives the controller.
+pub struct PlaybackController {
+    pipeline: gst::Pipeline,
+    sink: glib::WeakRef<gst::Element>,
+    playing: bool,
+    rate: f64,
+    seek_mode: SeekMode,
+    sender: mpsc::Sender<Command>,
+    commands: mpsc::Receiver<Command>,
+    event_sender: mpsc::Sender<PlaybackEvent>,
+    events: mpsc::Receiver<PlaybackEvent>,
+    remote: Option<RemoteServer>,
+    reporter: Option<StatusReporter>,
+    last_position_event: Instant,
+    hud: Option<Hud>,
+    last_command: String,
+    playlist: Option<SharedPlaylist>,
+    initial_state: gst::State,
+    looping: bool,
+    loop_start: Option<gst::ClockTime>,
+    loop_end: Option<gst::ClockTime>,
+    // the last seek carried the SEGMENT flag, so SegmentDone comes instead of EOS
+    segment_armed: bool,
+    snapshot_dir: PathBuf,
+    snapshot_format: ImageFormat,
+}
+
+impl PlaybackController {
+    // `sink_name` is the element step and EOS events are sent to
+    pub fn new(pipeline: gst::Pipeline, sink_name: &str) -> Result<Self, Error> {
+        let sink = pipeline
+            .get_by_name(sink_name)
+            .ok_or_else(|| MissingElement(sink_name.to_string()))?;
+        Ok(PlaybackController::with_sink(pipeline, sink))
+    }
+
+    // for sinks that can't be looked up by name, e.g. the video sink of a playbin
+    pub fn with_sink(pipeline: gst::Pipeline, sink: gst::Element) -> Self {
+        let (sender, commands) = mpsc::channel();
+        let (event_sender, events) = mpsc::channel();
+
+        PlaybackController {
+            pipeline,
+            sink: sink.downgrade(),
+            playing: false,
+            rate: 1.0,
+            seek_mode: SeekMode::Accurate,
+            sender,
+            commands,
+            event_sender,
+            events,
+            remote: None,
+            reporter: None,
+            last_position_event: Instant::now(),
+            hud: None,
+            last_command: String::new(),
+            playlist: None,
+            initial_state: gst::State::Playing,
+            looping: false,
+            loop_start: None,
+            loop_end: None,
+            segment_armed: false,
+            snapshot_dir: PathBuf::from("."),
+            snapshot_format: ImageFormat::Png,
+        }
+    }
+
+    // plays the entries of `playlist` one after the other through a playbin
+    pub fn with_playlist(playlist: Playlist) -> Result<Self, Error> {
+        let playlist = SharedPlaylist::new(std::sync::Mutex::new(playlist));
+        let (pipeline, sink) = playbin(playlist.clone())?;
+
+        let mut controller = PlaybackController::with_sink(pipeline, sink);
+        controller.playlist = Some(playlist);
+        Ok(controller)
+    }
+
+    // Everything the command line asks for: the inputs or pipeline to play, how
+    // playback starts, and the HUD, MQTT status and remote control. The extras
+    // only warn when they can't be set up, playback goes on without them.
+    pub fn from_options(options: &Options) -> Result<Self, Error> {
+        let mut controller = if options.inputs.is_empty() {
+            let pipeline = gst::parse_launch(&options.pipeline)?;
+            // a single element description has no pipeline around it
+            let pipeline = match pipeline.downcast::<gst::Pipeline>() {
+                Ok(pipeline) => pipeline,
+                Err(element) => {
+                    let pipeline = gst::Pipeline::new(None);
+                    pipeline.add(&element)?;
+                    pipeline
+                }
+            };
+            PlaybackController::new(pipeline, &options.sink)?
+        } else {
+            PlaybackController::with_playlist(Playlist::from_inputs(&options.inputs)?)?
+        };
+
+        controller.set_initial_state(options.initial_state.into());
+        controller.set_looping(options.looping);
+        controller.set_snapshot_dir(&options.snapshot_dir);
+        controller.set_snapshot_format(options.snapshot_format.into());
+
+        // status line over the video, for when the terminal is out of sight
+        if options.hud {
+            if let Err(e) = controller.enable_hud() {
+                eprintln!("No HUD: {}", e);
+            }
+        }
+
+        // presence, stats and errors over MQTT, e.g. `--mqtt 127.0.0.1:1883`
+        if let Some(reporter) = options.status_reporter()? {
+            controller.report_to(reporter);
+        }
+
+        // JSON-RPC over WebSocket, e.g. `--remote 127.0.0.1:9000`
+        if let Some(server) = remote_control::listen(options.remote.as_deref()) {
+            controller.serve(server);
+        }
+
+        Ok(controller)
+    }
+
+    // the state `start` puts the pipeline in, Playing unless changed
+    pub fn set_initial_state(&mut self, state: gst::State) {
+        self.initial_state = state;
+    }
+
+    // Whether playback starts over from the beginning instead of ending. A single
+    // stream loops seamlessly through segment seeks, a playlist starts over on EOS.
+    pub fn set_looping(&mut self, looping: bool) {
+        self.looping = looping;
+        self.rearm_segment();
+    }
+
+    // where and as what `snapshot` writes frames, the current directory and PNG by default
+    pub fn set_snapshot_dir<P: AsRef<Path>>(&mut self, dir: P) {
+        self.snapshot_dir = dir.as_ref().to_path_buf();
+    }
+
+    pub fn set_snapshot_format(&mut self, format: ImageFormat) {
+        self.snapshot_format = format;
+    }
+
+    pub fn loop_range(&self) -> Option<(gst::ClockTime, gst::ClockTime)> {
+        match (self.loop_start, self.loop_end) {
+            (Some(start), Some(end)) => Some((start, end)),
+            _ => None,
+        }
+    }
+
+    // A mark too close to the other one is refused and the loop stays as it was.
+    pub fn set_loop_start(&mut self) -> Result<(), Error> {
+        let start = self.position();
+        self.set_loop(start, self.loop_end)
+    }
+
+    pub fn set_loop_end(&mut self) -> Result<(), Error> {
+        let end = self.position();
+        let start = self
+            .loop_start
+            .or_else(|| Some(gst::ClockTime::from_seconds(0)));
+        self.set_loop(start, end)
+    }
+
+    pub fn clear_loop(&mut self) {
+        self.loop_start = None;
+        self.loop_end = None;
+        self.rearm_segment();
+    }
+
+    fn set_loop(
+        &mut self,
+        start: Option<gst::ClockTime>,
+        end: Option<gst::ClockTime>,
+    ) -> Result<(), Error> {
+        let (start, end) = match (start, end) {
+            (Some(a), Some(b)) => {
+                let (start, end) = loop_bounds(a, b).ok_or(LoopTooShort(MIN_LOOP))?;
+                (Some(start), Some(end))
+            }
+            range => range,
+        };
+
+        self.loop_start = start;
+        self.loop_end = end;
+        self.rearm_segment();
+        Ok(())
+    }
+
+    fn segment_looping(&self) -> bool {
+        self.loop_range().is_some() || (self.looping && self.playlist.is_none())
+    }
+
+    // seeks in place so the SEGMENT flag and the loop bounds follow the loop settings
+    fn rearm_segment(&mut self) {
+        if !self.segment_looping() && !self.segment_armed {
+            return;
+        }
+
+        // before preroll this fails, the segment is armed once the pipeline is paused
+        if let Some(position) = self.position() {
+            let flags = gst::SeekFlags::FLUSH | gst::SeekFlags::ACCURATE;
+            let _ = self.seek_with_flags(position, flags);
+        }
+    }
+
+    // starts the next round of the loop, the seek is not flushing so it is seamless
+    fn loop_again(&mut self) -> Result<(), Error> {
+        let (start, end) = match self.loop_range() {
+            Some((start, end)) => (start, Some(end)),
+            None => (gst::ClockTime::from_seconds(0), self.duration()),
+        };
+
+        let position = if self.rate > 0. {
+            start
+        } else {
+            end.ok_or(NoDuration)?
+        };
+        self.seek_with_flags(position, gst::SeekFlags::SEGMENT | gst::SeekFlags::ACCURATE)
+    }
+
+    pub fn is_looping(&self) -> bool {
+        self.looping
+    }
+
+    pub fn start(&mut self) -> Result<(), Error> {
+        match self.initial_state {
+            gst::State::Paused => self.pause(),
+            _ => self.play(),
+        }
+    }
+
+    // back to the first entry of a playlist or the start of the stream
+    fn restart(&mut self) -> Result<(), Error> {
+        let index = self
+            .playlist
+            .as_ref()
+            .map(|playlist| playlist.lock().unwrap().index())
+            .unwrap_or(0);
+
+        if index > 0 {
+            self.skip(-(index as isize))
+        } else {
+            self.seek_start()
+        }
+    }
+
+    pub fn playlist(&self) -> Option<&SharedPlaylist> {
+        self.playlist.as_ref()
+    }
+
+    // jumps `offset` entries through the playlist, staying put at either end
+    pub fn skip(&mut self, offset: isize) -> Result<(), Error> {
+        let playlist = self.playlist.as_ref().ok_or(NoPlaylist)?;
+
+        let (index, len, uri) = {
+            let mut playlist = playlist.lock().unwrap();
+            let index = playlist.index() as isize + offset;
+            if index < 0 || index as usize >= playlist.len() {
+                return Ok(());
+            }
+
+            let uri = playlist.select(index as usize).unwrap().to_string();
+            (index as usize, playlist.len(), uri)
+        };
+
+        // playbin only takes a new uri when it is not running
+        let was_playing = self.playing;
+        self.pipeline.set_state(gst::State::Ready)?;
+        self.pipeline.set_property("uri", &uri)?;
+        self.rate = 1.0;
+        if was_playing {
+            self.play()?;
+        } else {
+            self.pause()?;
+        }
+
+        self.emit(PlaybackEvent::Track { index, len, uri });
+        Ok(())
+    }
+
+    pub fn pipeline(&self) -> &gst::Pipeline {
+        &self.pipeline
+    }
+
+    pub fn sender(&self) -> mpsc::Sender<Command> {
+        self.sender.clone()
+    }
+
+    // events since the last call, never blocks
+    pub fn events(&self) -> mpsc::TryIter<'_, PlaybackEvent> {
+        self.events.try_iter()
+    }
+
+    fn emit(&self, event: PlaybackEvent) {
+        let _ = self.event_sender.send(event);
+    }
+
+    // also take commands from remote clients and push state and position events to them
+    pub fn serve(&mut self, server: RemoteServer) {
+        self.remote = Some(server);
+    }
+
+    // publishes presence, periodic stats and playback events over MQTT
+    pub fn report_to(&mut self, reporter: StatusReporter) {
+        self.reporter = Some(reporter);
+    }
+
+    // draws a status line over the video at the sink, shown after every command
+    pub fn enable_hud(&mut self) -> Result<(), Error> {
+        let sink = self
+            .sink
+            .upgrade()
+            .ok_or_else(|| MissingElement("sink".to_string()))?;
+        let hud = Hud::attach(&sink).ok_or_else(|| MissingElement("sink pad".to_string()))?;
+
+        self.hud = Some(hud);
+        Ok(())
+    }
+
+    // While paused no new frames arrive to draw on, so the HUD only changes on
+    // screen when the frame is prerolled again: after a command and once it is
+    // gone. The fade out itself is only seen during playback.
+    fn update_hud(&mut self) -> Result<(), Error> {
+        let status = HudStatus {
+            playing: self.playing,
+            position: self.position(),
+            duration: self.duration(),
+            rate: self.rate,
+            last_command: &self.last_command,
+        };
+
+        let hidden = match self.hud {
+            Some(ref mut hud) => hud.update(&status),
+            None => false,
+        };
+        if hidden && !self.playing {
+            self.refresh_frame()?;
+        }
+        Ok(())
+    }
+
+    // prerolls the current frame again with a flushing seek in place, accurate
+    // whatever the seek mode so it stays on the same frame
+    fn refresh_frame(&mut self) -> Result<(), Error> {
+        if let Some(position) = self.position() {
+            self.seek_with_flags(position, gst::SeekFlags::FLUSH | gst::SeekFlags::ACCURATE)?;
+        }
+        Ok(())
+    }
+
+    fn show_hud(&mut self) {
+        if let Some(ref mut hud) = self.hud {
+            hud.show();
+        }
+    }
+
+    pub fn status(&self) -> Value {
+        json!({
+            "state": format!("{:?}", self.pipeline.get_current_state()),
+            "playing": self.playing,
+            "position": seconds(self.position()),
+            "duration": seconds(self.duration()),
+            "rate": self.rate,
+            "seek_mode": format!("{:?}", self.seek_mode),
+            "looping": self.looping,
+            "loop": self.loop_range().map(|(start, end)| json!({
+                "start": seconds(Some(start)),
+                "end": seconds(Some(end)),
+            })),
+        })
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    pub fn rate(&self) -> f64 {
+        self.rate
+    }
+
+    pub fn seek_mode(&self) -> SeekMode {
+        self.seek_mode
+    }
+
+    pub fn set_seek_mode(&mut self, mode: SeekMode) {
+        self.seek_mode = mode;
+    }
+
+    pub fn position(&self) -> Option<gst::ClockTime> {
+        self.pipeline.query_position::<gst::ClockTime>()
+    }
+
+    pub fn duration(&self) -> Option<gst::ClockTime> {
+        self.pipeline.query_duration::<gst::ClockTime>()
+    }
+
+    pub fn play(&mut self) -> Result<(), Error> {
+        self.pipeline.set_state(gst::State::Playing)?;
+        self.playing = true;
+        Ok(())
+    }
+
+    pub fn pause(&mut self) -> Result<(), Error> {
+        self.pipeline.set_state(gst::State::Paused)?;
+        self.playing = false;
+        Ok(())
+    }
+
+    pub fn toggle(&mut self) -> Result<(), Error> {
+        if self.playing {
+            self.pause()
+        } else {
+            self.play()
+        }
+    }
+
+    pub fn step(&mut self, frames: u64) -> Result<(), Error> {
+        self.step_in_direction(frames, true)
+    }
+
+    // stepping backwards needs reverse playback, the rate is flipped if necessary
+    pub fn step_back(&mut self, frames: u64) -> Result<(), Error> {
+        self.step_in_direction(frames, false)
+    }
+
+    fn step_in_direction(&mut self, frames: u64, forward: bool) -> Result<(), Error> {
+        if self.playing {
+            self.pause()?;
+        }
+        if (self.rate > 0.) != forward {
+            self.set_rate(-self.rate)?;
+        }
+
+        let sink = self
+            .sink
+            .upgrade()
+            .ok_or_else(|| MissingElement("sink".to_string()))?;
+        let ev = gst::event::Step::new(
+            gst::format::Buffers(Some(frames)),
+            self.rate.abs(),
+            true,
+            false,
+        );
+        sink.send_event(ev);
+        Ok(())
+    }
+
+    pub fn seek(&mut self, position: gst::ClockTime) -> Result<(), Error> {
+        let forward = self.position().map(|p| position >= p).unwrap_or(true);
+        self.seek_with_flags(position, self.seek_flags(forward))
+    }
+
+    fn seek_flags(&self, forward: bool) -> gst::SeekFlags {
+        match self.seek_mode {
+            SeekMode::Accurate => gst::SeekFlags::FLUSH | gst::SeekFlags::ACCURATE,
+            SeekMode::KeyUnit if forward => {
+                gst::SeekFlags::FLUSH | gst::SeekFlags::KEY_UNIT | gst::SeekFlags::SNAP_AFTER
+            }
+            SeekMode::KeyUnit => {
+                gst::SeekFlags::FLUSH | gst::SeekFlags::KEY_UNIT | gst::SeekFlags::SNAP_BEFORE
+            }
+        }
+    }
+
+    fn seek_with_flags(
+        &mut self,
+        position: gst::ClockTime,
+        flags: gst::SeekFlags,
+    ) -> Result<(), Error> {
+        let mut flags = flags;
+        let mut position = position;
+        let (mut start, mut stop) = (gst::ClockTime::from_seconds(0), gst::CLOCK_TIME_NONE);
+
+        // a looping segment ends with SegmentDone instead of EOS, and seeks stay
+        // inside the A-B range
+        let looping = self.segment_looping();
+        if looping {
+            flags |= gst::SeekFlags::SEGMENT;
+
+            if let Some((loop_start, loop_end)) = self.loop_range() {
+                start = loop_start;
+                stop = loop_end;
+                if position < loop_start || position > loop_end {
+                    position = if self.rate > 0. { loop_start } else { loop_end };
+                }
+            }
+        }
+
+        // playing backwards runs from the stop position towards the start
+        let result = if self.rate > 0. {
+            let stop_type = if stop.is_some() {
+                gst::SeekType::Set
+            } else {
+                gst::SeekType::None
+            };
+            self.pipeline.seek(
+                self.rate,
+                flags,
+                gst::SeekType::Set,
+                position,
+                stop_type,
+                stop,
+            )
+        } else {
+            self.pipeline.seek(
+                self.rate,
+                flags,
+                gst::SeekType::Set,
+                start,
+                gst::SeekType::Set,
+                position,
+            )
+        };
+
+        result.map_err(|_| SeekFailed(position))?;
+        self.segment_armed = looping;
+        Ok(())
+    }
+
+    pub fn seek_relative(&mut self, offset_ms: i64) -> Result<(), Error> {
+        let current = self.position().and_then(|p| p.nseconds()).unwrap_or(0);
+        let offset = offset_ms.unsigned_abs().saturating_mul(1_000_000);
+
+        let mut target = if offset_ms < 0 {
+            current.saturating_sub(offset)
+        } else {
+            current.saturating_add(offset)
+        };
+        if let Some(duration) = self.duration().and_then(|d| d.nseconds()) {
+            target = target.min(duration);
+        }
+
+        let flags = self.seek_flags(offset_ms >= 0);
+        self.seek_with_flags(gst::ClockTime::from_nseconds(target), flags)
+    }
+
+    pub fn seek_start(&mut self) -> Result<(), Error> {
+        let flags = self.seek_flags(false);
+        self.seek_with_flags(gst::ClockTime::from_seconds(0), flags)
+    }
+
+    // pauses first, so the last frame stays on screen instead of running into EOS
+    pub fn seek_end(&mut self) -> Result<(), Error> {
+        let duration = self
+            .duration()
+            .and_then(|d| d.nseconds())
+            .ok_or(NoDuration)?;
+
+        if self.playing && self.rate > 0. {
+            self.pause()?;
+        }
+
+        let target = gst::ClockTime::from_nseconds(duration.saturating_sub(gst::MSECOND_VAL));
+        let flags = self.seek_flags(true);
+        self.seek_with_flags(target, flags)
+    }
+
+    pub fn set_rate(&mut self, rate: f64) -> Result<(), Error> {
+        if rate == 0. || !rate.is_finite() {
+            return Err(InvalidRate(rate).into());
+        }
+
+        // a rate only takes effect with a seek, so seek to where we are now. The
+        // seek is always accurate so changing speed never jumps to a keyframe.
+        let position = self
+            .position()
+            .unwrap_or_else(|| gst::ClockTime::from_seconds(0));
+        let previous = self.rate;
+        self.rate = rate;
+        let flags = gst::SeekFlags::FLUSH | gst::SeekFlags::ACCURATE;
+        if let Err(err) = self.seek_with_flags(position, flags) {
+            self.rate = previous;
+            return Err(err);
+        }
+
+        Ok(())
+    }
+
+    // moves one entry through RATES, `faster` is relative to the absolute speed
+    pub fn change_rate(&mut self, faster: bool) -> Result<(), Error> {
+        let speed = self.rate.abs();
+        let next = if faster {
+            RATES.iter().find(|r| **r > speed + f64::EPSILON)
+        } else {
+            RATES.iter().rev().find(|r| **r < speed - f64::EPSILON)
+        };
+
+        match next {
+            Some(speed) => self.set_rate(speed.copysign(self.rate)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn reverse(&mut self) -> Result<(), Error> {
+        self.set_rate(-self.rate)
+    }
+
+    pub fn send_eos(&mut self) -> Result<(), Error> {
+        // a paused sink never gets to handle the EOS
+        if !self.playing {
+            self.play()?;
+        }
+
+        let sink = self
+            .sink
+            .upgrade()
+            .ok_or_else(|| MissingElement("sink".to_string()))?;
+        sink.send_event(gst::event::Eos::new());
+        Ok(())
+    }
+
+    // Saves the last frame the sink rendered and hands the file written to `done`,
+    // from the main context once the frame is encoded. `run` iterates that context,
+    // other callers need a main loop.
+    pub fn snapshot<F>(&self, done: F)
+    where
+        F: FnOnce(Result<PathBuf, Error>) + Send + 'static,
+    {
+        let element = match self.sink.upgrade() {
+            Some(sink) => sink,
+            None => self.pipeline.clone().upcast(),
+        };
+
+        let (dir, format) = (&self.snapshot_dir, self.snapshot_format);
+        let events = self.event_sender.clone();
+        vid_overlay::snapshot(&element, dir, format, move |path| {
+            if let Ok(ref path) = path {
+                let _ = events.send(PlaybackEvent::Snapshot(path.clone()));
+            }
+            done(path)
+        });
+    }
+
+    pub fn handle_command(&mut self, command: Command) -> Result<Flow, Error> {
+        self.last_command = format!("{:?}", command);
+        self.show_hud();
+
+        let pausing = match command {
+            Command::Pause => true,
+            Command::Toggle => self.playing,
+            _ => false,
+        };
+
+        match command {
+            Command::Play => self.play()?,
+            Command::Pause => self.pause()?,
+            Command::Toggle => self.toggle()?,
+            Command::Step(frames) => self.step(frames)?,
+            Command::StepBack(frames) => self.step_back(frames)?,
+            Command::Seek(position) => self.seek(position)?,
+            Command::SeekRelative(offset_ms) => self.seek_relative(offset_ms)?,
+            Command::SeekStart => self.seek_start()?,
+            Command::SeekEnd => self.seek_end()?,
+            Command::SetSeekMode(mode) => self.set_seek_mode(mode),
+            Command::ToggleSeekMode => {
+                self.seek_mode = match self.seek_mode {
+                    SeekMode::Accurate => SeekMode::KeyUnit,
+                    SeekMode::KeyUnit => SeekMode::Accurate,
+                };
+                self.emit(PlaybackEvent::SeekMode(self.seek_mode));
+            }
+            Command::SetRate(rate) => self.set_rate(rate)?,
+            Command::Faster => self.change_rate(true)?,
+            Command::Slower => self.change_rate(false)?,
+            Command::Reverse => self.reverse()?,
+            Command::Next => self.skip(1)?,
+            Command::Previous => self.skip(-1)?,
+            Command::SetLoopStart => self.set_loop_start()?,
+            Command::SetLoopEnd => self.set_loop_end()?,
+            Command::ClearLoop => self.clear_loop(),
+            Command::ToggleLoop => {
+                let looping = !self.looping;
+                self.set_looping(looping);
+                self.emit(PlaybackEvent::Looping(looping));
+            }
+            Command::Snapshot => self.snapshot(|path| {
+                if let Err(err) = path {
+                    eprintln!("Snapshot failed: {}", err);
+                }
+            }),
+            Command::Eos => self.send_eos()?,
+            Command::Quit => return Ok(Flow::Quit),
+        }
+
+        // the paused frame is drawn again so the HUD reflects the pause
+        self.update_hud()?;
+        if pausing && self.hud.is_some() {
+            self.refresh_frame()?;
+        }
+
+        Ok(Flow::Continue)
+    }
+
+    // applies every command sent since the last call
+    pub fn process_commands(&mut self) -> Flow {
+        while let Ok(command) = self.commands.try_recv() {
+            match self.handle_command(command.clone()) {
+                Ok(Flow::Quit) => return Flow::Quit,
+                Ok(Flow::Continue) => (),
+                Err(err) => eprintln!("{:?} failed: {}", command, err),
+            }
+        }
+
+        let flow = self.process_remote();
+        if let Err(err) = self.update_hud() {
+            eprintln!("HUD update failed: {}", err);
+        }
+        if let Some(ref mut reporter) = self.reporter {
+            reporter.tick(&self.pipeline);
+        }
+
+        flow
+    }
+
+    fn process_remote(&mut self) -> Flow {
+        let requests: Vec<_> = match self.remote {
+            Some(ref server) => server.requests().collect(),
+            None => return Flow::Continue,
+        };
+
+        let mut flow = Flow::Continue;
+        for request in requests {
+            if request.method == "status" {
+                let status = self.status();
+                request.respond(Ok(status));
+                continue;
+            }
+
+            // answered with the file written rather than the status
+            if request.method == "snapshot" {
+                self.snapshot(move |path| {
+                    let result = path
+                        .map(|path| json!({ "path": path.display().to_string() }))
+                        .map_err(|err| RpcError::internal(&err.to_string()));
+                    request.respond(result);
+                });
+                continue;
+            }
+
+            let command = match remote::command(&request) {
+                Ok(command) => command,
+                Err(err) => {
+                    request.respond(Err(err));
+                    continue;
+                }
+            };
+
+            match self.handle_command(command) {
+                Ok(result) => {
+                    request.respond(Ok(self.status()));
+                    if result == Flow::Quit {
+                        flow = Flow::Quit;
+                    }
+                }
+                Err(err) => request.respond(Err(RpcError::internal(&err.to_string()))),
+            }
+        }
+
+        if self.last_position_event.elapsed() >= Duration::from_secs(1) {
+            self.last_position_event = Instant::now();
+            self.notify(
+                "position",
+                json!({
+                    "position": seconds(self.position()),
+                    "duration": seconds(self.duration()),
+                    "rate": self.rate,
+                }),
+            );
+        }
+
+        flow
+    }
+
+    fn notify(&self, method: &str, params: Value) {
+        if let Some(ref server) = self.remote {
+            server.notify(method, params);
+        }
+    }
+
+    fn report(&self, kind: &str, data: Value) {
+        if let Some(ref reporter) = self.reporter {
+            reporter.event(kind, data);
+        }
+    }
+
+    pub fn handle_message(&mut self, msg: &gst::Message) -> Flow {
+        use gst::MessageView;
+
+        match msg.view() {
+            MessageView::Eos(..) => {
+                self.emit(PlaybackEvent::Eos);
+                self.report("eos", json!({ "Looping": self.looping }));
+                if !self.looping {
+                    return Flow::Quit;
+                }
+
+                if let Err(err) = self.restart() {
+                    eprintln!("Could not start over: {}", err);
+                    return Flow::Quit;
+                }
+            }
+            MessageView::Error(err) => {
+                eprintln!(
+                    "Error from {:?}: {} ({:?})",
+                    err.get_src().map(|s| s.get_path_string()),
+                    err.get_error(),
+                    err.get_debug()
+                );
+                if let Some(ref reporter) = self.reporter {
+                    let src = err
+                        .get_src()
+                        .map(|s| String::from(s.get_path_string()))
+                        .unwrap_or_else(|| String::from("None"));
+                    let debug = err.get_debug();
+                    reporter.error(&src, &err.get_error().to_string(), debug.as_deref());
+                }
+                return Flow::Quit;
+            }
+            MessageView::SegmentDone(..) => {
+                if let Err(err) = self.loop_again() {
+                    eprintln!("Could not loop: {}", err);
+                    return Flow::Quit;
+                }
+            }
+            MessageView::StreamStart(..) => {
+                let started = self.playlist.as_ref().and_then(playlist::stream_started);
+                if let (Some((index, uri)), Some(playlist)) = (started, self.playlist.as_ref()) {
+                    let len = playlist.lock().unwrap().len();
+                    self.notify("track", json!({"index": index, "uri": uri}));
+                    self.report("track", json!({"Index": index, "Uri": uri}));
+                    self.emit(PlaybackEvent::Track { index, len, uri });
+                    self.show_hud();
+                }
+            }
+            MessageView::StateChanged(state_changed) => {
+                if state_changed
+                    .get_src()
+                    .map(|s| s == self.pipeline)
+                    .unwrap_or(false)
+                {
+                    let new_state = state_changed.get_current();
+                    let old_state = state_changed.get_old();
+
+                    self.playing = new_state == gst::State::Playing;
+                    self.show_hud();
+
+                    // seeks only work once prerolled, arm the loop as early as possible
+                    if old_state == gst::State::Ready
+                        && new_state == gst::State::Paused
+                        && self.segment_looping()
+                        && !self.segment_armed
+                    {
+                        self.rearm_segment();
+                    }
+
+                    self.notify(
+                        "state-changed",
+                        json!({"old": format!("{:?}", old_state), "new": format!("{:?}", new_state)}),
+                    );
+                    self.report(
+                        "state-changed",
+                        json!({"Old": format!("{:?}", old_state), "New": format!("{:?}", new_state)}),
+                    );
+                    self.emit(PlaybackEvent::StateChanged {
+                        old: old_state,
+                        new: new_state,
+                    });
+                }
+            }
+            _ => (),
+        }
+
+        Flow::Continue
+    }
+
+    // plays the pipeline until EOS, an error or a quit command, polling the bus
+    // and the command channel in turn and handing the events to `on_event`
+    pub fn run<F: FnMut(PlaybackEvent)>(&mut self, mut on_event: F) -> Result<(), Error> {
+        let bus = self.pipeline.get_bus().unwrap();
+        let context = glib::MainContext::ref_thread_default();
+        self.start()?;
+
+        loop {
+            if self.process_commands() == Flow::Quit {
+                break;
+            }
+
+            if let Some(msg) = bus.timed_pop(gst::MSECOND * 100) {
+                if self.handle_message(&msg) == Flow::Quit {
+                    break;
+                }
+            }
+
+            // snapshots report back through the main context
+            while context.iteration(false) {}
+            self.events().for_each(&mut on_event);
+        }
+
+        self.pipeline.set_state(gst::State::Null)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> gst::ClockTime {
+        gst::ClockTime::from_mseconds(ms)
+    }
+
+    // None when videotestsrc or fakesink are not installed
+    fn controller() -> Option<PlaybackController> {
+        gst::init().unwrap();
+
+        let missing = ["videotestsrc", "fakesink"]
+            .iter()
+            .find(|factory| gst::ElementFactory::find(factory).is_none());
+        if let Some(factory) = missing {
+            eprintln!("Skipped, no {}", factory);
+            return None;
+        }
+
+        let pipeline = gst::parse_launch("videotestsrc ! fakesink name=sink").unwrap();
+        let pipeline = pipeline.downcast::<gst::Pipeline>().unwrap();
+        Some(PlaybackController::new(pipeline, "sink").unwrap())
+    }
+
+    // waits for pending state changes and flushing seeks to finish
+    fn settled(controller: &PlaybackController) -> gst::State {
+        let (result, state, _) = controller
+            .pipeline
+            .get_state(gst::ClockTime::from_seconds(5));
+        result.unwrap();
+        state
+    }
+
+    fn position(controller: &PlaybackController) -> u64 {
+        settled(controller);
+        controller.position().and_then(|p| p.nseconds()).unwrap()
+    }
+
+    fn stop(controller: &PlaybackController) {
+        controller.pipeline.set_state(gst::State::Null).unwrap();
+    }
+
+    #[test]
+    fn loop_bounds_are_ordered() {
+        assert_eq!(loop_bounds(ms(1000), ms(3000)), Some((ms(1000), ms(3000))));
+        assert_eq!(loop_bounds(ms(3000), ms(1000)), Some((ms(1000), ms(3000))));
+    }
+
+    #[test]
+    fn empty_and_short_loops_are_refused() {
+        assert_eq!(loop_bounds(ms(2000), ms(2000)), None);
+        assert_eq!(loop_bounds(ms(2000), ms(2099)), None);
+        assert_eq!(loop_bounds(ms(2099), ms(2000)), None);
+        assert_eq!(loop_bounds(ms(2000), ms(2100)), Some((ms(2000), ms(2100))));
+        assert_eq!(loop_bounds(gst::CLOCK_TIME_NONE, ms(2000)), None);
+    }
+
+    #[test]
+    fn play_pause_and_toggle() {
+        let mut controller = match controller() {
+            Some(controller) => controller,
+            None => return,
+        };
+
+        controller.pause().unwrap();
+        assert!(!controller.is_playing());
+        assert_eq!(settled(&controller), gst::State::Paused);
+
+        controller.play().unwrap();
+        assert!(controller.is_playing());
+        assert_eq!(settled(&controller), gst::State::Playing);
+
+        controller.toggle().unwrap();
+        assert!(!controller.is_playing());
+        assert_eq!(settled(&controller), gst::State::Paused);
+
+        controller.toggle().unwrap();
+        assert!(controller.is_playing());
+        assert_eq!(settled(&controller), gst::State::Playing);
+
+        stop(&controller);
+    }
+
+    #[test]
+    fn stepping_pauses() {
+        let mut controller = match controller() {
+            Some(controller) => controller,
+            None => return,
+        };
+
+        controller.play().unwrap();
+        settled(&controller);
+        controller.step(1).unwrap();
+        assert!(!controller.is_playing());
+        assert_eq!(settled(&controller), gst::State::Paused);
+        assert!(controller.rate() > 0.);
+
+        stop(&controller);
+    }
+
+    #[test]
+    fn relative_seeks_stay_inside_the_stream() {
+        let mut controller = match controller() {
+            Some(controller) => controller,
+            None => return,
+        };
+
+        controller.pause().unwrap();
+        settled(&controller);
+
+        controller.seek_relative(2000).unwrap();
+        assert_eq!(position(&controller), ms(2000).nseconds().unwrap());
+
+        controller.seek_relative(-500).unwrap();
+        assert_eq!(position(&controller), ms(1500).nseconds().unwrap());
+
+        controller.seek_relative(-5000).unwrap();
+        assert_eq!(position(&controller), 0);
+
+        // videotestsrc runs forever unless the pipeline reports an end
+        let duration = controller.duration().and_then(|d| d.nseconds());
+        if let Some(duration) = duration {
+            controller.seek_relative(i64::MAX).unwrap();
+            assert!(position(&controller) <= duration);
+        }
+
+        stop(&controller);
+    }
+
+    #[test]
+    fn zero_and_non_finite_rates_are_refused() {
+        let mut controller = match controller() {
+            Some(controller) => controller,
+            None => return,
+        };
+
+        controller.pause().unwrap();
+        settled(&controller);
+
+        for rate in &[0., -0., f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            let err = controller.set_rate(*rate).unwrap_err();
+            assert!(err.downcast_ref::<InvalidRate>().is_some());
+            assert_eq!(controller.rate(), 1.0);
+        }
+
+        controller.set_rate(2.0).unwrap();
+        assert_eq!(controller.rate(), 2.0);
+
+        stop(&controller);
+    }
+
+    #[test]
+    fn events_read_like_the_old_messages() {
+        let track = PlaybackEvent::Track {
+            index: 0,
+            len: 3,
+            uri: "file:///a.mp4".to_string(),
+        };
+        assert_eq!(track.to_string(), "Playing 1/3: file:///a.mp4");
+        assert_eq!(PlaybackEvent::Looping(false).to_string(), "Looping off");
+        assert_eq!(PlaybackEvent::Eos.to_string(), "Received EOS");
+    }
+
+    #[test]
+    fn events_are_drained() {
+        let controller = match controller() {
+            Some(controller) => controller,
+            None => return,
+        };
+
+        controller.emit(PlaybackEvent::Eos);
+        controller.emit(PlaybackEvent::Looping(true));
+        let events: Vec<_> = controller.events().collect();
+        assert_eq!(
+            events,
+            vec![PlaybackEvent::Eos, PlaybackEvent::Looping(true)]
+        );
+        assert_eq!(controller.events().count(), 0);
+    }
+}
@@ -1,120 +1,74 @@
 extern crate gstreamer as gst;
 
 use gst::prelude::*;
-use std::io::{self, Read, Write};
-use std::thread;
+use std::cell::RefCell;
+use std::rc::Rc;
 
-struct CustomData {
-    pipeline: gst::Pipeline,
-    sink: glib::WeakRef<gst::Element>,
-    playing: bool,
-    // rate: f64,
-}
+use gst_play_pause::{spawn_reader, Flow, Options, PlaybackController, RawTerminal, HELP};
 
 fn example_main() {
     gst::init().unwrap();
 
     let main_loop = glib::MainLoop::new(None, false);
 
-    let pipeline = gst::parse_launch("videotestsrc ! autovideosink name=sink").unwrap();
-
-    pipeline.set_state(gst::State::Playing).unwrap();
-    let pipeline = pipeline.dynamic_cast::<gst::Pipeline>().unwrap();
-
-    let bus = pipeline.get_bus().unwrap();
+    let options = Options::from_args();
 
-    let sink = pipeline.get_by_name("sink").unwrap();
-    let weak_sink = sink.downgrade();
-
-    let mut custom_data = CustomData {
-        pipeline,
-        sink: weak_sink,
-        playing: true,
-        // rate: 1.0,
+    let controller = match PlaybackController::from_options(&options) {
+        Ok(controller) => controller,
+        Err(e) => {
+            eprintln!("Error! {}", e);
+            return;
+        }
     };
 
-    let pipeline_weak = custom_data.pipeline.downgrade();
+    let bus = controller.pipeline().get_bus().unwrap();
+    let commands = controller.sender();
+    let controller = Rc::new(RefCell::new(controller));
 
     let main_loop_clone = main_loop.clone();
-
-    bus.add_watch(move |_, msg| {
-        use gst::MessageView;
-        let main_loop = &main_loop_clone.clone();
-
-        match msg.view() {
-            MessageView::Eos(..) => {
-                println!("Received EOS event");
-                main_loop.quit();
-            }
-            MessageView::Error(_) => {
-                main_loop.quit();
-            }
-            MessageView::StateChanged(state_changed) => {
-                if state_changed
-                    .get_src()
-                    .map(|s| s == pipeline_weak.upgrade().unwrap())
-                    .unwrap_or(false)
-                {
-                    let new_state = state_changed.get_current();
-                    let old_state = state_changed.get_old();
-
-                    println!(
-                        "Pipeline state changed from {:?} to {:?}",
-                        &old_state, &new_state
-                    );
-                }
-            }
-            _ => (),
-        };
+    let controller_clone = controller.clone();
+    bus.add_watch_local(move |_, msg| {
+        let mut controller = controller_clone.borrow_mut();
+        let flow = controller.handle_message(msg);
+        controller.events().for_each(|event| println!("{}", event));
+        if flow == Flow::Quit {
+            main_loop_clone.quit();
+        }
 
         glib::Continue(true)
     })
     .expect("Failed to add watch");
 
-    handle_keyevent(&mut custom_data);
-    main_loop.run();
+    // commands from the input thread are applied on the main loop
+    let main_loop_clone = main_loop.clone();
+    let controller_clone = controller.clone();
+    glib::timeout_add_local(50, move || {
+        let mut controller = controller_clone.borrow_mut();
+        let flow = controller.process_commands();
+        controller.events().for_each(|event| println!("{}", event));
+        if flow == Flow::Quit {
+            main_loop_clone.quit();
+        }
 
-    custom_data.pipeline.set_state(gst::State::Null).unwrap();
-}
+        glib::Continue(true)
+    });
 
-fn handle_keyevent(custom_data: &mut CustomData) {
-    for b in io::stdin().bytes() {
-        let c = b.unwrap() as char;
+    let _terminal = RawTerminal::enable()
+        .map_err(|err| eprintln!("Keys need Enter, no raw terminal: {}", err))
+        .ok();
+    println!("{}", HELP);
+    spawn_reader(commands);
 
-        match &c {
-            'p' => {
-                if custom_data.playing {
-                    custom_data.playing = false;
-                    custom_data.pipeline.set_state(gst::State::Paused).unwrap();
-                } else {
-                    custom_data.playing = true;
-                    custom_data.pipeline.set_state(gst::State::Playing).unwrap();
-                }
-            }
-            'n' => {
-                if let Some(sink) = custom_data.sink.upgrade() {
-                    let ev = gst::event::Step::new(gst::format::Buffers(Some(1)), 1.0, true, false);
-                    sink.send_event(ev);
-                } else {
-                    println!("Failed to get sink from pipeline");
-                }
-            }
-            'q' => {
-                if !custom_data.playing {
-                    custom_data.pipeline.set_state(gst::State::Playing).unwrap();
-                };
+    controller.borrow_mut().start().unwrap();
+    main_loop.run();
 
-                if let Some(sink) = custom_data.sink.upgrade() {
-                    let ev = gst::event::Eos::new();
-                    sink.send_event(ev);
-                    break;
-                }
-            }
-            _ => (),
-        }
-    }
-    println!("exiting for loop in keyevent");
+    controller
+        .borrow()
+        .pipeline()
+        .set_state(gst::State::Null)
+        .unwrap();
 }
+
 fn main() {
     example_main();
 }
@@ -0,0 +1,184 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::Error;
+use clap::{Parser, ValueEnum};
+use vid_overlay::{mqtt_options, ImageFormat, StatusReporter, StatusTopics};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum InitialState {
+    Playing,
+    Paused,
+}
+
+impl From<InitialState> for gst::State {
+    fn from(state: InitialState) -> gst::State {
+        match state {
+            InitialState::Playing => gst::State::Playing,
+            InitialState::Paused => gst::State::Paused,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SnapshotFormat {
+    Png,
+    Jpeg,
+}
+
+impl From<SnapshotFormat> for ImageFormat {
+    fn from(format: SnapshotFormat) -> ImageFormat {
+        match format {
+            SnapshotFormat::Png => ImageFormat::Png,
+            SnapshotFormat::Jpeg => ImageFormat::Jpeg,
+        }
+    }
+}
+
+// stats every tenth of a second at most and at least once a day
+fn parse_interval(value: &str) -> Result<f64, String> {
+    let seconds: f64 = value
+        .parse()
+        .map_err(|_| format!("not a number: {}", value))?;
+    if !(0.1..=86_400.).contains(&seconds) {
+        return Err("must be between 0.1 and 86400 seconds".to_string());
+    }
+    Ok(seconds)
+}
+
+// command line shared by both front-ends
+#[derive(Debug, Parser)]
+#[command(about = "Interactive playback of files, playlists or any pipeline")]
+pub struct Options {
+    /// Files, URIs or M3U playlists, played one after the other
+    #[arg(conflicts_with = "pipeline")]
+    pub inputs: Vec<String>,
+
+    /// gst-launch style pipeline description to control instead of files
+    #[arg(short, long, default_value = "videotestsrc ! autovideosink name=sink")]
+    pub pipeline: String,
+
+    /// Name of the element step and EOS events are sent to
+    #[arg(short, long, default_value = "sink")]
+    pub sink: String,
+
+    /// State the pipeline is put in at startup
+    #[arg(long, value_enum, default_value = "playing")]
+    pub initial_state: InitialState,
+
+    /// Start over instead of quitting at the end of the stream
+    #[arg(short = 'l', long = "loop")]
+    pub looping: bool,
+
+    /// Address for the JSON-RPC endpoint, e.g. 127.0.0.1:9000
+    #[arg(long)]
+    pub remote: Option<String>,
+
+    /// Show a status line over the video
+    #[arg(long)]
+    pub hud: bool,
+
+    /// Directory snapshots are written to
+    #[arg(long, default_value = ".")]
+    pub snapshot_dir: PathBuf,
+
+    /// Image format of snapshots
+    #[arg(long, value_enum, default_value = "png")]
+    pub snapshot_format: SnapshotFormat,
+
+    /// MQTT broker to publish presence, stats and errors to, e.g. 127.0.0.1:1883
+    #[arg(long)]
+    pub mqtt: Option<String>,
+
+    /// MQTT client id, also used in the status topics
+    #[arg(long, default_value = "gst-play-pause")]
+    pub mqtt_client_id: String,
+
+    /// Station id of the status topics and presence messages
+    #[arg(long, default_value_t = 4)]
+    pub station: u32,
+
+    /// Seconds between stats messages
+    #[arg(long, default_value_t = 5., value_parser = parse_interval)]
+    pub stats_interval: f64,
+
+    /// Topic for presence messages
+    #[arg(long, default_value = "bw3/presence")]
+    pub presence_topic: String,
+
+    /// Topic for stats, bw3/<station>/<client id>/stats by default
+    #[arg(long)]
+    pub stats_topic: Option<String>,
+
+    /// Topic for events and errors, bw3/<station>/<client id>/events by default
+    #[arg(long)]
+    pub events_topic: Option<String>,
+
+    /// Topic answered on the pong topic, bw3/<station>/<client id>/ping by default
+    #[arg(long)]
+    pub ping_topic: Option<String>,
+
+    /// Topic echoing pings, bw3/<station>/<client id>/pong by default
+    #[arg(long)]
+    pub pong_topic: Option<String>,
+}
+
+impl Options {
+    pub fn from_args() -> Options {
+        Options::parse()
+    }
+
+    // the reporter asked for with `--mqtt`, connecting in the background
+    pub fn status_reporter(&self) -> Result<Option<StatusReporter>, Error> {
+        let addr = match self.mqtt {
+            Some(ref addr) => addr,
+            None => return Ok(None),
+        };
+
+        let mut topics = StatusTopics::station(self.station, &self.mqtt_client_id);
+        topics.presence = self.presence_topic.clone();
+        let custom = [
+            (&mut topics.stats, &self.stats_topic),
+            (&mut topics.events, &self.events_topic),
+            (&mut topics.ping, &self.ping_topic),
+            (&mut topics.pong, &self.pong_topic),
+        ];
+        for (topic, option) in custom {
+            if let Some(option) = option {
+                *topic = option.clone();
+            }
+        }
+
+        let interval = Duration::try_from_secs_f64(self.stats_interval.max(0.1))?;
+        let options = mqtt_options(&self.mqtt_client_id, addr)?;
+        let reporter = StatusReporter::connect(options, self.station, topics);
+        Ok(Some(reporter.with_interval(interval)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats_interval(value: &str) -> Result<f64, clap::Error> {
+        Options::try_parse_from(["gst-play-pause", "--stats-interval", value])
+            .map(|options| options.stats_interval)
+    }
+
+    #[test]
+    fn stats_interval_is_bounded() {
+        assert_eq!(stats_interval("0.5").unwrap(), 0.5);
+        assert_eq!(stats_interval("86400").unwrap(), 86_400.);
+        for value in &["0", "0.05", "-1", "86401", "1e30", "inf", "NaN", "soon"] {
+            assert!(stats_interval(value).is_err(), "{} accepted", value);
+        }
+    }
+
+    #[test]
+    fn huge_intervals_set_in_code_are_an_error() {
+        let mut options = Options::try_parse_from(["gst-play-pause"]).unwrap();
+        options.mqtt = Some("127.0.0.1:1883".to_string());
+        options.stats_interval = f64::INFINITY;
+        assert!(options.status_reporter().is_err());
+    }
+}
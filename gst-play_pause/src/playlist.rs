@@ -0,0 +1,174 @@
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use anyhow::Error;
+use derive_more::{Display, Error};
+use gst::prelude::*;
+
+#[derive(Debug, Display, Error)]
+#[display(fmt = "Empty playlist")]
+pub struct EmptyPlaylist;
+
+fn is_uri(entry: &str) -> bool {
+    entry.contains("://")
+}
+
+fn to_uri(entry: &str, base: Option<&Path>) -> Result<String, Error> {
+    if is_uri(entry) {
+        return Ok(entry.to_string());
+    }
+
+    // relative entries of an M3U are relative to the playlist itself
+    let path = match base {
+        Some(base) => base.join(entry),
+        None => Path::new(entry).to_path_buf(),
+    };
+    let path =
+        fs::canonicalize(&path).map_err(|err| anyhow::anyhow!("{}: {}", path.display(), err))?;
+
+    Ok(glib::filename_to_uri(&path, None)?.to_string())
+}
+
+fn is_m3u(entry: &str) -> bool {
+    let entry = entry.to_lowercase();
+    !is_uri(&entry) && (entry.ends_with(".m3u") || entry.ends_with(".m3u8"))
+}
+
+// Files and URIs played back to back. `current` is what is playing, `queued`
+// what playbin was handed for a gapless switch but has not started yet.
+#[derive(Debug, Default)]
+pub struct Playlist {
+    entries: Vec<String>,
+    current: usize,
+    queued: Option<usize>,
+}
+
+impl Playlist {
+    // files, URIs and M3U playlists, the latter expanded in place
+    pub fn from_inputs<S: AsRef<str>>(inputs: &[S]) -> Result<Playlist, Error> {
+        let mut entries = Vec::new();
+
+        for input in inputs {
+            let input = input.as_ref();
+            if !is_m3u(input) {
+                entries.push(to_uri(input, None)?);
+                continue;
+            }
+
+            let content = fs::read_to_string(input)?;
+            let base = Path::new(input).parent();
+            for line in content.lines() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+
+                entries.push(to_uri(line, base)?);
+            }
+        }
+
+        if entries.is_empty() {
+            return Err(EmptyPlaylist.into());
+        }
+
+        Ok(Playlist {
+            entries,
+            ..Playlist::default()
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn index(&self) -> usize {
+        self.current
+    }
+
+    pub fn current(&self) -> &str {
+        &self.entries[self.current]
+    }
+
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    // moves to another entry right away, forgetting a queued gapless switch
+    pub fn select(&mut self, index: usize) -> Option<&str> {
+        if index >= self.entries.len() {
+            return None;
+        }
+
+        self.current = index;
+        self.queued = None;
+        Some(&self.entries[index])
+    }
+
+    fn queue_next(&mut self) -> Option<&str> {
+        let next = self.current + 1;
+        if next >= self.entries.len() {
+            return None;
+        }
+
+        self.queued = Some(next);
+        Some(&self.entries[next])
+    }
+
+    // the queued entry started playing
+    fn advance(&mut self) -> bool {
+        match self.queued.take() {
+            Some(next) => {
+                self.current = next;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+pub type SharedPlaylist = Arc<Mutex<Playlist>>;
+
+// A playbin playing `playlist`, with the video going to the autovideosink returned
+// alongside. The sink isn't reachable by name until playbin builds its internal
+// bins, so it is kept here. The next entry is handed over on "about-to-finish" so
+// there is no gap between them.
+pub fn playbin(playlist: SharedPlaylist) -> Result<(gst::Pipeline, gst::Element), Error> {
+    let playbin = gst::ElementFactory::make("playbin", None)
+        .map_err(|_| crate::MissingElement("playbin".to_string()))?;
+    let sink = gst::ElementFactory::make("autovideosink", Some("sink"))
+        .map_err(|_| crate::MissingElement("autovideosink".to_string()))?;
+
+    playbin.set_property("video-sink", &sink)?;
+    playbin.set_property("uri", &playlist.lock().unwrap().current())?;
+
+    playbin.connect("about-to-finish", false, move |args| {
+        let playbin = args[0].get::<gst::Element>().unwrap().unwrap();
+
+        let mut playlist = playlist.lock().unwrap();
+        if let Some(uri) = playlist.queue_next() {
+            playbin.set_property("uri", &uri).unwrap();
+        }
+
+        None
+    })?;
+
+    let pipeline = playbin
+        .dynamic_cast::<gst::Pipeline>()
+        .map_err(|_| crate::MissingElement("playbin".to_string()))?;
+    Ok((pipeline, sink))
+}
+
+// called on "stream-start", returns the entry that is now playing if it changed
+pub(crate) fn stream_started(playlist: &SharedPlaylist) -> Option<(usize, String)> {
+    let mut playlist = playlist.lock().unwrap();
+    if playlist.advance() {
+        Some((playlist.index(), playlist.current().to_string()))
+    } else {
+        None
+    }
+}
@@ -0,0 +1,132 @@
+use remote_control::{RemoteRequest, RpcError};
+use serde_json::Value;
+
+use crate::{Command, SeekMode};
+
+// the longest jump in seconds whose nanoseconds still fit a ClockTime
+const MAX_OFFSET: f64 = (u64::MAX / 1_000_000_000) as f64;
+
+// maps a JSON-RPC call onto a controller command, "status" and "snapshot" are
+// answered separately
+pub(crate) fn command(request: &RemoteRequest) -> Result<Command, RpcError> {
+    let command = match request.method.as_str() {
+        "play" => Command::Play,
+        "pause" => Command::Pause,
+        "toggle" => Command::Toggle,
+        "step" => Command::Step(request.param_u64("frames").unwrap_or(1)),
+        "step_back" => Command::StepBack(request.param_u64("frames").unwrap_or(1)),
+        "seek" => {
+            let position = request.param_f64("position")?;
+            if !position.is_finite() || position < 0. {
+                return Err(RpcError::invalid_params("position must be positive"));
+            }
+            Command::Seek(gst::ClockTime::from_nseconds((position * 1e9) as u64))
+        }
+        "seek_relative" => {
+            let offset = request.param_f64("offset")?;
+            if !offset.is_finite() || offset.abs() > MAX_OFFSET {
+                return Err(RpcError::invalid_params("offset out of range"));
+            }
+            Command::SeekRelative((offset * 1000.) as i64)
+        }
+        "seek_start" => Command::SeekStart,
+        "seek_end" => Command::SeekEnd,
+        "seek_mode" => match request.params.get("mode").and_then(Value::as_str) {
+            Some("accurate") => Command::SetSeekMode(SeekMode::Accurate),
+            Some("keyframe") => Command::SetSeekMode(SeekMode::KeyUnit),
+            _ => {
+                return Err(RpcError::invalid_params(
+                    "mode must be \"accurate\" or \"keyframe\"",
+                ))
+            }
+        },
+        "rate" => Command::SetRate(request.param_f64("rate")?),
+        "faster" => Command::Faster,
+        "slower" => Command::Slower,
+        "reverse" => Command::Reverse,
+        "next" => Command::Next,
+        "previous" => Command::Previous,
+        "loop_start" => Command::SetLoopStart,
+        "loop_end" => Command::SetLoopEnd,
+        "loop_clear" => Command::ClearLoop,
+        "loop" => Command::ToggleLoop,
+        "eos" => Command::Eos,
+        "quit" => Command::Quit,
+        method => return Err(RpcError::method_not_found(method)),
+    };
+
+    Ok(command)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn call(method: &str, params: Value) -> Result<Command, RpcError> {
+        command(&RemoteRequest::notification(method, params))
+    }
+
+    fn error_code(result: Result<Command, RpcError>) -> i64 {
+        result.unwrap_err().code
+    }
+
+    #[test]
+    fn methods_map_to_commands() {
+        assert_eq!(call("toggle", json!({})), Ok(Command::Toggle));
+        assert_eq!(call("step", json!({})), Ok(Command::Step(1)));
+        assert_eq!(
+            call("step_back", json!({"frames": 5})),
+            Ok(Command::StepBack(5))
+        );
+        assert_eq!(
+            call("rate", json!({"rate": 2.0})),
+            Ok(Command::SetRate(2.0))
+        );
+        assert_eq!(
+            call("seek_mode", json!({"mode": "keyframe"})),
+            Ok(Command::SetSeekMode(SeekMode::KeyUnit))
+        );
+    }
+
+    #[test]
+    fn seconds_become_clock_times_and_milliseconds() {
+        assert_eq!(
+            call("seek", json!({"position": 1.5})),
+            Ok(Command::Seek(gst::ClockTime::from_mseconds(1500)))
+        );
+        assert_eq!(
+            call("seek_relative", json!({"offset": -2.5})),
+            Ok(Command::SeekRelative(-2500))
+        );
+    }
+
+    #[test]
+    fn bad_params_are_refused() {
+        assert_eq!(error_code(call("seek", json!({"position": -1}))), -32602);
+        assert_eq!(error_code(call("seek", json!({}))), -32602);
+        assert_eq!(
+            error_code(call("seek_mode", json!({"mode": "fast"}))),
+            -32602
+        );
+        assert_eq!(error_code(call("rate", json!({"rate": "2"}))), -32602);
+    }
+
+    #[test]
+    fn huge_relative_seeks_are_refused() {
+        assert_eq!(
+            error_code(call("seek_relative", json!({"offset": 1e12}))),
+            -32602
+        );
+        assert_eq!(
+            error_code(call("seek_relative", json!({"offset": -1e12}))),
+            -32602
+        );
+        assert!(call("seek_relative", json!({"offset": MAX_OFFSET})).is_ok());
+    }
+
+    #[test]
+    fn unknown_methods_are_not_found() {
+        assert_eq!(error_code(call("rewind", json!({}))), -32601);
+    }
+}
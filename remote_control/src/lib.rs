@@ -0,0 +1,70 @@
+// JSON-RPC 2.0 over a local WebSocket, shared by the players and the overlay
+// examples. Only `seconds`, behind the "gst" feature, needs GStreamer.
+
+mod rpc;
+mod server;
+
+pub use rpc::{RemoteRequest, RpcError};
+pub use server::{RemoteServer, POLL_INTERVAL};
+
+#[cfg(feature = "gst")]
+use gstreamer as gst;
+#[cfg(feature = "gst")]
+use serde_json::{json, Value};
+
+// clock time as fractional seconds, null when unknown
+#[cfg(feature = "gst")]
+pub fn seconds(time: Option<gst::ClockTime>) -> Value {
+    match time.and_then(|t| t.nseconds()) {
+        Some(ns) => json!(ns as f64 / 1e9),
+        None => Value::Null,
+    }
+}
+
+// the address following `--remote` on the command line
+pub fn remote_arg<S: AsRef<str>>(args: &[S]) -> Option<&str> {
+    args.iter()
+        .position(|arg| arg.as_ref() == "--remote")
+        .and_then(|idx| args.get(idx + 1))
+        .map(|addr| addr.as_ref())
+}
+
+// For the binaries: binds `addr` when one was given and says where clients
+// can connect, or why they can't.
+pub fn listen(addr: Option<&str>) -> Option<RemoteServer> {
+    let addr = addr?;
+    match RemoteServer::bind(addr) {
+        Ok(server) => {
+            println!("Remote control on ws://{}", server.local_addr());
+            Some(server)
+        }
+        Err(e) => {
+            eprintln!("Remote control unavailable: {}", e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remote_address_follows_the_flag() {
+        let args = ["play", "--loop", "--remote", "127.0.0.1:9000", "clip.mp4"];
+        assert_eq!(remote_arg(&args), Some("127.0.0.1:9000"));
+
+        assert_eq!(remote_arg(&["play", "--remote"]), None);
+        assert_eq!(remote_arg(&["play", "clip.mp4"]), None);
+        assert_eq!(remote_arg::<String>(&[]), None);
+    }
+
+    #[test]
+    fn listen_needs_an_address() {
+        assert!(listen(None).is_none());
+        assert!(listen(Some("not an address")).is_none());
+
+        let server = listen(Some("127.0.0.1:0")).unwrap();
+        assert_ne!(server.local_addr().port(), 0);
+    }
+}
@@ -0,0 +1,233 @@
+use std::sync::mpsc;
+
+use serde_json::{json, Value};
+
+// JSON-RPC 2.0 error object
+#[derive(Debug, Clone, PartialEq)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    pub fn parse_error() -> Self {
+        RpcError {
+            code: -32700,
+            message: "Parse error".to_string(),
+        }
+    }
+
+    pub fn invalid_request() -> Self {
+        RpcError {
+            code: -32600,
+            message: "Invalid request".to_string(),
+        }
+    }
+
+    pub fn method_not_found(method: &str) -> Self {
+        RpcError {
+            code: -32601,
+            message: format!("Method not found: {}", method),
+        }
+    }
+
+    pub fn invalid_params(message: &str) -> Self {
+        RpcError {
+            code: -32602,
+            message: format!("Invalid params: {}", message),
+        }
+    }
+
+    pub fn internal(message: &str) -> Self {
+        RpcError {
+            code: -32603,
+            message: message.to_string(),
+        }
+    }
+}
+
+pub(crate) fn response(id: &Value, result: Result<Value, RpcError>) -> String {
+    let msg = match result {
+        Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+        Err(err) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": {"code": err.code, "message": err.message},
+        }),
+    };
+
+    msg.to_string()
+}
+
+// A call received from a client. Calls without an id are notifications and
+// `respond` sends nothing back for them.
+pub struct RemoteRequest {
+    pub method: String,
+    pub params: Value,
+    id: Option<Value>,
+    reply: mpsc::Sender<String>,
+}
+
+impl RemoteRequest {
+    // a call without an id, for callers inside the process
+    pub fn notification(method: &str, params: Value) -> Self {
+        let (reply, _) = mpsc::channel();
+        RemoteRequest {
+            method: method.to_string(),
+            params,
+            id: None,
+            reply,
+        }
+    }
+
+    pub fn respond(self, result: Result<Value, RpcError>) {
+        if let Some(ref id) = self.id {
+            let _ = self.reply.send(response(id, result));
+        }
+    }
+
+    pub fn param_f64(&self, name: &str) -> Result<f64, RpcError> {
+        self.params
+            .get(name)
+            .and_then(Value::as_f64)
+            .ok_or_else(|| RpcError::invalid_params(&format!("expected number \"{}\"", name)))
+    }
+
+    pub fn param_u64(&self, name: &str) -> Result<u64, RpcError> {
+        self.params
+            .get(name)
+            .and_then(Value::as_u64)
+            .ok_or_else(|| RpcError::invalid_params(&format!("expected integer \"{}\"", name)))
+    }
+}
+
+// queues a call, or returns the error response for a malformed one
+pub(crate) fn dispatch(
+    text: &str,
+    requests: &mpsc::Sender<RemoteRequest>,
+    reply: &mpsc::Sender<String>,
+) -> Option<String> {
+    let msg: Value = match serde_json::from_str(text) {
+        Ok(msg) => msg,
+        Err(_) => return Some(response(&Value::Null, Err(RpcError::parse_error()))),
+    };
+
+    let id = msg.get("id").cloned();
+    let method = match msg.get("method").and_then(Value::as_str) {
+        Some(method) => method.to_string(),
+        None => {
+            let id = id.unwrap_or(Value::Null);
+            return Some(response(&id, Err(RpcError::invalid_request())));
+        }
+    };
+
+    let _ = requests.send(RemoteRequest {
+        method,
+        params: msg.get("params").cloned().unwrap_or(Value::Null),
+        id,
+        reply: reply.clone(),
+    });
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(text: &str) -> (Option<Value>, Option<RemoteRequest>, mpsc::Receiver<String>) {
+        let (requests, queued) = mpsc::channel();
+        let (reply, replies) = mpsc::channel();
+        let err = dispatch(text, &requests, &reply).map(|err| serde_json::from_str(&err).unwrap());
+        (err, queued.try_recv().ok(), replies)
+    }
+
+    fn error_code(msg: &Value) -> i64 {
+        msg["error"]["code"].as_i64().unwrap()
+    }
+
+    #[test]
+    fn malformed_json_is_a_parse_error() {
+        let (err, request, _) = call("{\"method\": ");
+        let err = err.unwrap();
+
+        assert_eq!(error_code(&err), -32700);
+        assert_eq!(err["id"], Value::Null);
+        assert!(request.is_none());
+    }
+
+    #[test]
+    fn missing_method_is_an_invalid_request() {
+        let (err, request, _) = call(r#"{"jsonrpc": "2.0", "id": 7, "params": {}}"#);
+        let err = err.unwrap();
+
+        assert_eq!(error_code(&err), -32600);
+        assert_eq!(err["id"], 7);
+        assert!(request.is_none());
+
+        let (err, _, _) = call(r#"{"jsonrpc": "2.0", "method": 3}"#);
+        assert_eq!(error_code(&err.unwrap()), -32600);
+    }
+
+    #[test]
+    fn calls_are_queued_and_answered_with_their_id() {
+        let (err, request, replies) =
+            call(r#"{"jsonrpc": "2.0", "id": "a", "method": "seek", "params": {"position": 2.5}}"#);
+        assert!(err.is_none());
+
+        let request = request.unwrap();
+        assert_eq!(request.method, "seek");
+        assert_eq!(request.param_f64("position"), Ok(2.5));
+        request.respond(Ok(json!({"done": true})));
+
+        let reply: Value = serde_json::from_str(&replies.try_recv().unwrap()).unwrap();
+        assert_eq!(reply["jsonrpc"], "2.0");
+        assert_eq!(reply["id"], "a");
+        assert_eq!(reply["result"]["done"], true);
+        assert!(reply.get("error").is_none());
+    }
+
+    #[test]
+    fn errors_carry_code_and_message() {
+        let (_, request, replies) = call(r#"{"jsonrpc": "2.0", "id": 1, "method": "warp"}"#);
+        let request = request.unwrap();
+        assert_eq!(request.params, Value::Null);
+        request.respond(Err(RpcError::method_not_found("warp")));
+
+        let reply: Value = serde_json::from_str(&replies.try_recv().unwrap()).unwrap();
+        assert_eq!(error_code(&reply), -32601);
+        assert_eq!(reply["error"]["message"], "Method not found: warp");
+        assert!(reply.get("result").is_none());
+    }
+
+    #[test]
+    fn notifications_get_no_response() {
+        let (err, request, replies) = call(r#"{"jsonrpc": "2.0", "method": "pause"}"#);
+        assert!(err.is_none());
+
+        request.unwrap().respond(Ok(Value::Null));
+        assert!(replies.try_recv().is_err());
+    }
+
+    #[test]
+    fn params_are_checked() {
+        let (_, request, _) = call(
+            r#"{"jsonrpc": "2.0", "id": 1, "method": "step", "params": {"frames": 3, "rate": "fast"}}"#,
+        );
+        let request = request.unwrap();
+
+        assert_eq!(request.param_u64("frames"), Ok(3));
+        assert_eq!(request.param_f64("frames"), Ok(3.));
+        assert_eq!(request.param_f64("rate").unwrap_err().code, -32602);
+        assert_eq!(request.param_u64("missing").unwrap_err().code, -32602);
+    }
+
+    #[test]
+    fn error_codes() {
+        assert_eq!(RpcError::parse_error().code, -32700);
+        assert_eq!(RpcError::invalid_request().code, -32600);
+        assert_eq!(RpcError::method_not_found("x").code, -32601);
+        assert_eq!(RpcError::invalid_params("x").code, -32602);
+        assert_eq!(RpcError::internal("x").code, -32603);
+    }
+}
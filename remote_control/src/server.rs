@@ -0,0 +1,164 @@
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Error;
+use serde_json::{json, Value};
+use tungstenite::Message;
+
+use crate::rpc::{dispatch, RemoteRequest};
+
+// how long a client thread waits for a message before flushing pushed events
+pub const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+type Clients = Arc<Mutex<Vec<mpsc::Sender<String>>>>;
+
+// Local WebSocket endpoint speaking JSON-RPC 2.0. Every client gets its own thread,
+// calls are queued for whoever owns the server and events are pushed to all clients.
+pub struct RemoteServer {
+    local_addr: SocketAddr,
+    requests: mpsc::Receiver<RemoteRequest>,
+    clients: Clients,
+}
+
+impl RemoteServer {
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> Result<RemoteServer, Error> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        let (sender, requests) = mpsc::channel();
+        let clients = Clients::default();
+
+        let clients_clone = clients.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(err) => {
+                        eprintln!("Remote control accept failed: {}", err);
+                        continue;
+                    }
+                };
+
+                let (events, outgoing) = mpsc::channel();
+                clients_clone.lock().unwrap().push(events.clone());
+
+                let requests = sender.clone();
+                thread::spawn(move || {
+                    let peer = stream.peer_addr().ok();
+                    if let Err(err) = serve_client(stream, requests, events, outgoing) {
+                        eprintln!("Remote client {:?} dropped: {}", peer, err);
+                    }
+                });
+            }
+        });
+
+        Ok(RemoteServer {
+            local_addr,
+            requests,
+            clients,
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    // calls received since the last time, never blocks
+    pub fn requests(&self) -> mpsc::TryIter<'_, RemoteRequest> {
+        self.requests.try_iter()
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<RemoteRequest> {
+        self.requests.recv_timeout(timeout).ok()
+    }
+
+    // pushes a JSON-RPC notification to every connected client
+    pub fn notify(&self, method: &str, params: Value) {
+        let msg = json!({"jsonrpc": "2.0", "method": method, "params": params}).to_string();
+
+        let mut clients = self.clients.lock().unwrap();
+        clients.retain(|client| client.send(msg.clone()).is_ok());
+    }
+
+    pub fn client_count(&self) -> usize {
+        self.clients.lock().unwrap().len()
+    }
+}
+
+fn serve_client(
+    stream: TcpStream,
+    requests: mpsc::Sender<RemoteRequest>,
+    reply: mpsc::Sender<String>,
+    outgoing: mpsc::Receiver<String>,
+) -> Result<(), Error> {
+    let mut ws = tungstenite::accept(stream).map_err(|err| anyhow::anyhow!("{}", err))?;
+    ws.get_ref().set_read_timeout(Some(POLL_INTERVAL))?;
+
+    loop {
+        match ws.read() {
+            Ok(Message::Text(text)) => {
+                if let Some(err) = dispatch(&text, &requests, &reply) {
+                    ws.send(Message::text(err))?;
+                }
+            }
+            Ok(Message::Close(_)) => return Ok(()),
+            Ok(_) => (),
+            Err(tungstenite::Error::Io(ref err))
+                if err.kind() == io::ErrorKind::WouldBlock
+                    || err.kind() == io::ErrorKind::TimedOut => {}
+            Err(tungstenite::Error::ConnectionClosed) => return Ok(()),
+            Err(err) => return Err(err.into()),
+        }
+
+        while let Ok(msg) = outgoing.try_recv() {
+            ws.send(Message::text(msg))?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::RpcError;
+
+    fn read_json<S: io::Read + io::Write>(ws: &mut tungstenite::WebSocket<S>) -> Value {
+        loop {
+            if let Message::Text(text) = ws.read().unwrap() {
+                return serde_json::from_str(&text).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn answers_calls_and_pushes_events_over_websocket() {
+        let server = RemoteServer::bind("127.0.0.1:0").unwrap();
+        let url = format!("ws://{}", server.local_addr());
+        let (mut ws, _) = tungstenite::connect(url).unwrap();
+
+        ws.send(Message::text("not json")).unwrap();
+        assert_eq!(read_json(&mut ws)["error"]["code"], -32700);
+
+        ws.send(Message::text(
+            r#"{"jsonrpc": "2.0", "id": 1, "method": "status"}"#,
+        ))
+        .unwrap();
+        let request = server.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(request.method, "status");
+        request.respond(Err(RpcError::internal("no pipeline")));
+
+        let reply = read_json(&mut ws);
+        assert_eq!(reply["id"], 1);
+        assert_eq!(reply["error"]["code"], -32603);
+        assert_eq!(reply["error"]["message"], "no pipeline");
+
+        assert_eq!(server.client_count(), 1);
+        server.notify("position", json!({"position": 1.5}));
+        let event = read_json(&mut ws);
+        assert_eq!(event["method"], "position");
+        assert_eq!(event["params"]["position"], 1.5);
+        assert!(event.get("id").is_none());
+    }
+}
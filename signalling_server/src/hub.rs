@@ -0,0 +1,227 @@
+use std::collections::{BTreeSet, HashMap};
+use std::net::SocketAddr;
+use std::sync::mpsc;
+
+use tracing::{debug, info};
+use tungstenite::Message;
+
+use crate::{Request, SignallingError};
+
+// replies of the server go out as bytes, like websocket_srv.py, so its clients
+// can keep decoding them
+pub(crate) fn reply(text: &str) -> Message {
+    Message::binary(text.as_bytes().to_vec())
+}
+
+pub(crate) fn error(err: &SignallingError) -> Message {
+    reply(&format!("ERROR {}", err))
+}
+
+struct Peer {
+    sender: mpsc::Sender<Message>,
+    addr: SocketAddr,
+    session: Option<String>,
+    room: Option<String>,
+}
+
+// Who is connected and who talks to whom. Every peer has a channel drained by
+// the thread serving its connection.
+#[derive(Default)]
+pub(crate) struct Hub {
+    peers: HashMap<String, Peer>,
+    rooms: HashMap<String, BTreeSet<String>>,
+}
+
+impl Hub {
+    pub(crate) fn register(
+        &mut self,
+        id: &str,
+        addr: SocketAddr,
+        sender: mpsc::Sender<Message>,
+    ) -> Result<(), SignallingError> {
+        if self.peers.contains_key(id) {
+            return Err(SignallingError::PeerIdTaken(id.to_string()));
+        }
+
+        self.peers.insert(
+            id.to_string(),
+            Peer {
+                sender,
+                addr,
+                session: None,
+                room: None,
+            },
+        );
+        info!(peer = id, %addr, peers = self.peers.len(), "peer registered");
+        Ok(())
+    }
+
+    // ends the session and leaves the room of a peer that went away
+    pub(crate) fn unregister(&mut self, id: &str) {
+        let peer = match self.peers.remove(id) {
+            Some(peer) => peer,
+            None => return,
+        };
+        info!(peer = id, addr = %peer.addr, peers = self.peers.len(), "peer left");
+
+        if let Some(other) = peer.session {
+            if let Some(other) = self.peers.get_mut(&other) {
+                other.session = None;
+                let _ = other
+                    .sender
+                    .send(error(&SignallingError::PeerLeft(id.to_string())));
+            }
+            info!(peer = id, other = %other, "session ended");
+        }
+
+        if let Some(room) = peer.room {
+            self.leave_room(id, &room);
+        }
+    }
+
+    pub(crate) fn peer_count(&self) -> usize {
+        self.peers.len()
+    }
+
+    fn send(&self, to: &str, msg: Message) {
+        if let Some(peer) = self.peers.get(to) {
+            let _ = peer.sender.send(msg);
+        }
+    }
+
+    // what a registered peer sent, the reply if any goes back to it
+    pub(crate) fn handle(&mut self, from: &str, msg: Message) {
+        let text = match msg {
+            Message::Text(ref text) => text.to_string(),
+            Message::Binary(ref bytes) => String::from_utf8_lossy(bytes).into_owned(),
+            _ => return,
+        };
+
+        let result = Request::parse(&text).and_then(|request| match request {
+            Request::Session(to) => self.start_session(from, &to),
+            Request::Room(room) => self.join_room(from, &room),
+            Request::RoomPeerMsg { to, msg } => self.room_peer_msg(from, &to, &msg),
+            Request::RoomPeerList => self.room_peer_list(from),
+            Request::Other => {
+                self.route(from, msg);
+                Ok(())
+            }
+        });
+
+        if let Err(err) = result {
+            debug!(peer = from, error = %err, "request refused");
+            self.send(from, error(&err));
+        }
+    }
+
+    fn start_session(&mut self, from: &str, to: &str) -> Result<(), SignallingError> {
+        let peer = &self.peers[from];
+        if peer.session.is_some() {
+            return Err(SignallingError::InSession);
+        }
+        if let Some(ref room) = peer.room {
+            return Err(SignallingError::InRoom(room.clone()));
+        }
+
+        match self.peers.get(to) {
+            None => return Err(SignallingError::PeerNotFound(to.to_string())),
+            Some(_) if to == from => return Err(SignallingError::PeerNotFound(to.to_string())),
+            Some(other) if other.session.is_some() || other.room.is_some() => {
+                return Err(SignallingError::PeerBusy(to.to_string()))
+            }
+            Some(_) => (),
+        }
+
+        self.peers.get_mut(from).unwrap().session = Some(to.to_string());
+        self.peers.get_mut(to).unwrap().session = Some(from.to_string());
+        self.send(from, reply("SESSION_OK"));
+        info!(peer = from, other = to, "session started");
+        Ok(())
+    }
+
+    // to the session peer, or only logged like websocket_srv.py does
+    fn route(&self, from: &str, msg: Message) {
+        match self.peers[from].session {
+            Some(ref to) => {
+                debug!(peer = from, to = %to, len = msg.len(), "message routed");
+                self.send(to, msg);
+            }
+            None => info!(peer = from, message = %msg, "message received outside a session"),
+        }
+    }
+
+    fn join_room(&mut self, from: &str, room: &str) -> Result<(), SignallingError> {
+        let peer = &self.peers[from];
+        if peer.session.is_some() {
+            return Err(SignallingError::InSession);
+        }
+        if let Some(ref room) = peer.room {
+            return Err(SignallingError::InRoom(room.clone()));
+        }
+
+        let members = self.rooms.entry(room.to_string()).or_default();
+        let others: Vec<String> = members.iter().cloned().collect();
+        members.insert(from.to_string());
+        self.peers.get_mut(from).unwrap().room = Some(room.to_string());
+
+        self.send(from, reply(&with_peers("ROOM_OK", &others)));
+        for other in &others {
+            self.send(other, reply(&format!("ROOM_PEER_JOINED {}", from)));
+        }
+        info!(peer = from, room, members = others.len() + 1, "room joined");
+        Ok(())
+    }
+
+    fn leave_room(&mut self, id: &str, room: &str) {
+        let members = match self.rooms.get_mut(room) {
+            Some(members) => members,
+            None => return,
+        };
+        members.remove(id);
+        let others: Vec<String> = members.iter().cloned().collect();
+        if others.is_empty() {
+            self.rooms.remove(room);
+        }
+
+        for other in &others {
+            self.send(other, reply(&format!("ROOM_PEER_LEFT {}", id)));
+        }
+        info!(peer = id, room, members = others.len(), "room left");
+    }
+
+    fn room_peer_msg(&self, from: &str, to: &str, msg: &str) -> Result<(), SignallingError> {
+        let room = self.peers[from]
+            .room
+            .as_ref()
+            .ok_or(SignallingError::NoRoom)?;
+        if to == from || !self.rooms[room].contains(to) {
+            return Err(SignallingError::PeerNotFound(to.to_string()));
+        }
+
+        debug!(peer = from, to, room = %room, len = msg.len(), "room message routed");
+        self.send(to, reply(&format!("ROOM_PEER_MSG {} {}", from, msg)));
+        Ok(())
+    }
+
+    fn room_peer_list(&self, from: &str) -> Result<(), SignallingError> {
+        let room = self.peers[from]
+            .room
+            .as_ref()
+            .ok_or(SignallingError::NoRoom)?;
+        let others: Vec<String> = self.rooms[room]
+            .iter()
+            .filter(|id| *id != from)
+            .cloned()
+            .collect();
+
+        self.send(from, reply(&with_peers("ROOM_PEER_LIST", &others)));
+        Ok(())
+    }
+}
+
+// e.g. "ROOM_OK a b", just "ROOM_OK" when alone
+fn with_peers(command: &str, peers: &[String]) -> String {
+    let mut words = vec![command.to_string()];
+    words.extend_from_slice(peers);
+    words.join(" ")
+}
@@ -0,0 +1,6 @@
+mod hub;
+mod protocol;
+mod server;
+
+pub use protocol::{parse_hello, Request, SignallingError};
+pub use server::{Config, Server};
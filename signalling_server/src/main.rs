@@ -0,0 +1,51 @@
+use std::time::Duration;
+
+use clap::Parser;
+use signalling_server::{Config, Server};
+use tracing_subscriber::EnvFilter;
+
+#[derive(Debug, Parser)]
+#[command(about = "WebRTC signalling server for peers saying \"Hello <peer_id>\"")]
+struct Options {
+    /// Address to listen on, all interfaces so browsers on the LAN can connect
+    #[arg(short, long, default_value = "0.0.0.0:8765")]
+    bind: String,
+
+    /// Seconds between heartbeats sent to every peer
+    #[arg(long, default_value_t = 3.)]
+    heartbeat: f64,
+
+    /// Seconds of silence after which a peer is dropped
+    #[arg(long, default_value_t = 10.)]
+    timeout: f64,
+
+    /// Log JSON lines instead of text
+    #[arg(long)]
+    json_logs: bool,
+}
+
+fn main() {
+    let options = Options::parse();
+
+    // RUST_LOG=debug also shows every routed message
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    if options.json_logs {
+        tracing_subscriber::fmt()
+            .json()
+            .with_env_filter(filter)
+            .init();
+    } else {
+        tracing_subscriber::fmt().with_env_filter(filter).init();
+    }
+
+    let config = Config {
+        heartbeat: Duration::from_secs_f64(options.heartbeat.max(0.1)),
+        timeout: Duration::from_secs_f64(options.timeout.max(0.1)),
+    };
+
+    let result = Server::bind(options.bind.as_str(), config).and_then(|server| server.run());
+    if let Err(e) = result {
+        eprintln!("Error! {}", e);
+        std::process::exit(1);
+    }
+}
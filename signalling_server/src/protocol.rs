@@ -0,0 +1,84 @@
+use derive_more::{Display, Error};
+
+// Sent back to peers as "ERROR <reason>", the wording follows websocket_srv.py
+#[derive(Debug, Clone, PartialEq, Eq, Display, Error)]
+pub enum SignallingError {
+    #[display(fmt = "invalid hello {:?}, expected \"Hello <peer_id>\"", _0)]
+    InvalidHello(#[error(not(source))] String),
+    #[display(fmt = "peer {:?} already registered", _0)]
+    PeerIdTaken(#[error(not(source))] String),
+    #[display(fmt = "peer '{}' not found", _0)]
+    PeerNotFound(#[error(not(source))] String),
+    #[display(fmt = "peer '{}' busy", _0)]
+    PeerBusy(#[error(not(source))] String),
+    #[display(fmt = "peer '{}' left", _0)]
+    PeerLeft(#[error(not(source))] String),
+    #[display(fmt = "already in a session")]
+    InSession,
+    #[display(fmt = "already in room '{}'", _0)]
+    InRoom(#[error(not(source))] String),
+    #[display(fmt = "not in a room")]
+    NoRoom,
+    #[display(fmt = "invalid room id {:?}", _0)]
+    InvalidRoom(#[error(not(source))] String),
+    #[display(fmt = "invalid message {:?}", _0)]
+    InvalidMessage(#[error(not(source))] String),
+}
+
+// ids end up in space separated commands
+fn valid_id(id: &str) -> bool {
+    !id.is_empty() && !id.contains(char::is_whitespace)
+}
+
+// the peer id of "Hello <peer_id>", the first message of every peer
+pub fn parse_hello(msg: &str) -> Result<String, SignallingError> {
+    let mut words = msg.split_whitespace();
+    match (words.next(), words.next(), words.next()) {
+        (Some("Hello"), Some(id), None) if valid_id(id) => Ok(id.to_string()),
+        _ => Err(SignallingError::InvalidHello(msg.to_string())),
+    }
+}
+
+// what a registered peer can send
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    // "SESSION <peer_id>", what follows goes to that peer and back
+    Session(String),
+    // "ROOM <room_id>", answered with "ROOM_OK <peer_id>..." listing the others
+    Room(String),
+    // "ROOM_PEER_MSG <peer_id> <message>", to another peer of the room
+    RoomPeerMsg { to: String, msg: String },
+    // "ROOM_PEER_LIST"
+    RoomPeerList,
+    // anything else, for the session peer
+    Other,
+}
+
+impl Request {
+    pub fn parse(msg: &str) -> Result<Request, SignallingError> {
+        let invalid = || SignallingError::InvalidMessage(msg.to_string());
+        let (command, rest) = match msg.find(' ') {
+            Some(idx) => (&msg[..idx], msg[idx + 1..].trim()),
+            None => (msg.trim(), ""),
+        };
+
+        match command {
+            "SESSION" if valid_id(rest) => Ok(Request::Session(rest.to_string())),
+            "SESSION" => Err(invalid()),
+            "ROOM" if valid_id(rest) => Ok(Request::Room(rest.to_string())),
+            "ROOM" => Err(SignallingError::InvalidRoom(rest.to_string())),
+            "ROOM_PEER_MSG" => {
+                let (to, msg) = match rest.find(' ') {
+                    Some(idx) => (&rest[..idx], &rest[idx + 1..]),
+                    None => return Err(invalid()),
+                };
+                Ok(Request::RoomPeerMsg {
+                    to: to.to_string(),
+                    msg: msg.to_string(),
+                })
+            }
+            "ROOM_PEER_LIST" => Ok(Request::RoomPeerList),
+            _ => Ok(Request::Other),
+        }
+    }
+}
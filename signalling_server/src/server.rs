@@ -0,0 +1,192 @@
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::Error;
+use tracing::{debug, info, warn};
+use tungstenite::{Message, WebSocket};
+
+use crate::hub::{self, Hub};
+use crate::parse_hello;
+
+// how long a connection thread waits for a message before sending what is queued
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Config {
+    // between the "Hello" and ping frames every peer is sent
+    pub heartbeat: Duration,
+    // peers not heard from for this long, pongs included, are dropped
+    pub timeout: Duration,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            heartbeat: Duration::from_secs(3),
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+// Signalling server for peers introducing themselves with "Hello <peer_id>", as
+// websocket_srv.py does, with sessions between two peers and rooms on top. Every
+// connection is served by its own thread.
+pub struct Server {
+    listener: TcpListener,
+    config: Config,
+    hub: Arc<Mutex<Hub>>,
+}
+
+impl Server {
+    pub fn bind<A: ToSocketAddrs>(addr: A, config: Config) -> Result<Server, Error> {
+        Ok(Server {
+            listener: TcpListener::bind(addr)?,
+            config,
+            hub: Arc::default(),
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    pub fn peer_count(&self) -> usize {
+        self.hub.lock().unwrap().peer_count()
+    }
+
+    // accepts connections until the listener fails
+    pub fn run(&self) -> Result<(), Error> {
+        info!(addr = %self.local_addr()?, "listening");
+
+        for stream in self.listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    warn!(error = %err, "accept failed");
+                    continue;
+                }
+            };
+
+            let hub = self.hub.clone();
+            let config = self.config;
+            thread::spawn(move || {
+                let addr = match stream.peer_addr() {
+                    Ok(addr) => addr,
+                    Err(_) => return,
+                };
+                if let Err(err) = serve_peer(stream, addr, &hub, config) {
+                    debug!(%addr, error = %err, "connection dropped");
+                }
+            });
+        }
+
+        Ok(())
+    }
+}
+
+fn is_timeout(err: &tungstenite::Error) -> bool {
+    match err {
+        tungstenite::Error::Io(err) => {
+            err.kind() == io::ErrorKind::WouldBlock || err.kind() == io::ErrorKind::TimedOut
+        }
+        _ => false,
+    }
+}
+
+// the "Hello <peer_id>" every connection starts with
+fn hello(ws: &mut WebSocket<TcpStream>, config: Config) -> Result<Option<String>, Error> {
+    let deadline = Instant::now() + config.timeout;
+    while Instant::now() < deadline {
+        let msg = match ws.read() {
+            Ok(Message::Text(text)) => text.to_string(),
+            Ok(Message::Binary(bytes)) => String::from_utf8_lossy(&bytes).into_owned(),
+            Ok(Message::Close(_)) => return Ok(None),
+            Ok(_) => continue,
+            Err(ref err) if is_timeout(err) => continue,
+            Err(err) => return Err(err.into()),
+        };
+
+        return match parse_hello(&msg) {
+            Ok(id) => Ok(Some(id)),
+            Err(err) => {
+                ws.send(hub::error(&err))?;
+                Err(err.into())
+            }
+        };
+    }
+
+    Ok(None)
+}
+
+fn serve_peer(
+    stream: TcpStream,
+    addr: SocketAddr,
+    hub: &Mutex<Hub>,
+    config: Config,
+) -> Result<(), Error> {
+    let mut ws = tungstenite::accept(stream).map_err(|err| anyhow::anyhow!("{}", err))?;
+    ws.get_ref().set_read_timeout(Some(POLL_INTERVAL))?;
+    debug!(%addr, "connected");
+
+    let id = match hello(&mut ws, config)? {
+        Some(id) => id,
+        None => return Ok(()),
+    };
+
+    let (sender, outgoing) = mpsc::channel();
+    if let Err(err) = hub.lock().unwrap().register(&id, addr, sender) {
+        warn!(peer = %id, %addr, error = %err, "registration refused");
+        ws.send(hub::error(&err))?;
+        let _ = ws.close(None);
+        return Err(err.into());
+    }
+
+    let result = serve_registered(&mut ws, &id, &outgoing, hub, config);
+    hub.lock().unwrap().unregister(&id);
+    let _ = ws.close(None);
+
+    result
+}
+
+fn serve_registered(
+    ws: &mut WebSocket<TcpStream>,
+    id: &str,
+    outgoing: &mpsc::Receiver<Message>,
+    hub: &Mutex<Hub>,
+    config: Config,
+) -> Result<(), Error> {
+    ws.send(hub::reply("Hello"))?;
+    let mut last_seen = Instant::now();
+    let mut last_heartbeat = Instant::now();
+
+    loop {
+        match ws.read() {
+            Ok(Message::Close(_)) => return Ok(()),
+            Ok(msg) => {
+                last_seen = Instant::now();
+                hub.lock().unwrap().handle(id, msg);
+            }
+            Err(ref err) if is_timeout(err) => (),
+            Err(tungstenite::Error::ConnectionClosed) => return Ok(()),
+            Err(err) => return Err(err.into()),
+        }
+
+        while let Ok(msg) = outgoing.try_recv() {
+            ws.send(msg)?;
+        }
+
+        if last_heartbeat.elapsed() >= config.heartbeat {
+            last_heartbeat = Instant::now();
+            ws.send(hub::reply("Hello"))?;
+            ws.send(Message::Ping(Vec::new().into()))?;
+        }
+
+        if last_seen.elapsed() >= config.timeout {
+            warn!(peer = id, silent_for = ?last_seen.elapsed(), "peer timed out");
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,235 @@
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use signalling_server::{Config, Server};
+use tungstenite::stream::MaybeTlsStream;
+use tungstenite::{Message, WebSocket};
+
+type Client = WebSocket<MaybeTlsStream<TcpStream>>;
+
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn start(config: Config) -> (SocketAddr, Arc<Server>) {
+    let server = Arc::new(Server::bind("127.0.0.1:0", config).unwrap());
+    let addr = server.local_addr().unwrap();
+
+    let server_clone = server.clone();
+    thread::spawn(move || server_clone.run());
+
+    (addr, server)
+}
+
+fn start_default() -> SocketAddr {
+    start(Config::default()).0
+}
+
+fn connect(addr: SocketAddr) -> Client {
+    let (ws, _) = tungstenite::connect(format!("ws://{}", addr)).unwrap();
+    if let MaybeTlsStream::Plain(ref stream) = ws.get_ref() {
+        stream.set_read_timeout(Some(READ_TIMEOUT)).unwrap();
+    }
+    ws
+}
+
+// the next message that is not a heartbeat
+fn recv(ws: &mut Client) -> String {
+    loop {
+        let msg = match ws.read().unwrap() {
+            Message::Text(text) => text.to_string(),
+            Message::Binary(bytes) => String::from_utf8(bytes.to_vec()).unwrap(),
+            _ => continue,
+        };
+        if msg != "Hello" {
+            return msg;
+        }
+    }
+}
+
+// the next message, heartbeats included
+fn recv_any(ws: &mut Client) -> String {
+    match ws.read().unwrap() {
+        Message::Text(text) => text.to_string(),
+        Message::Binary(bytes) => String::from_utf8(bytes.to_vec()).unwrap(),
+        msg => panic!("unexpected message {:?}", msg),
+    }
+}
+
+fn send(ws: &mut Client, msg: &str) {
+    ws.send(Message::text(msg)).unwrap();
+}
+
+fn register(addr: SocketAddr, id: &str) -> Client {
+    let mut ws = connect(addr);
+    send(&mut ws, &format!("Hello {}", id));
+    match ws.read().unwrap() {
+        Message::Binary(bytes) => assert_eq!(&bytes[..], b"Hello"),
+        msg => panic!("unexpected reply {:?}", msg),
+    }
+    ws
+}
+
+fn wait_for_peers(server: &Server, count: usize) {
+    let deadline = Instant::now() + READ_TIMEOUT;
+    while server.peer_count() != count {
+        assert!(Instant::now() < deadline, "{} peers", server.peer_count());
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn hello_registers_peer() {
+    let (addr, server) = start(Config::default());
+    let _a = register(addr, "a");
+    wait_for_peers(&server, 1);
+}
+
+#[test]
+fn hello_as_bytes_like_the_python_client() {
+    let addr = start_default();
+    let mut ws = connect(addr);
+    ws.send(Message::binary(b"Hello 1234".to_vec())).unwrap();
+    assert_eq!(recv_any(&mut ws), "Hello");
+}
+
+#[test]
+fn invalid_hello_is_refused() {
+    let addr = start_default();
+    let mut ws = connect(addr);
+    send(&mut ws, "Hi there");
+    assert!(recv_any(&mut ws).starts_with("ERROR invalid hello"));
+}
+
+#[test]
+fn taken_peer_id_is_refused() {
+    let addr = start_default();
+    let _a = register(addr, "a");
+
+    let mut ws = connect(addr);
+    send(&mut ws, "Hello a");
+    assert_eq!(recv_any(&mut ws), "ERROR peer \"a\" already registered");
+}
+
+#[test]
+fn session_routes_messages_both_ways() {
+    let addr = start_default();
+    let mut a = register(addr, "a");
+    let mut b = register(addr, "b");
+
+    send(&mut a, "SESSION b");
+    assert_eq!(recv(&mut a), "SESSION_OK");
+
+    let offer = r#"{"sdp": {"type": "offer", "sdp": "v=0"}}"#;
+    send(&mut a, offer);
+    assert_eq!(recv(&mut b), offer);
+
+    let answer = r#"{"sdp": {"type": "answer", "sdp": "v=0"}}"#;
+    send(&mut b, answer);
+    assert_eq!(recv(&mut a), answer);
+}
+
+#[test]
+fn session_with_unknown_or_busy_peer_fails() {
+    let addr = start_default();
+    let mut a = register(addr, "a");
+    let mut b = register(addr, "b");
+    let mut c = register(addr, "c");
+
+    send(&mut a, "SESSION nobody");
+    assert_eq!(recv(&mut a), "ERROR peer 'nobody' not found");
+
+    send(&mut a, "SESSION b");
+    assert_eq!(recv(&mut a), "SESSION_OK");
+    send(&mut c, "SESSION b");
+    assert_eq!(recv(&mut c), "ERROR peer 'b' busy");
+    send(&mut b, "SESSION c");
+    assert_eq!(recv(&mut b), "ERROR already in a session");
+}
+
+#[test]
+fn leaving_ends_the_session() {
+    let (addr, server) = start(Config::default());
+    let mut a = register(addr, "a");
+    let mut b = register(addr, "b");
+
+    send(&mut a, "SESSION b");
+    assert_eq!(recv(&mut a), "SESSION_OK");
+
+    a.close(None).unwrap();
+    assert_eq!(recv(&mut b), "ERROR peer 'a' left");
+    wait_for_peers(&server, 1);
+
+    // the id is free again
+    let _a = register(addr, "a");
+}
+
+#[test]
+fn rooms_list_announce_and_route() {
+    let addr = start_default();
+    let mut a = register(addr, "a");
+    let mut b = register(addr, "b");
+    let mut c = register(addr, "c");
+
+    send(&mut a, "ROOM r");
+    assert_eq!(recv(&mut a), "ROOM_OK");
+    send(&mut b, "ROOM r");
+    assert_eq!(recv(&mut b), "ROOM_OK a");
+    assert_eq!(recv(&mut a), "ROOM_PEER_JOINED b");
+
+    send(&mut b, "ROOM_PEER_MSG a {\"ice\": {}}");
+    assert_eq!(recv(&mut a), "ROOM_PEER_MSG b {\"ice\": {}}");
+
+    // peers of other rooms are out of reach
+    send(&mut c, "ROOM s");
+    assert_eq!(recv(&mut c), "ROOM_OK");
+    send(&mut c, "ROOM_PEER_MSG a hi");
+    assert_eq!(recv(&mut c), "ERROR peer 'a' not found");
+
+    send(&mut a, "ROOM_PEER_LIST");
+    assert_eq!(recv(&mut a), "ROOM_PEER_LIST b");
+
+    b.close(None).unwrap();
+    assert_eq!(recv(&mut a), "ROOM_PEER_LEFT b");
+}
+
+#[test]
+fn heartbeats_are_sent() {
+    let addr = start(Config {
+        heartbeat: Duration::from_millis(100),
+        timeout: Duration::from_secs(10),
+    })
+    .0;
+    let mut a = register(addr, "a");
+
+    let mut hellos = 0;
+    let mut pings = 0;
+    while hellos < 2 || pings < 2 {
+        match a.read().unwrap() {
+            Message::Binary(bytes) if &bytes[..] == b"Hello" => hellos += 1,
+            Message::Ping(_) => pings += 1,
+            msg => panic!("unexpected message {:?}", msg),
+        }
+    }
+}
+
+#[test]
+fn silent_peers_time_out() {
+    let (addr, server) = start(Config {
+        heartbeat: Duration::from_millis(50),
+        timeout: Duration::from_millis(300),
+    });
+
+    // never reads, so never answers the pings
+    let _silent = register(addr, "silent");
+    wait_for_peers(&server, 1);
+    wait_for_peers(&server, 0);
+
+    // answering pings keeps a peer around
+    let mut alive = register(addr, "alive");
+    let until = Instant::now() + Duration::from_millis(900);
+    while Instant::now() < until {
+        let _ = alive.read();
+    }
+    assert_eq!(server.peer_count(), 1);
+}
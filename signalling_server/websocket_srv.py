@@ -1,16 +1,21 @@
 # import socket
 import asyncio
+import sys
 import websockets
 from websockets.exceptions import ConnectionClosedError
 
 connected = set()
 peers = dict()
+# peer_id -> peer_id of the other end, both ways
+sessions = dict()
 
 
 async def hello_peer(websocket):
     raddr = websocket.remote_address
     hello = await websocket.recv()
     hello, peer_id = hello.split(maxsplit=1)
+    if isinstance(peer_id, bytes):
+        peer_id = peer_id.decode('utf-8')
     await websocket.send(b'Hello')
     return peer_id
 
@@ -30,14 +35,39 @@ async def sender(websocket, message):
         print("Client has disconnected. Unable to send message")
 
 
-async def listener(websocket):
+async def start_session(websocket, peer_id, callee_id):
+    if callee_id not in peers or callee_id == peer_id:
+        await sender(websocket, f"ERROR peer {callee_id!r} not found")
+        return
+
+    sessions[peer_id] = callee_id
+    sessions[callee_id] = peer_id
+    await sender(websocket, b'SESSION_OK')
+    print(f"Session between {peer_id} and {callee_id}")
+
+
+async def listener(websocket, peer_id):
     raddr = websocket.remote_address
     try:
         async for message in websocket:
-            print(f"Message received : {message.decode('utf-8')}")
+            text = message.decode('utf-8') if isinstance(message, bytes) else message
+            # "SESSION <peer_id>" pairs us with a peer, what follows goes to it
+            if text.startswith('SESSION '):
+                await start_session(websocket, peer_id, text.split(maxsplit=1)[1])
+            elif peer_id in sessions and sessions[peer_id] in peers:
+                await sender(peers[sessions[peer_id]][0], message)
+            else:
+                print(f"Message received : {text}")
     except ConnectionClosedError:
         print(
             f"Client has disconnected. Unable to listen to message from {raddr}\n")
+    finally:
+        peers.pop(peer_id, None)
+        other = sessions.pop(peer_id, None)
+        if other is not None:
+            sessions.pop(other, None)
+            if other in peers:
+                await sender(peers[other][0], f"ERROR peer {peer_id!r} left")
 
 
 async def hello_repeat(websocket):
@@ -57,12 +87,16 @@ async def server(websocket, _):
         print(f"Connection to peer {raddr} closed")
 
     loop = asyncio.get_event_loop()
-    loop.create_task(listener(websocket))
+    loop.create_task(listener(websocket, peer_id))
     loop.create_task(hello_repeat(websocket))
 
     await websocket.wait_closed()
 
-start_server = websockets.serve(server, "localhost", 8765)
+# host:port to listen on, only this machine by default. Pass
+# `python3 websocket_srv.py 0.0.0.0:8765` to accept peers from the network.
+addr = sys.argv[1] if len(sys.argv) > 1 else "localhost:8765"
+host, _, port = addr.rpartition(":")
+start_server = websockets.serve(server, host or "localhost", int(port))
 
 try:
     asyncio.get_event_loop().run_until_complete(start_server)